@@ -0,0 +1,24 @@
+CREATE TABLE newsletter_issues(
+    newsletter_issue_id uuid NOT NULL,
+    title TEXT NOT NULL,
+    text_content TEXT NOT NULL,
+    html_content TEXT NOT NULL,
+    status TEXT NOT NULL,
+    revision INT NOT NULL,
+    previewed_revision INT NULL,
+    created_at timestamptz NOT NULL,
+    updated_at timestamptz NOT NULL,
+    published_at timestamptz NULL,
+    PRIMARY KEY (newsletter_issue_id)
+);
+
+CREATE TABLE newsletter_issue_revisions(
+    newsletter_issue_id uuid NOT NULL REFERENCES newsletter_issues (newsletter_issue_id),
+    revision INT NOT NULL,
+    title TEXT NOT NULL,
+    text_content TEXT NOT NULL,
+    html_content TEXT NOT NULL,
+    created_by uuid NOT NULL REFERENCES users (user_id),
+    created_at timestamptz NOT NULL,
+    PRIMARY KEY (newsletter_issue_id, revision)
+);
@@ -0,0 +1,15 @@
+CREATE TABLE newsletter_deliveries(
+    newsletter_issue_id uuid NOT NULL REFERENCES newsletter_issues (newsletter_issue_id),
+    subscriber_id uuid NOT NULL REFERENCES subscriptions (id),
+    subscriber_email TEXT NOT NULL,
+    state TEXT NOT NULL,
+    attempts INT NOT NULL DEFAULT 0,
+    last_error TEXT NULL,
+    message_id TEXT NULL,
+    execute_after timestamptz NOT NULL DEFAULT now(),
+    created_at timestamptz NOT NULL DEFAULT now(),
+    updated_at timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY (newsletter_issue_id, subscriber_id)
+);
+
+CREATE INDEX newsletter_deliveries_queued_idx ON newsletter_deliveries (execute_after) WHERE state = 'queued';
@@ -0,0 +1,13 @@
+CREATE TABLE newsletter_events(
+    event_id uuid NOT NULL,
+    newsletter_issue_id uuid NOT NULL REFERENCES newsletter_issues (newsletter_issue_id),
+    subscriber_id uuid NOT NULL REFERENCES subscriptions (id),
+    kind TEXT NOT NULL,
+    url TEXT NULL,
+    occurred_at timestamptz NOT NULL,
+    PRIMARY KEY (event_id)
+);
+
+CREATE INDEX newsletter_events_issue_idx ON newsletter_events (newsletter_issue_id, subscriber_id);
+
+ALTER TABLE subscriptions ADD COLUMN tracking_opt_out BOOLEAN NOT NULL DEFAULT false;
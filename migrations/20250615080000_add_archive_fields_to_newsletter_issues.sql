@@ -0,0 +1,2 @@
+ALTER TABLE newsletter_issues ADD COLUMN public BOOLEAN NOT NULL DEFAULT false;
+ALTER TABLE newsletter_issues ADD COLUMN slug TEXT NULL UNIQUE;
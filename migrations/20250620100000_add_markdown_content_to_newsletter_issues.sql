@@ -0,0 +1,2 @@
+ALTER TABLE newsletter_issues ADD COLUMN markdown_content TEXT NULL;
+ALTER TABLE newsletter_issue_revisions ADD COLUMN markdown_content TEXT NULL;
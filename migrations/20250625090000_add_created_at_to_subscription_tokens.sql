@@ -0,0 +1,2 @@
+-- Tokens are only valid for a while: record when they were issued so that stale ones can be purged.
+ALTER TABLE subscription_tokens ADD COLUMN created_at timestamptz NOT NULL DEFAULT now();
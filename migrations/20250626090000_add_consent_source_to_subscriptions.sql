@@ -0,0 +1,2 @@
+-- Where the consent of subscribers who did not confirm through us comes from, e.g. an imported list.
+ALTER TABLE subscriptions ADD COLUMN consent_source TEXT NULL;
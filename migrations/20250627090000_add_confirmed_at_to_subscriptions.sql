@@ -0,0 +1,2 @@
+-- When the subscriber confirmed. Unknown, hence NULL, for those confirmed before it was recorded.
+ALTER TABLE subscriptions ADD COLUMN confirmed_at timestamptz NULL;
@@ -0,0 +1,22 @@
+-- Deleting a subscriber deletes everything that refers to them.
+ALTER TABLE subscription_tokens
+    DROP CONSTRAINT subscription_tokens_subscriber_id_fkey,
+    ADD CONSTRAINT subscription_tokens_subscriber_id_fkey
+        FOREIGN KEY (subscriber_id) REFERENCES subscriptions (id) ON DELETE CASCADE;
+ALTER TABLE newsletter_deliveries
+    DROP CONSTRAINT newsletter_deliveries_subscriber_id_fkey,
+    ADD CONSTRAINT newsletter_deliveries_subscriber_id_fkey
+        FOREIGN KEY (subscriber_id) REFERENCES subscriptions (id) ON DELETE CASCADE;
+ALTER TABLE newsletter_events
+    DROP CONSTRAINT newsletter_events_subscriber_id_fkey,
+    ADD CONSTRAINT newsletter_events_subscriber_id_fkey
+        FOREIGN KEY (subscriber_id) REFERENCES subscriptions (id) ON DELETE CASCADE;
+
+-- Addresses that must never be mailed again. Only a keyed hash of the address is kept,
+-- so that erased subscribers can be recognised without storing who they were.
+CREATE TABLE suppressions(
+    email_hash TEXT NOT NULL,
+    reason TEXT NOT NULL,
+    created_at timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY (email_hash)
+);
@@ -0,0 +1,5 @@
+-- Lifting a suppression keeps the row, to remember who lifted it and why.
+ALTER TABLE suppressions
+    ADD COLUMN lifted_at timestamptz NULL,
+    ADD COLUMN lift_reason TEXT NULL,
+    ADD COLUMN lifted_by uuid NULL REFERENCES users (user_id);
@@ -0,0 +1,6 @@
+-- Subscribers are unique by their normalized address, so that `Foo@Example.com` and
+-- `foo@example.com` are the same subscriber. `email` keeps the address as it was typed.
+ALTER TABLE subscriptions ADD COLUMN email_normalized TEXT NULL;
+
+-- Normalizing IDNA-encodes internationalized domains, which SQL cannot do: `run_migrations`
+-- fills the column in from Rust, before the next migration makes it unique.
@@ -0,0 +1,36 @@
+-- `sqlx migrate run` skips the backfill of the previous migration: only `migrate` runs it.
+DO $$
+BEGIN
+    IF EXISTS (SELECT 1 FROM subscriptions WHERE email_normalized IS NULL) THEN
+        RAISE EXCEPTION 'Some subscribers have no normalized email address'
+            USING HINT = 'Run the migrations with the `migrate` command of the application.';
+    END IF;
+END
+$$;
+
+-- Merging subscribers that collide is a decision for a human: report them all and stop,
+-- so that duplicates can be deleted (e.g. with `DELETE /admin/subscribers/{id}`) before migrating again.
+DO $$
+DECLARE
+    collisions TEXT;
+BEGIN
+    SELECT string_agg(format('%s: %s', email_normalized, ids), E'\n' ORDER BY email_normalized)
+    INTO collisions
+    FROM (
+        SELECT email_normalized, string_agg(format('%s (%s)', id, email), ', ' ORDER BY subscribed_at) AS ids
+        FROM subscriptions
+        GROUP BY email_normalized
+        HAVING count(*) > 1
+    ) AS duplicates;
+    IF collisions IS NOT NULL THEN
+        RAISE EXCEPTION 'Some subscribers share an email address once normalized'
+            USING DETAIL = collisions,
+                HINT = 'Delete or merge the duplicate subscribers, then run the migration again.';
+    END IF;
+END
+$$;
+
+ALTER TABLE subscriptions
+    ALTER COLUMN email_normalized SET NOT NULL,
+    DROP CONSTRAINT subscriptions_email_key,
+    ADD CONSTRAINT subscriptions_email_normalized_key UNIQUE (email_normalized);
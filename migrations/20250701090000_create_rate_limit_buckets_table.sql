@@ -0,0 +1,9 @@
+-- The token buckets of the rate limits, when they are shared by the instances of the application.
+-- A bucket is full again by `expires_at`, so it can be deleted from then on.
+CREATE TABLE rate_limit_buckets(
+    key TEXT NOT NULL,
+    tokens DOUBLE PRECISION NOT NULL,
+    updated_at timestamptz NOT NULL,
+    expires_at timestamptz NOT NULL,
+    PRIMARY KEY (key)
+);
@@ -0,0 +1,7 @@
+-- The signup form tokens that were already submitted, so that each is accepted once.
+-- A token is rejected as expired from `expires_at` on, so its row can be deleted from then on.
+CREATE TABLE used_form_tokens(
+    nonce uuid NOT NULL,
+    expires_at timestamptz NOT NULL,
+    PRIMARY KEY (nonce)
+);
@@ -0,0 +1,8 @@
+-- The confirmation emails of imported subscribers, sent by the background worker rather than
+-- while the import request is held open. A queued email goes away with its token.
+CREATE TABLE confirmation_email_queue(
+    subscription_token TEXT NOT NULL REFERENCES subscription_tokens (subscription_token) ON DELETE CASCADE,
+    attempts INT NOT NULL DEFAULT 0,
+    execute_after timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY (subscription_token)
+);
@@ -0,0 +1,341 @@
+//! Defenses of the signup form against scripts using it to send our welcome email to arbitrary addresses.
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use anyhow::Context;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, SecretString};
+use sha2::Sha256;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// The field of the signup form that is hidden from people: only bots fill it in.
+pub const HONEYPOT_FIELD: &str = "website";
+
+/// Expired used tokens are deleted every this many redemptions.
+const PRUNE_EVERY: u64 = 1000;
+
+/// Issues and verifies the tokens of `GET /subscriptions/form`: `<unix timestamp>.<nonce>.<hex hmac>`.
+/// The timestamp tells how long the form took to fill in, the nonce tells the tokens apart,
+/// the signature that we issued it.
+#[derive(Clone)]
+pub struct FormTokens {
+    hmac_secret: SecretString,
+    min_fill_time: chrono::Duration,
+    max_age: chrono::Duration,
+}
+
+impl FormTokens {
+    pub fn new(hmac_secret: SecretString, min_fill_time: chrono::Duration, max_age: chrono::Duration) -> Self {
+        Self { hmac_secret, min_fill_time, max_age }
+    }
+
+    pub fn min_fill_time(&self) -> chrono::Duration {
+        self.min_fill_time
+    }
+
+    fn mac(&self, issued_at: i64, nonce: &str) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.hmac_secret.expose_secret().as_bytes()).unwrap();
+        // Keeps these signatures apart from the others made with the same secret.
+        mac.update(format!("subscription-form:{}:{}", issued_at, nonce).as_bytes());
+        mac
+    }
+
+    pub fn issue(&self, now: DateTime<Utc>) -> String {
+        let issued_at = now.timestamp();
+        let nonce = Uuid::new_v4().simple().to_string();
+        let tag = hex::encode(self.mac(issued_at, &nonce).finalize().into_bytes());
+        format!("{}.{}.{}", issued_at, nonce, tag)
+    }
+
+    /// Check the signature and the age of the token. Whether it was already used is up to `UsedFormTokens`.
+    pub fn verify(&self, token: &str, now: DateTime<Utc>) -> Result<VerifiedFormToken, BotCheckError> {
+        let mut parts = token.splitn(3, '.');
+        let (Some(issued_at), Some(nonce), Some(tag)) = (parts.next(), parts.next(), parts.next()) else {
+            return Err(BotCheckError::InvalidFormToken);
+        };
+        let issued_at: i64 = issued_at.parse().map_err(|_| BotCheckError::InvalidFormToken)?;
+        let tag = hex::decode(tag).map_err(|_| BotCheckError::InvalidFormToken)?;
+        self.mac(issued_at, nonce)
+            .verify_slice(&tag)
+            .map_err(|_| BotCheckError::InvalidFormToken)?;
+        let nonce = Uuid::parse_str(nonce).map_err(|_| BotCheckError::InvalidFormToken)?;
+
+        let issued_at = DateTime::from_timestamp(issued_at, 0).ok_or(BotCheckError::InvalidFormToken)?;
+        let elapsed = now - issued_at;
+        if elapsed < self.min_fill_time {
+            return Err(BotCheckError::FilledTooFast);
+        }
+        if elapsed > self.max_age {
+            return Err(BotCheckError::ExpiredFormToken);
+        }
+        Ok(VerifiedFormToken { nonce, expires_at: issued_at + self.max_age })
+    }
+}
+
+/// A form token with a valid signature and age, which may still have been used already.
+#[derive(Debug, Clone, Copy)]
+pub struct VerifiedFormToken {
+    nonce: Uuid,
+    expires_at: DateTime<Utc>,
+}
+
+/// Keeps the form tokens that were submitted in `used_form_tokens` until they expire,
+/// so that a script cannot fetch one token and submit it over and over.
+#[derive(Clone)]
+pub struct UsedFormTokens {
+    db_pool: PgPool,
+    redemptions: Arc<AtomicU64>,
+}
+
+impl UsedFormTokens {
+    pub fn new(db_pool: PgPool) -> Self {
+        Self { db_pool, redemptions: Arc::new(AtomicU64::new(0)) }
+    }
+
+    /// Record the use of the token. `false` if it was already used.
+    #[tracing::instrument(name = "Redeem a form token", skip_all)]
+    pub async fn redeem(&self, token: &VerifiedFormToken) -> Result<bool, sqlx::Error> {
+        if self.redemptions.fetch_add(1, Ordering::Relaxed).is_multiple_of(PRUNE_EVERY) {
+            sqlx::query!("DELETE FROM used_form_tokens WHERE expires_at < now()")
+                .execute(&self.db_pool)
+                .await?;
+        }
+        let result = sqlx::query!(
+            "INSERT INTO used_form_tokens (nonce, expires_at) VALUES ($1, $2) ON CONFLICT (nonce) DO NOTHING",
+            token.nonce,
+            token.expires_at,
+        )
+        .execute(&self.db_pool)
+        .await?;
+        Ok(result.rows_affected() == 1)
+    }
+}
+
+/// Checks the response of the person to a CAPTCHA challenge.
+#[async_trait]
+pub trait CaptchaVerifier: Send + Sync {
+    /// Whether `response`, as submitted with the form, solves a challenge.
+    async fn verify(&self, response: &str) -> Result<bool, anyhow::Error>;
+}
+
+/// Asks the `siteverify` endpoint of the CAPTCHA provider.
+/// reCAPTCHA, hCaptcha and Turnstile all speak this protocol.
+pub struct SiteVerifyCaptcha {
+    http_client: reqwest::Client,
+    verify_url: String,
+    secret: SecretString,
+}
+
+#[derive(serde::Deserialize)]
+struct SiteVerifyResponse {
+    success: bool,
+}
+
+impl SiteVerifyCaptcha {
+    pub fn new(verify_url: String, secret: SecretString, timeout: Duration) -> Self {
+        let http_client = reqwest::Client::builder().timeout(timeout).build().unwrap();
+        Self { http_client, verify_url, secret }
+    }
+}
+
+#[async_trait]
+impl CaptchaVerifier for SiteVerifyCaptcha {
+    #[tracing::instrument(name = "Verify a CAPTCHA response", skip_all)]
+    async fn verify(&self, response: &str) -> Result<bool, anyhow::Error> {
+        let outcome: SiteVerifyResponse = self
+            .http_client
+            .post(&self.verify_url)
+            .form(&[("secret", self.secret.expose_secret()), ("response", response)])
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+            .context("The CAPTCHA provider answered with an unexpected body.")?;
+        Ok(outcome.success)
+    }
+}
+
+/// Accepts one response, whatever the challenge. For local development and tests.
+pub struct FakeCaptcha {
+    accepted_response: String,
+}
+
+impl FakeCaptcha {
+    pub fn new(accepted_response: String) -> Self {
+        Self { accepted_response }
+    }
+}
+
+#[async_trait]
+impl CaptchaVerifier for FakeCaptcha {
+    async fn verify(&self, response: &str) -> Result<bool, anyhow::Error> {
+        Ok(response == self.accepted_response)
+    }
+}
+
+/// What a submission of the signup form carries for the bot checks.
+#[derive(Debug, Default)]
+pub struct BotCheckFields<'a> {
+    pub honeypot: Option<&'a str>,
+    pub form_token: Option<&'a str>,
+    pub captcha_response: Option<&'a str>,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum BotCheckError {
+    #[error("The form token is missing: fetch one from `GET /subscriptions/form`.")]
+    MissingFormToken,
+    #[error("The form token is invalid.")]
+    InvalidFormToken,
+    #[error("The form was submitted too quickly after it was loaded.")]
+    FilledTooFast,
+    #[error("The form token has expired: reload the form.")]
+    ExpiredFormToken,
+    #[error("The form token was already used: reload the form.")]
+    UsedFormToken,
+    #[error("Failed to record the use of the form token.")]
+    FormTokenUnrecorded(#[source] sqlx::Error),
+    #[error("The CAPTCHA response is missing.")]
+    MissingCaptcha,
+    #[error("The CAPTCHA was not solved.")]
+    CaptchaFailed,
+    #[error("Failed to verify the CAPTCHA response.")]
+    CaptchaUnavailable(#[source] anyhow::Error),
+}
+
+/// The checks a submission of the signup form goes through. By default, none is.
+#[derive(Clone, Default)]
+pub struct BotProtection {
+    honeypot: bool,
+    form_tokens: Option<(FormTokens, UsedFormTokens)>,
+    captcha: Option<Arc<dyn CaptchaVerifier>>,
+}
+
+impl BotProtection {
+    /// Drop the submissions that fill the honeypot field in.
+    pub fn with_honeypot(mut self) -> Self {
+        self.honeypot = true;
+        self
+    }
+
+    /// Require a valid form token, issued long enough before the submission and not used before.
+    pub fn with_required_form_token(mut self, form_tokens: FormTokens, used_form_tokens: UsedFormTokens) -> Self {
+        self.form_tokens = Some((form_tokens, used_form_tokens));
+        self
+    }
+
+    /// Require a solved CAPTCHA.
+    pub fn with_captcha(mut self, verifier: Arc<dyn CaptchaVerifier>) -> Self {
+        self.captcha = Some(verifier);
+        self
+    }
+
+    pub fn captcha_required(&self) -> bool {
+        self.captcha.is_some()
+    }
+
+    /// Whether the submission fell for the honeypot. Bots are not told: they would stop falling for it.
+    pub fn caught_in_honeypot(&self, fields: &BotCheckFields<'_>) -> bool {
+        self.honeypot && fields.honeypot.is_some_and(|value| !value.trim().is_empty())
+    }
+
+    /// Check the form token, then the CAPTCHA, which costs a request to its provider.
+    /// The form token is not used up yet: the submission can still be rejected for its fields and
+    /// resubmitted with the same token. `redeem` it once the submission is accepted.
+    #[tracing::instrument(name = "Check a signup form submission for bots", skip_all)]
+    pub async fn check(&self, fields: &BotCheckFields<'_>) -> Result<Option<VerifiedFormToken>, BotCheckError> {
+        let mut verified = None;
+        if let Some((form_tokens, _)) = &self.form_tokens {
+            let token = fields.form_token.ok_or(BotCheckError::MissingFormToken)?;
+            verified = Some(form_tokens.verify(token, Utc::now())?);
+        }
+        if let Some(captcha) = &self.captcha {
+            let response = fields.captcha_response.ok_or(BotCheckError::MissingCaptcha)?;
+            let solved = captcha
+                .verify(response)
+                .await
+                .map_err(BotCheckError::CaptchaUnavailable)?;
+            if !solved {
+                return Err(BotCheckError::CaptchaFailed);
+            }
+        }
+        Ok(verified)
+    }
+
+    /// Use the form token that passed `check` up, so that it cannot be submitted again.
+    pub async fn redeem(&self, token: Option<VerifiedFormToken>) -> Result<(), BotCheckError> {
+        let (Some((_, used_form_tokens)), Some(token)) = (&self.form_tokens, token) else {
+            return Ok(());
+        };
+        let redeemed = used_form_tokens
+            .redeem(&token)
+            .await
+            .map_err(BotCheckError::FormTokenUnrecorded)?;
+        if !redeemed {
+            return Err(BotCheckError::UsedFormToken);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use chrono::{Duration, Utc};
+    use claim::{assert_matches, assert_ok};
+    use secrecy::SecretString;
+    use super::{BotCheckError, BotCheckFields, BotProtection, FakeCaptcha, FormTokens};
+
+    fn form_tokens(secret: &str) -> FormTokens {
+        FormTokens::new(SecretString::from(secret), Duration::seconds(3), Duration::hours(1))
+    }
+
+    #[test]
+    fn a_token_is_valid_between_the_minimum_fill_time_and_its_maximum_age() {
+        let tokens = form_tokens("secret");
+        let issued_at = Utc::now();
+        let token = tokens.issue(issued_at);
+
+        assert_matches!(tokens.verify(&token, issued_at + Duration::seconds(1)), Err(BotCheckError::FilledTooFast));
+        assert_ok!(tokens.verify(&token, issued_at + Duration::seconds(3)));
+        assert_ok!(tokens.verify(&token, issued_at + Duration::minutes(59)));
+        assert_matches!(tokens.verify(&token, issued_at + Duration::hours(2)), Err(BotCheckError::ExpiredFormToken));
+    }
+
+    #[test]
+    fn a_token_signed_with_another_secret_or_tampered_with_is_rejected() {
+        let issued_at = Utc::now() - Duration::minutes(1);
+        let token = form_tokens("another secret").issue(issued_at);
+        assert_matches!(form_tokens("secret").verify(&token, Utc::now()), Err(BotCheckError::InvalidFormToken));
+
+        let token = form_tokens("secret").issue(issued_at);
+        let (_, nonce_and_tag) = token.split_once('.').unwrap();
+        let backdated = format!("{}.{}", issued_at.timestamp() - 60, nonce_and_tag);
+        assert_matches!(form_tokens("secret").verify(&backdated, Utc::now()), Err(BotCheckError::InvalidFormToken));
+        assert_matches!(form_tokens("secret").verify("garbage", Utc::now()), Err(BotCheckError::InvalidFormToken));
+    }
+
+    #[test]
+    fn only_a_filled_honeypot_catches_a_submission() {
+        let protection = BotProtection::default().with_honeypot();
+        assert!(protection.caught_in_honeypot(&BotCheckFields { honeypot: Some("https://spam"), ..Default::default() }));
+        assert!(!protection.caught_in_honeypot(&BotCheckFields { honeypot: Some(" "), ..Default::default() }));
+        assert!(!protection.caught_in_honeypot(&BotCheckFields::default()));
+        let disabled = BotProtection::default();
+        assert!(!disabled.caught_in_honeypot(&BotCheckFields { honeypot: Some("https://spam"), ..Default::default() }));
+    }
+
+    #[tokio::test]
+    async fn the_captcha_must_be_solved_when_required() {
+        let protection = BotProtection::default().with_captcha(Arc::new(FakeCaptcha::new("solved".into())));
+        let fields = |captcha_response| BotCheckFields { captcha_response, ..Default::default() };
+
+        assert_matches!(protection.check(&fields(None)).await, Err(BotCheckError::MissingCaptcha));
+        assert_matches!(protection.check(&fields(Some("wrong"))).await, Err(BotCheckError::CaptchaFailed));
+        assert_ok!(protection.check(&fields(Some("solved"))).await);
+    }
+}
@@ -0,0 +1,260 @@
+//! The command line of the binary: serving the API, and the chores of operating it.
+use std::io::{BufRead, Write};
+use std::path::PathBuf;
+use anyhow::Context;
+use clap::{Parser, Subcommand};
+use futures_util::TryStreamExt;
+use secrecy::{ExposeSecret, SecretString};
+use sqlx::PgPool;
+use tokio::io::AsyncBufRead;
+use uuid::Uuid;
+use crate::authentication::compute_password_hash;
+use crate::configuration::Settings;
+use crate::damain::{SubscriberEmail, SubscriberId, SubscriptionStatus};
+use crate::email_client::EmailClient;
+use crate::import::{self, ImportMode, ImportReport};
+use crate::startup::{get_connection_pool, run_migrations};
+use crate::subscribers::{self, ExportFormat};
+use crate::suppressions::EmailHasher;
+
+#[derive(Parser)]
+#[command(version, about = "A newsletter delivery service.")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Serve the API and deliver the newsletter issues. The default.
+    Serve,
+    /// Apply the pending database migrations, then exit.
+    Migrate,
+    /// Create an admin, reading their password from the standard input.
+    CreateAdmin {
+        #[arg(long)]
+        username: String,
+    },
+    /// Manage the subscribers.
+    #[command(subcommand)]
+    Subscribers(SubscribersCommand),
+    /// Send an email through the email API, to check the configuration.
+    SendTestEmail {
+        #[arg(long)]
+        to: String,
+    },
+    /// Manage the confirmation tokens.
+    #[command(subcommand)]
+    Tokens(TokensCommand),
+}
+
+#[derive(Subcommand)]
+pub enum SubscribersCommand {
+    /// Print the subscribers, one per line.
+    List {
+        #[arg(long)]
+        status: Option<SubscriptionStatus>,
+    },
+    /// Write the subscribers to the standard output.
+    Export {
+        #[arg(long, value_enum, default_value_t = ExportFormat::Csv)]
+        format: ExportFormat,
+        #[arg(long)]
+        status: Option<SubscriptionStatus>,
+    },
+    /// Add the subscribers of a CSV file with `email` and `name` columns.
+    Import {
+        file: PathBuf,
+        /// `pending_confirmation` subscribers are sent a confirmation email.
+        #[arg(long, default_value = "confirmed")]
+        status: SubscriptionStatus,
+        /// How the `confirmed` subscribers gave their consent.
+        #[arg(long)]
+        consent_source: Option<String>,
+    },
+    /// Delete a subscriber, given their id or their email.
+    Delete { subscriber: String },
+}
+
+#[derive(Subcommand)]
+pub enum TokensCommand {
+    /// Delete the confirmation tokens older than `application.subscription_token_ttl_hours`.
+    PurgeExpired,
+}
+
+/// Run any command but `serve`, writing its report to `out`.
+pub async fn execute(command: Command, configuration: Settings, out: &mut impl Write) -> Result<(), anyhow::Error> {
+    let db_pool = get_connection_pool(&configuration.database);
+    match command {
+        Command::Serve => anyhow::bail!("`serve` is not a one-off command."),
+        Command::Migrate => {
+            run_migrations(&db_pool)
+                .await
+                .context("Failed to migrate the database.")?;
+            writeln!(out, "The database is up to date.")?;
+        }
+        Command::CreateAdmin { username } => {
+            let mut password = String::new();
+            std::io::stdin()
+                .lock()
+                .read_line(&mut password)
+                .context("Failed to read the password from the standard input.")?;
+            let password = SecretString::from(password.trim_end_matches(['\r', '\n']));
+            let user_id = create_admin(&db_pool, &username, password).await?;
+            writeln!(out, "Created the admin `{}` ({}).", username, user_id)?;
+        }
+        Command::Subscribers(SubscribersCommand::List { status }) => {
+            list_subscribers(&db_pool, status, out).await?;
+        }
+        Command::Subscribers(SubscribersCommand::Export { format, status }) => {
+            export_subscribers(&db_pool, status, format, out).await?;
+        }
+        Command::Subscribers(SubscribersCommand::Import { file, status, consent_source }) => {
+            let mode = ImportMode::new(status, consent_source)?;
+            let input = tokio::fs::File::open(&file)
+                .await
+                .with_context(|| format!("Failed to open {}.", file.display()))?;
+            let input = tokio::io::BufReader::new(input);
+            import_subscribers(&db_pool, &configuration.application.email_hasher(), &mode, input, out).await?;
+        }
+        Command::Subscribers(SubscribersCommand::Delete { subscriber }) => {
+            delete_subscriber(&db_pool, &subscriber).await?;
+            writeln!(out, "Deleted {}.", subscriber)?;
+        }
+        Command::SendTestEmail { to } => {
+            let email_client = configuration
+                .email_client
+                .client()
+                .with_suppression_list(db_pool.clone(), configuration.application.email_hasher());
+            send_test_email(&email_client, to).await?;
+            writeln!(out, "The test email was accepted by the email API.")?;
+        }
+        Command::Tokens(TokensCommand::PurgeExpired) => {
+            let cutoff = chrono::Utc::now() - configuration.application.subscription_token_ttl();
+            let purged = subscribers::purge_expired_tokens(&db_pool, cutoff)
+                .await
+                .context("Failed to purge the expired tokens.")?;
+            writeln!(out, "Purged {} expired tokens.", purged)?;
+        }
+    }
+    Ok(())
+}
+
+pub async fn create_admin(db_pool: &PgPool, username: &str, password: SecretString) -> Result<Uuid, anyhow::Error> {
+    anyhow::ensure!(!username.trim().is_empty(), "The username must not be empty.");
+    anyhow::ensure!(!password.expose_secret().is_empty(), "The password must not be empty.");
+    let password_hash = tokio::task::spawn_blocking(move || compute_password_hash(password))
+        .await
+        .context("Failed to spawn blocking task.")??;
+    let user_id = Uuid::new_v4();
+    sqlx::query!(
+        "INSERT INTO users (user_id, username, password_hash) VALUES ($1, $2, $3)",
+        user_id,
+        username,
+        password_hash.expose_secret(),
+    )
+    .execute(db_pool)
+    .await
+    .with_context(|| format!("Failed to create the admin `{}`. Is the username already taken?", username))?;
+    Ok(user_id)
+}
+
+pub async fn list_subscribers(
+    db_pool: &PgPool,
+    status: Option<SubscriptionStatus>,
+    out: &mut impl Write,
+) -> Result<(), anyhow::Error> {
+    let subscribers = subscribers::list_subscribers(db_pool, status)
+        .await
+        .context("Failed to fetch the subscribers.")?;
+    for subscriber in &subscribers {
+        writeln!(out, "{}", subscriber.csv_record().join("\t"))?;
+    }
+    writeln!(out, "{} subscribers.", subscribers.len())?;
+    Ok(())
+}
+
+pub async fn export_subscribers(
+    db_pool: &PgPool,
+    status: Option<SubscriptionStatus>,
+    format: ExportFormat,
+    out: &mut impl Write,
+) -> Result<(), anyhow::Error> {
+    let mut batches = std::pin::pin!(subscribers::export_subscribers(db_pool.clone(), status));
+    format.write_header(out)?;
+    while let Some(batch) = batches
+        .try_next()
+        .await
+        .context("Failed to fetch the subscribers.")?
+    {
+        format.write_subscribers(out, &batch)?;
+    }
+    Ok(())
+}
+
+/// Import a CSV file, then print the invalid rows and a summary.
+pub async fn import_subscribers(
+    db_pool: &PgPool,
+    email_hasher: &EmailHasher,
+    mode: &ImportMode,
+    input: impl AsyncBufRead + Unpin,
+    out: &mut impl Write,
+) -> Result<ImportReport, anyhow::Error> {
+    let report = import::import_subscribers(db_pool, email_hasher, mode, input).await?;
+    for row in &report.errors {
+        for error in &row.errors {
+            writeln!(out, "Line {}: {}", row.line, error.message)?;
+        }
+    }
+    if report.invalid > report.errors.len() {
+        writeln!(out, "... and {} more invalid rows.", report.invalid - report.errors.len())?;
+    }
+    writeln!(
+        out,
+        "Imported {} subscribers. {} were already subscribed, {} rows were invalid.",
+        report.imported, report.already_subscribed, report.invalid
+    )?;
+    if report.suppressed > 0 {
+        writeln!(out, "{} suppressed addresses were skipped.", report.suppressed)?;
+    }
+    if report.confirmation_emails_queued > 0 {
+        writeln!(
+            out,
+            "{} confirmation emails were queued: the background worker of the application sends them.",
+            report.confirmation_emails_queued
+        )?;
+    }
+    Ok(report)
+}
+
+/// Delete a subscriber, given their id or their email.
+pub async fn delete_subscriber(db_pool: &PgPool, subscriber: &str) -> Result<(), anyhow::Error> {
+    let subscriber_id = match Uuid::parse_str(subscriber) {
+        Ok(id) => Some(SubscriberId::new(id)),
+        Err(_) => subscribers::find_subscriber_id(db_pool, subscriber)
+            .await
+            .context("Failed to look the subscriber up.")?,
+    };
+    let deleted = match subscriber_id {
+        Some(subscriber_id) => subscribers::delete_subscriber(db_pool, subscriber_id)
+            .await
+            .context("Failed to delete the subscriber.")?,
+        None => false,
+    };
+    anyhow::ensure!(deleted, "There is no subscriber {}.", subscriber);
+    Ok(())
+}
+
+pub async fn send_test_email(email_client: &EmailClient, to: String) -> Result<(), anyhow::Error> {
+    let recipient = SubscriberEmail::parse(to).map_err(|e| anyhow::anyhow!("The email {}.", e))?;
+    email_client
+        .send_email(
+            recipient,
+            "A test email",
+            "<p>This is a test email: the newsletter can send emails.</p>",
+            "This is a test email: the newsletter can send emails.",
+        )
+        .await
+        .context("Failed to send the test email.")?;
+    Ok(())
+}
@@ -1,22 +1,272 @@
+use std::net::IpAddr;
+use std::sync::Arc;
+use anyhow::Context;
 use secrecy::{ExposeSecret, SecretString};
 use config::Config;
-use sqlx::ConnectOptions;
+use ipnet::IpNet;
+use sqlx::{ConnectOptions, PgPool};
 use sqlx::postgres::{PgConnectOptions, PgSslMode};
 use tracing::log::LevelFilter;
-use crate::damain::SubscriberEmail;
+use crate::bot_protection::{BotProtection, FakeCaptcha, FormTokens, SiteVerifyCaptcha, UsedFormTokens};
+use crate::damain::{SubscriberEmail, ValidationError};
+use crate::deliverability::{parse_domain_list, DeliverabilityChecker, DnsResolver, StubResolver};
+use crate::email_client::EmailClient;
+use crate::suppressions::EmailHasher;
+use crate::rate_limit::{InMemoryStore, PostgresStore, Quota, RateLimitStore, RateLimiter};
+use crate::tracking::LinkTracker;
 
 #[derive(serde::Deserialize, Debug, Clone)]
 pub struct Settings {
     pub database: DatabaseSettings,
     pub application: ApplicationSettings,
-    pub jaeger: JaegerSettings,
+    pub telemetry: TelemetrySettings,
     pub email_client: EmailClientSettings,
+    pub metrics: MetricsSettings,
+    pub readiness: ReadinessSettings,
+    pub deliverability: DeliverabilitySettings,
+    pub bot_protection: BotProtectionSettings,
+    pub rate_limit: RateLimitSettings,
 }
 
+/// The limits on the public subscription endpoints.
 #[derive(serde::Deserialize, Debug, Clone)]
-pub struct JaegerSettings {
-    pub address: String,
-    pub port: u16,
+pub struct RateLimitSettings {
+    pub store: RateLimitStoreSettings,
+    /// The proxies whose `X-Forwarded-For` header is believed: IP addresses or CIDR ranges.
+    pub trusted_proxies: Vec<String>,
+    /// The requests of each client IP to `/subscriptions`.
+    pub per_ip: Option<QuotaSettings>,
+    /// The confirmation emails sent to each address.
+    pub confirmation_emails_per_address: Option<QuotaSettings>,
+}
+
+impl RateLimitSettings {
+    pub fn rate_limiter(&self, db_pool: &PgPool, email_hasher: &EmailHasher) -> Result<RateLimiter, anyhow::Error> {
+        let store: Arc<dyn RateLimitStore> = match self.store {
+            RateLimitStoreSettings::Memory => Arc::new(InMemoryStore::default()),
+            RateLimitStoreSettings::Postgres => Arc::new(PostgresStore::new(db_pool.clone())),
+        };
+        let trusted_proxies = self
+            .trusted_proxies
+            .iter()
+            .map(|proxy| {
+                proxy
+                    .parse::<IpNet>()
+                    .or_else(|_| proxy.parse::<IpAddr>().map(IpNet::from))
+                    .with_context(|| format!("{:?} is neither an IP address nor a CIDR range.", proxy))
+            })
+            .collect::<Result<_, _>>()?;
+        let mut limiter = RateLimiter::new(store).with_trusted_proxies(trusted_proxies);
+        if let Some(quota) = &self.per_ip {
+            limiter = limiter.with_per_ip_limit(quota.quota().context("Invalid per IP rate limit.")?);
+        }
+        if let Some(quota) = &self.confirmation_emails_per_address {
+            let quota = quota.quota().context("Invalid confirmation email rate limit.")?;
+            limiter = limiter.with_confirmation_email_limit(quota, email_hasher.clone());
+        }
+        Ok(limiter)
+    }
+}
+
+/// Where the token buckets are kept.
+#[derive(serde::Deserialize, Debug, Clone)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum RateLimitStoreSettings {
+    /// In each instance: every instance enforces the limits on its own.
+    Memory,
+    /// In the database, shared by all the instances.
+    Postgres,
+}
+
+/// Up to `capacity` requests at once, refilled at `capacity` per `period_seconds`.
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct QuotaSettings {
+    pub capacity: u32,
+    pub period_seconds: u64,
+}
+
+impl QuotaSettings {
+    pub fn quota(&self) -> Result<Quota, anyhow::Error> {
+        Quota::new(self.capacity, std::time::Duration::from_secs(self.period_seconds))
+    }
+}
+
+/// The defenses of the signup form against bots.
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct BotProtectionSettings {
+    /// Silently drop the submissions that fill the hidden `website` field in.
+    pub honeypot: bool,
+    pub form_token: FormTokenSettings,
+    pub captcha: CaptchaSettings,
+}
+
+impl BotProtectionSettings {
+    pub fn bot_protection(&self, hmac_secret: &SecretString, db_pool: &PgPool) -> BotProtection {
+        let mut protection = BotProtection::default();
+        if self.honeypot {
+            protection = protection.with_honeypot();
+        }
+        if self.form_token.required {
+            protection = protection
+                .with_required_form_token(self.form_tokens(hmac_secret), UsedFormTokens::new(db_pool.clone()));
+        }
+        match &self.captcha {
+            CaptchaSettings::None => {}
+            CaptchaSettings::SiteVerify { verify_url, secret, timeout_milliseconds } => {
+                let timeout = std::time::Duration::from_millis(*timeout_milliseconds);
+                let verifier = SiteVerifyCaptcha::new(verify_url.clone(), secret.clone(), timeout);
+                protection = protection.with_captcha(Arc::new(verifier));
+            }
+            CaptchaSettings::Fake { accepted_response } => {
+                protection = protection.with_captcha(Arc::new(FakeCaptcha::new(accepted_response.clone())));
+            }
+        }
+        protection
+    }
+
+    pub fn form_tokens(&self, hmac_secret: &SecretString) -> FormTokens {
+        FormTokens::new(
+            hmac_secret.clone(),
+            chrono::Duration::seconds(self.form_token.min_fill_seconds as i64),
+            chrono::Duration::seconds(self.form_token.max_age_seconds as i64),
+        )
+    }
+}
+
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct FormTokenSettings {
+    /// Reject the submissions without a token from `GET /subscriptions/form`.
+    pub required: bool,
+    /// People do not fill the form in faster than this after loading it.
+    pub min_fill_seconds: u64,
+    pub max_age_seconds: u64,
+}
+
+/// Which CAPTCHA the signup form must come with the solution of.
+#[derive(serde::Deserialize, Debug, Clone)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum CaptchaSettings {
+    None,
+    /// A provider with a `siteverify` endpoint: reCAPTCHA, hCaptcha or Turnstile.
+    SiteVerify { verify_url: String, secret: SecretString, timeout_milliseconds: u64 },
+    /// Accept one response, whatever the challenge.
+    Fake { accepted_response: String },
+}
+
+/// The checks the address of a new subscriber goes through, on top of its syntax.
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct DeliverabilitySettings {
+    /// Reject the common misspellings of the big providers' domains, suggesting the correction.
+    pub suggest_typo_corrections: bool,
+    /// A file of disposable email domains, one per line, whose addresses are rejected.
+    pub disposable_domains_file: Option<std::path::PathBuf>,
+    /// Reject addresses like `postmaster@` or `noreply@`, which do not reach a person.
+    pub reject_role_addresses: bool,
+    pub mail_server_check: MailServerCheckSettings,
+}
+
+impl DeliverabilitySettings {
+    pub fn checker(&self) -> Result<DeliverabilityChecker, anyhow::Error> {
+        let mut checker = DeliverabilityChecker::default();
+        if self.suggest_typo_corrections {
+            checker = checker.with_typo_suggestions();
+        }
+        if let Some(path) = &self.disposable_domains_file {
+            let contents = std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read the disposable domains from {}.", path.display()))?;
+            checker = checker.with_disposable_domains(parse_domain_list(&contents));
+        }
+        if self.reject_role_addresses {
+            checker = checker.with_role_address_rejection();
+        }
+        match &self.mail_server_check {
+            MailServerCheckSettings::None => {}
+            MailServerCheckSettings::Dns { timeout_milliseconds } => {
+                let timeout = std::time::Duration::from_millis(*timeout_milliseconds);
+                checker = checker.with_mail_server_check(Arc::new(DnsResolver::from_system_conf(timeout)?));
+            }
+            MailServerCheckSettings::Stub { domains_without_mail_server } => {
+                let resolver = StubResolver::new(domains_without_mail_server.iter().cloned());
+                checker = checker.with_mail_server_check(Arc::new(resolver));
+            }
+        }
+        Ok(checker)
+    }
+}
+
+/// How to find out whether the domain of an address receives email.
+#[derive(serde::Deserialize, Debug, Clone)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum MailServerCheckSettings {
+    None,
+    /// Look the MX records up with the name servers of the system.
+    Dns { timeout_milliseconds: u64 },
+    /// Pretend every domain receives email, except these.
+    Stub { domains_without_mail_server: Vec<String> },
+}
+
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct ReadinessSettings {
+    /// How long each dependency gets to answer `/ready`.
+    pub timeout_milliseconds: u64,
+    /// Also check that the email API can be reached. It is not critical: we can still take
+    /// subscriptions while it is down, the confirmation emails are simply not sent.
+    pub probe_email_api: bool,
+}
+
+impl ReadinessSettings {
+    pub fn timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.timeout_milliseconds)
+    }
+}
+
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct MetricsSettings {
+    /// Serve the metrics in the Prometheus format on `/metrics`.
+    pub prometheus_enabled: bool,
+    /// Push the metrics to an OpenTelemetry collector, over gRPC.
+    pub otlp_endpoint: Option<String>,
+    pub export_interval_seconds: u64,
+}
+
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct TelemetrySettings {
+    pub service_name: String,
+    /// Defaults to the version of the crate.
+    pub service_version: Option<String>,
+    pub environment: String,
+    pub exporter: TraceExporterSettings,
+    pub sampler: SamplerSettings,
+}
+
+impl TelemetrySettings {
+    pub fn service_version(&self) -> String {
+        self.service_version
+            .clone()
+            .unwrap_or_else(|| env!("CARGO_PKG_VERSION").into())
+    }
+}
+
+/// Where the spans are sent.
+#[derive(serde::Deserialize, Debug, Clone)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum TraceExporterSettings {
+    None,
+    OtlpGrpc { endpoint: String },
+    OtlpHttp { endpoint: String },
+    Stdout,
+}
+
+/// Which traces are recorded.
+#[derive(serde::Deserialize, Debug, Clone)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SamplerSettings {
+    AlwaysOn,
+    AlwaysOff,
+    /// Keep a fraction of the traces, between 0 and 1.
+    Ratio { ratio: f64 },
+    /// Follow the decision of the caller, and use `ratio` for the traces we start.
+    ParentBased { ratio: f64 },
 }
 
 #[derive(serde::Deserialize, Debug, Clone)]
@@ -24,6 +274,31 @@ pub struct ApplicationSettings {
     pub address: String,
     pub port: u16,
     pub base_url: String,
+    pub hmac_secret: SecretString,
+    /// Keys the hashes of the suppression list. Changing it forgets every suppression.
+    pub email_hash_key: SecretString,
+    /// How long in-flight requests and background tasks get to finish when the application stops.
+    pub shutdown_timeout_seconds: u64,
+    /// How long a confirmation link stays valid, before `tokens purge-expired` deletes its token.
+    pub subscription_token_ttl_hours: u64,
+}
+
+impl ApplicationSettings {
+    pub fn shutdown_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.shutdown_timeout_seconds)
+    }
+
+    pub fn subscription_token_ttl(&self) -> chrono::Duration {
+        chrono::Duration::hours(self.subscription_token_ttl_hours as i64)
+    }
+
+    pub fn link_tracker(&self) -> LinkTracker {
+        LinkTracker::new(self.base_url.clone(), self.hmac_secret.clone())
+    }
+
+    pub fn email_hasher(&self) -> EmailHasher {
+        EmailHasher::new(self.email_hash_key.clone())
+    }
 }
 
 #[derive(serde::Deserialize, Debug, Clone)]
@@ -33,6 +308,8 @@ pub struct DatabaseSettings { pub username: String,
     pub host: String,
     pub database_name: String,
     pub require_ssl: bool,
+    /// Apply the pending migrations when the application starts, instead of with `migrate`.
+    pub run_migrations_on_startup: bool,
 }
 
 #[derive(serde::Deserialize, Debug, Clone)]
@@ -43,7 +320,13 @@ pub struct EmailClientSettings {
 }
 
 impl EmailClientSettings {
-    pub fn sender(&self) -> Result<SubscriberEmail, String> {
+    pub fn client(self) -> EmailClient {
+        let sender_email = self.sender().expect("Invalid sender email address.");
+        let timeout = self.timeout();
+        EmailClient::new(sender_email, self.base_url, timeout)
+    }
+
+    pub fn sender(&self) -> Result<SubscriberEmail, ValidationError> {
         SubscriberEmail::parse(self.sender_email.clone())
     }
     
@@ -67,7 +350,7 @@ impl DatabaseSettings {
             .host(&self.host)
             .port(self.port)
             .username(&self.username)
-            .password(&self.password.expose_secret())
+            .password(self.password.expose_secret())
             .ssl_mode(ssl_mode)
     }
 }
@@ -113,4 +396,26 @@ impl TryFrom<String> for Environment {
             "production" => Ok(Self::Production),
             other => Err(format!("{} is not a supported environment. Use either `local` or `production`.", other )),
     } }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trace_exporters_are_selected_by_kind() {
+        let exporter: TraceExporterSettings = serde_json::from_str(r#"{"kind": "none"}"#).unwrap();
+        assert!(matches!(exporter, TraceExporterSettings::None));
+
+        let exporter: TraceExporterSettings =
+            serde_json::from_str(r#"{"kind": "otlp_http", "endpoint": "http://collector:4318"}"#).unwrap();
+        assert!(matches!(exporter, TraceExporterSettings::OtlpHttp { endpoint } if endpoint == "http://collector:4318"));
+    }
+
+    #[test]
+    fn samplers_are_selected_by_kind() {
+        let sampler: SamplerSettings = serde_json::from_str(r#"{"kind": "ratio", "ratio": 0.25}"#).unwrap();
+        assert!(matches!(sampler, SamplerSettings::Ratio { ratio } if ratio == 0.25));
+
+        assert!(serde_json::from_str::<SamplerSettings>(r#"{"kind": "sometimes"}"#).is_err());
+    }
+}
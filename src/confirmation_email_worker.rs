@@ -0,0 +1,171 @@
+//! Sends the confirmation emails queued in `confirmation_email_queue`, e.g. by imports.
+use std::time::Duration;
+use sqlx::{PgPool, Postgres, Transaction};
+use crate::configuration::Settings;
+use crate::email_client::{EmailClient, SendEmailError};
+use crate::issue_delivery_worker::ExecutionOutcome;
+use crate::metrics::{EmailKind, Metrics};
+use crate::routes::{parse_new_subscriber, send_confirmation_email};
+use crate::shutdown::ShutdownSignal;
+use crate::startup::get_connection_pool;
+
+/// How many times we try to send a confirmation email before giving up.
+const MAX_SEND_ATTEMPTS: i32 = 5;
+
+struct Task {
+    subscription_token: String,
+    attempts: i32,
+    email: String,
+    name: String,
+    status: String,
+}
+
+/// Queue the confirmation email of each token, in the transaction that stores them.
+#[tracing::instrument(name = "Queue confirmation emails", skip_all)]
+pub async fn queue_confirmation_emails(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscription_tokens: &[String],
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "INSERT INTO confirmation_email_queue (subscription_token) SELECT * FROM UNNEST($1::text[])",
+        subscription_tokens,
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(skip_all, err)]
+pub async fn try_send_confirmation_email(
+    db_pool: &PgPool,
+    email_client: &EmailClient,
+    base_url: &str,
+    metrics: &Metrics,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let Some((mut transaction, task)) = dequeue_task(db_pool).await? else {
+        return Ok(ExecutionOutcome::EmptyQueue);
+    };
+    if task.status != "pending_confirmation" {
+        tracing::info!("Dropping the confirmation email of a subscriber who is no longer pending.");
+        delete_task(&mut transaction, &task).await?;
+        transaction.commit().await?;
+        return Ok(ExecutionOutcome::TaskCompleted);
+    }
+    // The address was valid when it was queued: retrying would not make it valid again.
+    let new_subscriber = match parse_new_subscriber(task.email.clone(), task.name.clone()) {
+        Ok(new_subscriber) => new_subscriber,
+        Err(errors) => {
+            tracing::error!(?errors, "Dropping a confirmation email: the stored contact details are invalid.");
+            metrics.email_failed(EmailKind::Confirmation);
+            delete_task(&mut transaction, &task).await?;
+            transaction.commit().await?;
+            return Ok(ExecutionOutcome::TaskCompleted);
+        }
+    };
+
+    match send_confirmation_email(email_client, new_subscriber, base_url, &task.subscription_token).await {
+        Ok(()) => {
+            metrics.email_sent(EmailKind::Confirmation);
+            delete_task(&mut transaction, &task).await?;
+        }
+        Err(SendEmailError::Suppressed) => delete_task(&mut transaction, &task).await?,
+        Err(e) => {
+            metrics.email_failed(EmailKind::Confirmation);
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to send a queued confirmation email.",
+            );
+            mark_failed_attempt(&mut transaction, &task).await?;
+        }
+    }
+    transaction.commit().await?;
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+#[tracing::instrument(skip_all)]
+async fn dequeue_task(db_pool: &PgPool) -> Result<Option<(Transaction<'static, Postgres>, Task)>, anyhow::Error> {
+    let mut transaction = db_pool.begin().await?;
+    let task = sqlx::query_as!(
+        Task,
+        r#"SELECT q.subscription_token, q.attempts, s.email, s.name, s.status
+        FROM confirmation_email_queue q
+        JOIN subscription_tokens t ON t.subscription_token = q.subscription_token
+        JOIN subscriptions s ON s.id = t.subscriber_id
+        WHERE q.execute_after <= now()
+        FOR UPDATE OF q
+        SKIP LOCKED
+        LIMIT 1"#,
+    )
+    .fetch_optional(&mut *transaction)
+    .await?;
+    Ok(task.map(|task| (transaction, task)))
+}
+
+#[tracing::instrument(skip_all)]
+async fn delete_task(transaction: &mut Transaction<'_, Postgres>, task: &Task) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        "DELETE FROM confirmation_email_queue WHERE subscription_token = $1",
+        task.subscription_token,
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
+
+/// Record a failed attempt: the email is retried with an exponential backoff
+/// until it runs out of attempts, then it is dropped.
+#[tracing::instrument(skip_all)]
+async fn mark_failed_attempt(transaction: &mut Transaction<'_, Postgres>, task: &Task) -> Result<(), anyhow::Error> {
+    let attempts = task.attempts + 1;
+    if attempts >= MAX_SEND_ATTEMPTS {
+        tracing::error!("Giving up on a confirmation email after {} attempts.", attempts);
+        return delete_task(transaction, task).await;
+    }
+    let backoff_seconds = 2_f64.powi(attempts) * 30.0;
+    sqlx::query!(
+        r#"UPDATE confirmation_email_queue
+        SET attempts = $2, execute_after = now() + make_interval(secs => $3)
+        WHERE subscription_token = $1"#,
+        task.subscription_token,
+        attempts,
+        backoff_seconds,
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
+
+async fn worker_loop(
+    db_pool: PgPool,
+    email_client: EmailClient,
+    base_url: String,
+    metrics: Metrics,
+    mut shutdown: ShutdownSignal,
+) -> Result<(), anyhow::Error> {
+    while !shutdown.is_requested() {
+        let pause = match try_send_confirmation_email(&db_pool, &email_client, &base_url, &metrics).await {
+            Ok(ExecutionOutcome::EmptyQueue) => Duration::from_secs(10),
+            Err(_) => Duration::from_secs(1),
+            Ok(ExecutionOutcome::TaskCompleted) => continue,
+        };
+        tokio::select! {
+            _ = tokio::time::sleep(pause) => {}
+            _ = shutdown.requested() => {}
+        }
+    }
+    Ok(())
+}
+
+pub async fn run_worker_until_stopped(
+    configuration: Settings,
+    metrics: Metrics,
+    shutdown: ShutdownSignal,
+) -> Result<(), anyhow::Error> {
+    let connection_pool = get_connection_pool(&configuration.database);
+    let email_client = configuration
+        .email_client
+        .client()
+        .with_suppression_list(connection_pool.clone(), configuration.application.email_hasher());
+    worker_loop(connection_pool, email_client, configuration.application.base_url, metrics, shutdown).await
+}
@@ -0,0 +1,284 @@
+//! Just enough of RFC 4180 to move subscriber lists in and out of spreadsheets.
+use std::io::Write;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt};
+
+/// The characters that make a spreadsheet read a cell as a formula.
+const FORMULA_TRIGGERS: [char; 6] = ['=', '+', '-', '@', '\t', '\r'];
+
+/// Write one record, quoting the fields that need it. Fields that a spreadsheet would run as
+/// formulas, like a name of `=HYPERLINK(...)` typed into the signup form, are prefixed with `'`.
+pub fn write_record<W, I>(out: &mut W, fields: I) -> std::io::Result<()>
+where
+    W: Write,
+    I: IntoIterator,
+    I::Item: AsRef<str>,
+{
+    for (i, field) in fields.into_iter().enumerate() {
+        if i > 0 {
+            out.write_all(b",")?;
+        }
+        let field = field.as_ref();
+        let escaped;
+        let field = if field.starts_with(FORMULA_TRIGGERS) {
+            escaped = format!("'{}", field);
+            escaped.as_str()
+        } else {
+            field
+        };
+        if field.contains([',', '"', '\n', '\r']) {
+            write!(out, "\"{}\"", field.replace('"', "\"\""))?;
+        } else {
+            out.write_all(field.as_bytes())?;
+        }
+    }
+    out.write_all(b"\r\n")
+}
+
+/// Records longer than this, in bytes, are skipped rather than read into memory.
+pub const DEFAULT_MAX_RECORD_LEN: usize = 64 * 1024;
+
+#[derive(thiserror::Error, Debug)]
+pub enum ReadError {
+    /// The record was skipped: the next one can still be read.
+    #[error("The record starting on line {line} is longer than {max_len} bytes.")]
+    RecordTooLong { line: usize, max_len: usize },
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+/// Reads records one at a time, so that large files never have to fit in memory.
+pub struct Reader<R> {
+    input: R,
+    max_record_len: usize,
+    lines: Lines,
+}
+
+#[derive(Default)]
+struct Lines {
+    read: usize,
+    /// The line the last record read started on.
+    record: usize,
+}
+
+/// Where the parser is within a record.
+#[derive(Default)]
+struct RecordState {
+    max_len: usize,
+    fields: Vec<Vec<u8>>,
+    field: Vec<u8>,
+    /// The length of `field`, which is no longer kept once the record is too long.
+    field_len: usize,
+    in_quotes: bool,
+    /// A quote was read in a quoted field: it is either escaped by the next one, or closes the field.
+    quote_pending: bool,
+    /// Anything but line breaks was read: the line is not blank.
+    started: bool,
+    len: usize,
+    too_long: bool,
+}
+
+impl<R: AsyncBufRead + Unpin> Reader<R> {
+    pub fn new(input: R) -> Self {
+        Self {
+            input,
+            max_record_len: DEFAULT_MAX_RECORD_LEN,
+            lines: Lines::default(),
+        }
+    }
+
+    pub fn with_max_record_len(mut self, max_record_len: usize) -> Self {
+        self.max_record_len = max_record_len;
+        self
+    }
+
+    /// The line the last record read started on, counting from 1.
+    pub fn line(&self) -> usize {
+        self.lines.record
+    }
+
+    /// The next record, skipping blank lines. `None` at the end of the input.
+    pub async fn read_record(&mut self) -> Result<Option<Vec<String>>, ReadError> {
+        if self.lines.read == 0 {
+            let buffer = self.input.fill_buf().await?;
+            if buffer.starts_with("\u{feff}".as_bytes()) {
+                self.input.consume("\u{feff}".len());
+            }
+        }
+        let mut state = RecordState { max_len: self.max_record_len, ..Default::default() };
+        let complete = loop {
+            let buffer = self.input.fill_buf().await?;
+            if buffer.is_empty() {
+                break false;
+            }
+            let mut consumed = 0;
+            let mut complete = false;
+            for &byte in buffer {
+                consumed += 1;
+                if state.step(byte, &mut self.lines) {
+                    complete = true;
+                    break;
+                }
+            }
+            self.input.consume(consumed);
+            if complete {
+                break true;
+            }
+        };
+        if !complete {
+            if !state.started {
+                return Ok(None);
+            }
+            if state.in_quotes && !state.quote_pending {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("The quoted field starting on line {} is never closed.", self.lines.record),
+                )
+                .into());
+            }
+        }
+        if state.too_long {
+            return Err(ReadError::RecordTooLong { line: self.lines.record, max_len: self.max_record_len });
+        }
+        state.fields.push(state.field);
+        state
+            .fields
+            .into_iter()
+            .map(|field| {
+                String::from_utf8(field).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e).into())
+            })
+            .collect::<Result<_, _>>()
+            .map(Some)
+    }
+
+}
+
+impl RecordState {
+    /// Feed one byte to the parser: `true` once it ends the record. Every byte that matters
+    /// to the syntax is ASCII, so UTF-8 can be parsed byte by byte.
+    fn step(&mut self, byte: u8, lines: &mut Lines) -> bool {
+        if byte == b'\n' {
+            lines.read += 1;
+        } else if !self.started && byte != b'\r' {
+            self.started = true;
+            lines.record = lines.read + 1;
+        }
+        if self.quote_pending {
+            self.quote_pending = false;
+            if byte == b'"' {
+                self.push(b'"');
+                return false;
+            }
+            self.in_quotes = false;
+        }
+        match (self.in_quotes, byte) {
+            (true, b'"') => self.quote_pending = true,
+            (true, byte) => self.push(byte),
+            (false, b'"') if self.field_len == 0 => self.in_quotes = true,
+            (false, b',') => {
+                self.count_byte();
+                if !self.too_long {
+                    self.fields.push(std::mem::take(&mut self.field));
+                }
+                self.field_len = 0;
+            }
+            (false, b'\n') => return self.started,
+            (false, b'\r') => {}
+            (false, byte) => self.push(byte),
+        }
+        false
+    }
+
+    fn push(&mut self, byte: u8) {
+        self.count_byte();
+        self.field_len += 1;
+        if !self.too_long {
+            self.field.push(byte);
+        }
+    }
+
+    /// Past the limit, the rest of the record is parsed but dropped.
+    fn count_byte(&mut self) {
+        self.len += 1;
+        if self.len > self.max_len && !self.too_long {
+            self.too_long = true;
+            self.fields = Vec::new();
+            self.field = Vec::new();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn read_all(input: &str) -> Vec<Vec<String>> {
+        let mut reader = Reader::new(input.as_bytes());
+        let mut records = Vec::new();
+        while let Some(record) = reader.read_record().await.unwrap() {
+            records.push(record);
+        }
+        records
+    }
+
+    #[tokio::test]
+    async fn plain_records_are_split_on_commas() {
+        assert_eq!(
+            read_all("email,name\r\nursula@example.com,Ursula\n").await,
+            vec![vec!["email", "name"], vec!["ursula@example.com", "Ursula"]]
+        );
+    }
+
+    #[tokio::test]
+    async fn quoted_fields_can_hold_separators_quotes_and_newlines() {
+        assert_eq!(
+            read_all("\"Le Guin, Ursula\",\"She said \"\"hi\"\"\",\"two\nlines\"\n").await,
+            vec![vec!["Le Guin, Ursula", "She said \"hi\"", "two\nlines"]]
+        );
+    }
+
+    #[tokio::test]
+    async fn blank_lines_and_a_byte_order_mark_are_skipped() {
+        let mut reader = Reader::new("\u{feff}email\n\n\nursula@example.com\n".as_bytes());
+        assert_eq!(reader.read_record().await.unwrap(), Some(vec!["email".to_string()]));
+        assert_eq!(reader.read_record().await.unwrap(), Some(vec!["ursula@example.com".to_string()]));
+        assert_eq!(reader.line(), 4);
+        assert_eq!(reader.read_record().await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn an_unterminated_quote_is_an_error() {
+        let mut reader = Reader::new("\"never closed\n".as_bytes());
+        assert!(reader.read_record().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn written_records_can_be_read_back() {
+        let record = ["plain", "with, comma", "with \"quotes\"", "with\nnewline"];
+        let mut out = Vec::new();
+        write_record(&mut out, record).unwrap();
+        assert_eq!(read_all(std::str::from_utf8(&out).unwrap()).await, vec![record.to_vec()]);
+    }
+
+    #[tokio::test]
+    async fn fields_a_spreadsheet_would_run_as_formulas_are_escaped() {
+        let mut out = Vec::new();
+        write_record(&mut out, ["=HYPERLINK(\"https://evil\")", "+1", "-1", "@SUM(A1)", "\tx", "Ursula"]).unwrap();
+        assert_eq!(
+            read_all(std::str::from_utf8(&out).unwrap()).await,
+            vec![vec!["'=HYPERLINK(\"https://evil\")", "'+1", "'-1", "'@SUM(A1)", "'\tx", "Ursula"]]
+        );
+    }
+
+    #[tokio::test]
+    async fn a_record_over_the_limit_is_skipped_and_the_next_one_read() {
+        let input = "email\n\"a very, very\nlong field\",x\nu@example.io\n";
+        let mut reader = Reader::new(input.as_bytes()).with_max_record_len(16);
+        assert_eq!(reader.read_record().await.unwrap(), Some(vec!["email".to_string()]));
+        assert!(matches!(
+            reader.read_record().await,
+            Err(ReadError::RecordTooLong { line: 2, max_len: 16 })
+        ));
+        assert_eq!(reader.read_record().await.unwrap(), Some(vec!["u@example.io".to_string()]));
+        assert_eq!(reader.line(), 4);
+    }
+}
@@ -0,0 +1,64 @@
+/// The URL-friendly identifier of a published newsletter issue in the web archive.
+#[derive(Debug)]
+pub struct IssueSlug(String);
+
+impl IssueSlug {
+    /// Derive a slug from an issue title: lowercase ASCII letters and digits,
+    /// with every other run of characters collapsed into a single dash.
+    pub fn from_title(title: &str) -> Self {
+        let mut slug = String::with_capacity(title.len());
+        for c in title.chars() {
+            if c.is_ascii_alphanumeric() {
+                slug.push(c.to_ascii_lowercase());
+            } else if !slug.is_empty() && !slug.ends_with('-') {
+                slug.push('-');
+            }
+        }
+        let slug = slug.trim_end_matches('-');
+        if slug.is_empty() {
+            Self("issue".into())
+        } else {
+            Self(slug.into())
+        }
+    }
+
+    /// Disambiguate a slug that is already taken by another issue.
+    pub fn with_suffix(self, suffix: &str) -> Self {
+        Self(format!("{}-{}", self.0, suffix))
+    }
+}
+
+impl AsRef<str> for IssueSlug {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn titles_are_lowercased_and_dashed() {
+        let slug = IssueSlug::from_title("Rust 2024: What's New?");
+        assert_eq!(slug.as_ref(), "rust-2024-what-s-new");
+    }
+
+    #[test]
+    fn leading_and_trailing_separators_are_dropped() {
+        let slug = IssueSlug::from_title("  -- Hello, world! --  ");
+        assert_eq!(slug.as_ref(), "hello-world");
+    }
+
+    #[test]
+    fn titles_without_ascii_letters_get_a_default_slug() {
+        let slug = IssueSlug::from_title("ニュース");
+        assert_eq!(slug.as_ref(), "issue");
+    }
+
+    #[test]
+    fn a_suffix_can_be_appended() {
+        let slug = IssueSlug::from_title("Weekly").with_suffix("1a2b3c4d");
+        assert_eq!(slug.as_ref(), "weekly-1a2b3c4d");
+    }
+}
@@ -1,9 +1,15 @@
+mod issue_slug;
 mod new_subscriber;
 mod subscriber_email;
 mod subscriber_name;
 mod subscriber_id;
+mod subscription_status;
+mod validation_error;
 
+pub use issue_slug::IssueSlug;
 pub use subscriber_name::SubscriberName;
 pub use new_subscriber::NewSubscriber;
-pub use subscriber_email::SubscriberEmail;
-pub use subscriber_id::SubscriberId;
\ No newline at end of file
+pub use subscriber_email::{normalize_email, SubscriberEmail};
+pub use subscriber_id::SubscriberId;
+pub use subscription_status::SubscriptionStatus;
+pub use validation_error::ValidationError;
@@ -1,24 +1,62 @@
 use validator::ValidateEmail;
+use crate::damain::ValidationError;
 
+/// An email address, as the subscriber typed it minus surrounding whitespace,
+/// along with the normalized form addresses are compared in.
 #[derive(Debug)]
-pub struct SubscriberEmail(String);
+pub struct SubscriberEmail {
+    address: String,
+    normalized: String,
+}
 
 impl SubscriberEmail {
-    pub fn parse(email: String) -> Result<Self, String> {
-        if email.validate_email() {
-            Ok(Self(email))
-        } else {
-            Err(format!("Invalid email: {}", email))
+    pub fn parse(email: String) -> Result<Self, ValidationError> {
+        let address = email.trim().to_owned();
+        if !address.validate_email() {
+            return Err(ValidationError::InvalidEmail);
         }
+        let normalized = normalize_email(&address).ok_or(ValidationError::InvalidEmail)?;
+        Ok(Self { address, normalized })
+    }
+
+    /// The address two subscribers must not share: see [`normalize_email`].
+    pub fn normalized(&self) -> &str {
+        &self.normalized
+    }
+
+    /// The local part of the normalized address.
+    pub fn local_part(&self) -> &str {
+        self.split().0
+    }
+
+    /// The domain of the normalized address, in ASCII.
+    pub fn domain(&self) -> &str {
+        self.split().1
+    }
+
+    fn split(&self) -> (&str, &str) {
+        self.normalized
+            .rsplit_once('@')
+            .expect("A normalized email always has an `@`.")
     }
 }
 
 impl AsRef<str> for SubscriberEmail {
     fn as_ref(&self) -> &str {
-        &self.0
+        &self.address
     }
 }
 
+/// The form in which two spellings of the same address are equal: trimmed, the domain lowercased
+/// and IDNA-encoded (`Bücher.de` becomes `xn--bcher-kva.de`), and the local part lowercased too.
+/// Local parts are case-sensitive in theory, but no mailbox provider treats them so in practice.
+/// Returns `None` if there is no `@` or the domain is not a valid domain name.
+pub fn normalize_email(email: &str) -> Option<String> {
+    let (local, domain) = email.trim().rsplit_once('@')?;
+    let domain = idna::domain_to_ascii(domain).ok()?;
+    Some(format!("{}@{}", local.to_lowercase(), domain))
+}
+
 #[cfg(test)]
 mod tests {
     use claim::{assert_err, assert_ok};
@@ -45,6 +83,26 @@ mod tests {
         assert_err!( SubscriberEmail::parse(email));
     }
 
+    #[test]
+    fn surrounding_whitespace_is_trimmed() {
+        let email = SubscriberEmail::parse("  ursula@example.com\n".to_string()).unwrap();
+        assert_eq!(email.as_ref(), "ursula@example.com");
+    }
+
+    #[test]
+    fn the_original_spelling_is_kept_for_display() {
+        let email = SubscriberEmail::parse("Ursula@Example.COM".to_string()).unwrap();
+        assert_eq!(email.as_ref(), "Ursula@Example.COM");
+        assert_eq!(email.normalized(), "ursula@example.com");
+    }
+
+    #[test]
+    fn internationalized_domains_are_normalized_to_punycode() {
+        let email = SubscriberEmail::parse("leser@Bücher.de".to_string()).unwrap();
+        assert_eq!(email.as_ref(), "leser@Bücher.de");
+        assert_eq!(email.normalized(), "leser@xn--bcher-kva.de");
+    }
+
     fn safe_email_strategy() -> impl Strategy<Value = String> {
         any::<u8>().prop_map(|_| SafeEmail().fake::<String>())
     }
@@ -1,5 +1,8 @@
 use unicode_segmentation::UnicodeSegmentation;
+use crate::damain::ValidationError;
 
+const MAX_LENGTH: usize = 256;
+const FORBIDDEN_CHARACTERS: [char; 9] = ['/', '(', ')', '"', '<', '>', '\\', '{', '}'];
 
 #[derive(Debug)]
 pub struct SubscriberName(String);
@@ -13,18 +16,18 @@ impl SubscriberName {
     pub fn inner_mut(&mut self) -> &mut String {
         &mut self.0
     }
-    pub fn parse(s: String) -> Result<Self, String> {
-        let is_empty = s.trim().is_empty();
-        let is_too_long = s.graphemes(true).count() > 256;
-        let forbidden_characters = ['/', '(', ')', '"', '<', '>', '\\', '{', '}'];
-        let contains_forbidden_characters = forbidden_characters.iter().any(|c| s.contains(*c));
-
-        if is_empty || is_too_long || contains_forbidden_characters {
-            Err(format!("Invalid subscriber name {}", s))
+    pub fn parse(s: String) -> Result<Self, ValidationError> {
+        if s.trim().is_empty() {
+            return Err(ValidationError::EmptyName);
+        }
+        let length = s.graphemes(true).count();
+        if length > MAX_LENGTH {
+            return Err(ValidationError::TooLong { max: MAX_LENGTH, actual: length });
         }
-        else {
-            Ok(Self(s))
+        if let Some(c) = s.chars().find(|c| FORBIDDEN_CHARACTERS.contains(c)) {
+            return Err(ValidationError::ForbiddenCharacter(c));
         }
+        Ok(Self(s))
     }
 }
 
@@ -37,7 +40,7 @@ impl AsRef<str> for SubscriberName {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use claim::{assert_err, assert_ok};
+    use claim::assert_ok;
 
 
     #[test]
@@ -48,24 +51,30 @@ mod tests {
     #[test]
     fn a_name_longer_than_256_graphemes_is_rejected() {
         let name = "a".repeat(257);
-        assert_err!(SubscriberName::parse(name));
+        assert_eq!(
+            SubscriberName::parse(name).unwrap_err(),
+            ValidationError::TooLong { max: 256, actual: 257 }
+        );
     }
     #[test]
     fn whitespace_only_names_are_rejected() {
         let name = " ".to_string();
-        assert_err!(SubscriberName::parse(name));
+        assert_eq!(SubscriberName::parse(name).unwrap_err(), ValidationError::EmptyName);
     }
 
     #[test]
     fn empty_string_is_rejected() {
         let name = "".to_string();
-        assert_err!(SubscriberName::parse(name));
+        assert_eq!(SubscriberName::parse(name).unwrap_err(), ValidationError::EmptyName);
     }
     #[test]
     fn names_containing_an_invalid_character_are_rejected() {
-        for name in &['/', '(', ')', '"', '<', '>', '\\', '{', '}'] {
-            let name = name.to_string();
-            assert_err!(SubscriberName::parse(name));
+        for c in &['/', '(', ')', '"', '<', '>', '\\', '{', '}'] {
+            let name = format!("Ursula {}", c);
+            assert_eq!(
+                SubscriberName::parse(name).unwrap_err(),
+                ValidationError::ForbiddenCharacter(*c)
+            );
         }
     }
     #[test]
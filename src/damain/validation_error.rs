@@ -0,0 +1,45 @@
+/// Why a piece of user input was rejected by one of the domain parsers,
+/// or an email address by the deliverability checks.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ValidationError {
+    #[error("must not be empty")]
+    EmptyName,
+    #[error("must be at most {max} characters long, got {actual}")]
+    TooLong { max: usize, actual: usize },
+    #[error("must not contain the character {0:?}")]
+    ForbiddenCharacter(char),
+    #[error("is not a valid email address")]
+    InvalidEmail,
+    #[error("is probably mistyped (did you mean {suggestion}?)")]
+    PossibleTypo { suggestion: String },
+    #[error("must not be a disposable address")]
+    DisposableDomain,
+    #[error("must reach a person, not a role like postmaster or noreply")]
+    RoleAddress,
+    #[error("is on a domain that does not receive email")]
+    NoMailServer,
+}
+
+impl ValidationError {
+    /// A stable, machine-readable identifier for the error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ValidationError::EmptyName => "empty_name",
+            ValidationError::TooLong { .. } => "too_long",
+            ValidationError::ForbiddenCharacter(_) => "forbidden_character",
+            ValidationError::InvalidEmail => "invalid_email",
+            ValidationError::PossibleTypo { .. } => "possible_typo",
+            ValidationError::DisposableDomain => "disposable_domain",
+            ValidationError::RoleAddress => "role_address",
+            ValidationError::NoMailServer => "no_mail_server",
+        }
+    }
+
+    /// What the user probably meant to type, if we can tell.
+    pub fn suggestion(&self) -> Option<&str> {
+        match self {
+            ValidationError::PossibleTypo { suggestion } => Some(suggestion),
+            _ => None,
+        }
+    }
+}
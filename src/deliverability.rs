@@ -0,0 +1,293 @@
+//! Checks that an address is worth sending a confirmation email to, beyond its syntax.
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+use anyhow::Context;
+use async_trait::async_trait;
+use hickory_resolver::TokioResolver;
+use crate::damain::{SubscriberEmail, ValidationError};
+
+/// Local parts that reach a mailbox shared by a team or read by a machine, rather than a person.
+const ROLE_LOCAL_PARTS: &[&str] = &[
+    "abuse",
+    "do-not-reply",
+    "donotreply",
+    "hostmaster",
+    "mailer-daemon",
+    "no-reply",
+    "nobody",
+    "noreply",
+    "postmaster",
+    "root",
+    "webmaster",
+];
+
+/// Common misspellings of the domains of the big mailbox providers, with the domain that was meant.
+const DOMAIN_TYPOS: &[(&str, &str)] = &[
+    ("gamil.com", "gmail.com"),
+    ("gmai.com", "gmail.com"),
+    ("gmail.co", "gmail.com"),
+    ("gmail.con", "gmail.com"),
+    ("gmaill.com", "gmail.com"),
+    ("gmal.com", "gmail.com"),
+    ("gmial.com", "gmail.com"),
+    ("gnail.com", "gmail.com"),
+    ("hotmai.com", "hotmail.com"),
+    ("hotmail.co", "hotmail.com"),
+    ("hotmail.con", "hotmail.com"),
+    ("hotmal.com", "hotmail.com"),
+    ("hotmial.com", "hotmail.com"),
+    ("iclod.com", "icloud.com"),
+    ("icloud.co", "icloud.com"),
+    ("outlok.com", "outlook.com"),
+    ("outlook.co", "outlook.com"),
+    ("outlook.con", "outlook.com"),
+    ("yaho.com", "yahoo.com"),
+    ("yahoo.co", "yahoo.com"),
+    ("yahoo.con", "yahoo.com"),
+    ("yahooo.com", "yahoo.com"),
+];
+
+/// Finds out whether a domain receives email.
+#[async_trait]
+pub trait MailServerResolver: Send + Sync {
+    /// Whether `domain`, in ASCII, has a server that accepts email for it.
+    async fn has_mail_server(&self, domain: &str) -> Result<bool, anyhow::Error>;
+}
+
+/// Looks the MX records of the domain up in the DNS.
+pub struct DnsResolver {
+    resolver: TokioResolver,
+    timeout: Duration,
+}
+
+impl DnsResolver {
+    /// A resolver using the name servers of the system, e.g. from `/etc/resolv.conf`.
+    pub fn from_system_conf(timeout: Duration) -> Result<Self, anyhow::Error> {
+        let resolver = TokioResolver::builder_tokio()
+            .context("Failed to read the DNS configuration of the system.")?
+            .build();
+        Ok(Self { resolver, timeout })
+    }
+
+    async fn lookup(&self, domain: &str) -> Result<bool, anyhow::Error> {
+        // The trailing dot makes the name fully qualified, so that search domains are not tried.
+        let name = format!("{}.", domain);
+        match self.resolver.mx_lookup(name.as_str()).await {
+            // A "null MX", whose exchange is the root, says the domain accepts no email (RFC 7505).
+            Ok(records) => Ok(records.iter().any(|mx| !mx.exchange().is_root())),
+            // Without MX records, email goes to the address of the domain itself (RFC 5321, section 5.1).
+            Err(e) if e.is_no_records_found() => match self.resolver.lookup_ip(name.as_str()).await {
+                Ok(addresses) => Ok(addresses.iter().next().is_some()),
+                Err(e) if e.is_no_records_found() => Ok(false),
+                Err(e) => Err(e.into()),
+            },
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+#[async_trait]
+impl MailServerResolver for DnsResolver {
+    #[tracing::instrument(name = "Look the mail servers of a domain up", skip(self))]
+    async fn has_mail_server(&self, domain: &str) -> Result<bool, anyhow::Error> {
+        tokio::time::timeout(self.timeout, self.lookup(domain))
+            .await
+            .context("The DNS lookup timed out.")?
+    }
+}
+
+/// Pretends every domain receives email, except those it is given. For local development and tests.
+pub struct StubResolver {
+    domains_without_mail_server: HashSet<String>,
+}
+
+impl StubResolver {
+    pub fn new(domains_without_mail_server: impl IntoIterator<Item = String>) -> Self {
+        Self {
+            domains_without_mail_server: domains_without_mail_server.into_iter().filter_map(normalize_domain).collect(),
+        }
+    }
+}
+
+#[async_trait]
+impl MailServerResolver for StubResolver {
+    async fn has_mail_server(&self, domain: &str) -> Result<bool, anyhow::Error> {
+        Ok(!self.domains_without_mail_server.contains(domain))
+    }
+}
+
+/// The checks applied to the addresses of new subscribers. By default, none is.
+#[derive(Clone, Default)]
+pub struct DeliverabilityChecker {
+    suggest_typo_corrections: bool,
+    disposable_domains: HashSet<String>,
+    reject_role_addresses: bool,
+    mail_servers: Option<Arc<dyn MailServerResolver>>,
+}
+
+impl DeliverabilityChecker {
+    /// Reject the common misspellings of the big providers' domains, suggesting the correction.
+    pub fn with_typo_suggestions(mut self) -> Self {
+        self.suggest_typo_corrections = true;
+        self
+    }
+
+    /// Reject the addresses of `domains` and of their subdomains.
+    pub fn with_disposable_domains(mut self, domains: impl IntoIterator<Item = String>) -> Self {
+        self.disposable_domains = domains.into_iter().filter_map(normalize_domain).collect();
+        self
+    }
+
+    /// Reject addresses like `postmaster@` or `noreply@`, which do not reach a person.
+    pub fn with_role_address_rejection(mut self) -> Self {
+        self.reject_role_addresses = true;
+        self
+    }
+
+    /// Reject the addresses of domains that `resolver` says receive no email.
+    pub fn with_mail_server_check(mut self, resolver: Arc<dyn MailServerResolver>) -> Self {
+        self.mail_servers = Some(resolver);
+        self
+    }
+
+    /// Run the enabled checks, cheapest first. A failed mail server lookup lets the address through:
+    /// the DNS being unreachable is no reason to turn subscribers away.
+    #[tracing::instrument(name = "Check the deliverability of an email address", skip_all)]
+    pub async fn check(&self, email: &SubscriberEmail) -> Result<(), ValidationError> {
+        let domain = email.domain();
+        if self.suggest_typo_corrections
+            && let Some(suggestion) = suggest_correction(email)
+        {
+            return Err(ValidationError::PossibleTypo { suggestion });
+        }
+        if self.is_disposable(domain) {
+            return Err(ValidationError::DisposableDomain);
+        }
+        if self.reject_role_addresses && is_role_address(email) {
+            return Err(ValidationError::RoleAddress);
+        }
+        if let Some(resolver) = &self.mail_servers {
+            match resolver.has_mail_server(domain).await {
+                Ok(true) => {}
+                Ok(false) => return Err(ValidationError::NoMailServer),
+                Err(e) => tracing::warn!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    "Failed to look the mail servers of {} up, accepting the address.", domain
+                ),
+            }
+        }
+        Ok(())
+    }
+
+    fn is_disposable(&self, domain: &str) -> bool {
+        // `mailinator.com` covers `eu.mailinator.com` too.
+        std::iter::successors(Some(domain), |d| d.split_once('.').map(|(_, parent)| parent))
+            .any(|d| self.disposable_domains.contains(d))
+    }
+}
+
+/// Read a list of domains, one per line. Blank lines and lines starting with `#` are ignored.
+pub fn parse_domain_list(contents: &str) -> Vec<String> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_owned)
+        .collect()
+}
+
+fn normalize_domain(domain: String) -> Option<String> {
+    match idna::domain_to_ascii(domain.trim()) {
+        Ok(domain) => Some(domain),
+        Err(_) => {
+            tracing::warn!("Ignoring {:?}, which is not a valid domain.", domain);
+            None
+        }
+    }
+}
+
+fn suggest_correction(email: &SubscriberEmail) -> Option<String> {
+    let (_, meant) = DOMAIN_TYPOS.iter().find(|(typo, _)| *typo == email.domain())?;
+    // Keep the local part as the user typed it.
+    let (local_part, _) = email.as_ref().rsplit_once('@')?;
+    Some(format!("{}@{}", local_part, meant))
+}
+
+fn is_role_address(email: &SubscriberEmail) -> bool {
+    // `noreply+news@` is as much of a role as `noreply@`.
+    let local_part = email.local_part();
+    let local_part = local_part.split_once('+').map_or(local_part, |(base, _)| base);
+    ROLE_LOCAL_PARTS.contains(&local_part)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use async_trait::async_trait;
+    use claim::{assert_err, assert_ok};
+    use crate::damain::{SubscriberEmail, ValidationError};
+    use super::{parse_domain_list, DeliverabilityChecker, MailServerResolver, StubResolver};
+
+    struct UnreachableDns;
+
+    #[async_trait]
+    impl MailServerResolver for UnreachableDns {
+        async fn has_mail_server(&self, _domain: &str) -> Result<bool, anyhow::Error> {
+            Err(anyhow::anyhow!("No name server answered."))
+        }
+    }
+
+    fn email(address: &str) -> SubscriberEmail {
+        SubscriberEmail::parse(address.to_string()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn no_check_is_enabled_by_default() {
+        let checker = DeliverabilityChecker::default();
+        assert_ok!(checker.check(&email("postmaster@gmial.com")).await);
+    }
+
+    #[tokio::test]
+    async fn a_mistyped_domain_comes_with_a_suggestion_that_keeps_the_local_part() {
+        let checker = DeliverabilityChecker::default().with_typo_suggestions();
+        assert_eq!(
+            checker.check(&email("Ursula@GMIAL.com")).await,
+            Err(ValidationError::PossibleTypo { suggestion: "Ursula@gmail.com".into() })
+        );
+        assert_ok!(checker.check(&email("ursula@gmail.com")).await);
+    }
+
+    #[tokio::test]
+    async fn disposable_domains_and_their_subdomains_are_rejected() {
+        let list = parse_domain_list("# Disposable domains\n\nmailinator.com\n  Yopmail.com \n");
+        let checker = DeliverabilityChecker::default().with_disposable_domains(list);
+        assert_eq!(checker.check(&email("a@mailinator.com")).await, Err(ValidationError::DisposableDomain));
+        assert_eq!(checker.check(&email("a@eu.mailinator.com")).await, Err(ValidationError::DisposableDomain));
+        assert_eq!(checker.check(&email("a@yopmail.com")).await, Err(ValidationError::DisposableDomain));
+        assert_ok!(checker.check(&email("a@notmailinator.com")).await);
+    }
+
+    #[tokio::test]
+    async fn role_addresses_are_rejected_with_or_without_a_tag() {
+        let checker = DeliverabilityChecker::default().with_role_address_rejection();
+        assert_eq!(checker.check(&email("PostMaster@example.com")).await, Err(ValidationError::RoleAddress));
+        assert_eq!(checker.check(&email("noreply+news@example.com")).await, Err(ValidationError::RoleAddress));
+        assert_ok!(checker.check(&email("rooted@example.com")).await);
+    }
+
+    #[tokio::test]
+    async fn domains_without_a_mail_server_are_rejected() {
+        let resolver = StubResolver::new(["No-Mail.example.com".to_string()]);
+        let checker = DeliverabilityChecker::default().with_mail_server_check(Arc::new(resolver));
+        assert_err!(checker.check(&email("ursula@no-mail.example.com")).await);
+        assert_ok!(checker.check(&email("ursula@example.com")).await);
+    }
+
+    #[tokio::test]
+    async fn addresses_are_accepted_when_the_lookup_fails() {
+        let checker = DeliverabilityChecker::default().with_mail_server_check(Arc::new(UnreachableDns));
+        assert_ok!(checker.check(&email("ursula@example.com")).await);
+    }
+}
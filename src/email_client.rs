@@ -1,12 +1,30 @@
 use std::time::Duration;
+use opentelemetry::global;
+use opentelemetry_http::HeaderInjector;
 use reqwest::Client;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
 use crate::damain::SubscriberEmail;
+use crate::request_id::{RequestId, X_REQUEST_ID};
+use crate::suppressions::{self, EmailHasher};
 
 pub struct EmailClient {
     sender: SubscriberEmail,
     http_client: Client,
     base_url: String,
+    suppression_list: Option<(PgPool, EmailHasher)>,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum SendEmailError {
+    #[error("The recipient is on the suppression list.")]
+    Suppressed,
+    #[error("Failed to check whether the recipient is on the suppression list.")]
+    SuppressionCheck(#[source] sqlx::Error),
+    #[error("The email API rejected the email, or could not be reached.")]
+    Request(#[from] reqwest::Error),
 }
 
 impl EmailClient {
@@ -18,9 +36,57 @@ impl EmailClient {
                 .build()
                 .unwrap(),
             base_url,
+            suppression_list: None,
+        }
+    }
+
+    /// Refuse to send emails to the addresses on the suppression list stored in `db_pool`.
+    /// Every client of the application is built with it: only tests of the client itself go without.
+    pub fn with_suppression_list(mut self, db_pool: PgPool, email_hasher: EmailHasher) -> Self {
+        self.suppression_list = Some((db_pool, email_hasher));
+        self
+    }
+
+    /// Send an email through the Postmark API, unless the recipient is suppressed.
+    /// Returns the `MessageID` Postmark assigned to the email, if the response carried one.
+    pub async fn send_email(
+        &self,
+        recipient: SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+    ) -> Result<Option<String>, SendEmailError> {
+        if let Some((db_pool, email_hasher)) = &self.suppression_list {
+            let suppressed = suppressions::is_suppressed(db_pool, email_hasher, recipient.as_ref())
+                .await
+                .map_err(SendEmailError::SuppressionCheck)?;
+            if suppressed {
+                tracing::info!("Not sending an email to a suppressed address.");
+                return Err(SendEmailError::Suppressed);
+            }
         }
+        Ok(self.post_email(recipient, subject, html_content, text_content).await?)
     }
-    pub async fn send_email(&self, recipient: SubscriberEmail, subject: &str, html_content: &str, text_content: &str) -> Result<(), reqwest::Error>{
+
+    /// POST the email to the Postmark API.
+    ///
+    /// When called while serving a request, its id is forwarded to Postmark in the `X-Request-Id` header.
+    /// The call is traced as an OpenTelemetry client span, whose context is forwarded in `traceparent`.
+    #[tracing::instrument(
+        name = "Call the email API",
+        skip_all,
+        fields(
+            otel.name = "POST",
+            otel.kind = "client",
+            otel.status_code = tracing::field::Empty,
+            http.request.method = "POST",
+            url.full = tracing::field::Empty,
+            server.address = tracing::field::Empty,
+            http.response.status_code = tracing::field::Empty,
+            error.type = tracing::field::Empty,
+        )
+    )]
+    async fn post_email(&self, recipient: SubscriberEmail, subject: &str, html_content: &str, text_content: &str) -> Result<Option<String>, reqwest::Error>{
         let address = format!("{}/email", self.base_url);
         let body = SendEmailRequest{
             from: self.sender.as_ref(),
@@ -29,17 +95,68 @@ impl EmailClient {
             html_body: html_content,
             text_body: text_content,
         };
-        let response = self.http_client
-            .post(address)
-            .json(&body)
-            .send()
-            .await?;
-        
-        response.error_for_status()?;
+        let mut request = self.http_client.post(&address).json(&body);
+        if let Some(request_id) = RequestId::current() {
+            request = request.header(X_REQUEST_ID.as_str(), request_id.as_ref());
+        }
+        let mut request = request.build()?;
+        let span = Span::current();
+        span.record("url.full", &address);
+        if let Some(host) = request.url().host_str() {
+            span.record("server.address", host);
+        }
+        global::get_text_map_propagator(|propagator| {
+            propagator.inject_context(&span.context(), &mut HeaderInjector(request.headers_mut()))
+        });
+
+        let response = match self.http_client.execute(request).await {
+            Ok(response) => response,
+            Err(e) => {
+                span.record("error.type", error_type(&e));
+                span.record("otel.status_code", "ERROR");
+                return Err(e);
+            }
+        };
+        span.record("http.response.status_code", response.status().as_u16());
+        let response = match response.error_for_status() {
+            Ok(response) => {
+                span.record("otel.status_code", "OK");
+                response
+            }
+            Err(e) => {
+                span.record("error.type", error_type(&e));
+                span.record("otel.status_code", "ERROR");
+                return Err(e);
+            }
+        };
+        let message_id = response
+            .json::<SendEmailResponse>()
+            .await
+            .ok()
+            .and_then(|r| r.message_id);
+        Ok(message_id)
+    }
+
+    /// Check that the email API can be reached: any answer but a server error will do.
+    #[tracing::instrument(name = "Probe the email API", skip_all)]
+    pub async fn probe(&self) -> Result<(), reqwest::Error> {
+        let response = self.http_client.get(&self.base_url).send().await?;
+        if response.status().is_server_error() {
+            response.error_for_status()?;
+        }
         Ok(())
     }
 }
 
+/// The `error.type` of a failed call, following the HTTP semantic conventions.
+fn error_type(e: &reqwest::Error) -> String {
+    match e.status() {
+        Some(status) => status.as_str().to_owned(),
+        None if e.is_timeout() => "timeout".into(),
+        None if e.is_connect() => "connect".into(),
+        None => "_OTHER".into(),
+    }
+}
 
 #[derive(Serialize)]
 #[serde(rename_all = "PascalCase")]
@@ -51,6 +168,12 @@ struct SendEmailRequest<'a> {
     text_body: &'a str,
 }
 
+#[derive(Deserialize)]
+struct SendEmailResponse {
+    #[serde(rename = "MessageID")]
+    message_id: Option<String>,
+}
+
 
 #[cfg(test)]
 mod tests {
@@ -149,4 +272,25 @@ mod tests {
         
         assert_err!(response);
     }
+
+    #[tokio::test]
+    async fn send_email_returns_the_message_id_assigned_by_the_server() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "To": "receiver@example.com",
+                "ErrorCode": 0,
+                "Message": "OK",
+                "MessageID": "b7bc2f4a-e38e-4336-af7d-e6c392c2f817",
+            })))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let message_id = email_client.send_email(email(), &subject(), &content(), &content()).await.unwrap();
+
+        assert_eq!(message_id.as_deref(), Some("b7bc2f4a-e38e-4336-af7d-e6c392c2f817"));
+    }
 }
\ No newline at end of file
@@ -0,0 +1,240 @@
+//! Bulk import of the subscribers of another provider, shared by the admin API and the command line.
+use std::collections::HashSet;
+use anyhow::Context;
+use sqlx::PgPool;
+use tokio::io::AsyncBufRead;
+use uuid::Uuid;
+use crate::confirmation_email_worker::queue_confirmation_emails;
+use crate::csv;
+use crate::damain::{NewSubscriber, SubscriptionStatus};
+use crate::routes::{error_chain_fmt, generate_subscription_token, parse_new_subscriber, FieldError};
+use crate::suppressions::EmailHasher;
+
+/// Rows are inserted this many at a time.
+const BATCH_SIZE: usize = 500;
+/// Past this many, invalid rows are counted but not detailed in the report.
+const MAX_REPORTED_ERRORS: usize = 1000;
+
+/// What the imported subscribers become.
+#[derive(Debug, Clone)]
+pub enum ImportMode {
+    /// They already gave their consent to the previous provider: `consent_source` records how.
+    Confirmed { consent_source: String },
+    /// They are sent a confirmation email, as if they had just signed up, by the background worker.
+    PendingConfirmation,
+}
+
+impl ImportMode {
+    pub fn new(status: SubscriptionStatus, consent_source: Option<String>) -> Result<Self, ImportError> {
+        match status {
+            SubscriptionStatus::PendingConfirmation => Ok(ImportMode::PendingConfirmation),
+            SubscriptionStatus::Confirmed => match consent_source.map(|s| s.trim().to_owned()) {
+                Some(consent_source) if !consent_source.is_empty() => {
+                    Ok(ImportMode::Confirmed { consent_source })
+                }
+                _ => Err(ImportError::MissingConsentSource),
+            },
+        }
+    }
+
+    fn status(&self) -> SubscriptionStatus {
+        match self {
+            ImportMode::Confirmed { .. } => SubscriptionStatus::Confirmed,
+            ImportMode::PendingConfirmation => SubscriptionStatus::PendingConfirmation,
+        }
+    }
+
+    fn consent_source(&self) -> Option<&str> {
+        match self {
+            ImportMode::Confirmed { consent_source } => Some(consent_source),
+            ImportMode::PendingConfirmation => None,
+        }
+    }
+}
+
+#[derive(Debug, Default, serde::Serialize)]
+pub struct ImportReport {
+    pub imported: usize,
+    pub already_subscribed: usize,
+    /// Skipped because the address is on the suppression list, e.g. it was erased.
+    pub suppressed: usize,
+    pub invalid: usize,
+    /// Left to the background worker to send.
+    pub confirmation_emails_queued: usize,
+    /// The first `MAX_REPORTED_ERRORS` invalid rows.
+    pub errors: Vec<RowError>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct RowError {
+    /// The line of the file the row starts on, counting from 1.
+    pub line: usize,
+    pub errors: Vec<FieldError>,
+}
+
+#[derive(thiserror::Error)]
+pub enum ImportError {
+    #[error("A consent source is required to import confirmed subscribers.")]
+    MissingConsentSource,
+    #[error("{0}")]
+    InvalidFile(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for ImportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+/// Import a CSV file with `email` and `name` columns, row by row, so that memory stays flat.
+/// Invalid rows are reported and skipped, emails that are already subscribed are left untouched,
+/// and suppressed emails are never imported.
+#[tracing::instrument(name = "Import subscribers", skip_all)]
+pub async fn import_subscribers(
+    db_pool: &PgPool,
+    email_hasher: &EmailHasher,
+    mode: &ImportMode,
+    input: impl AsyncBufRead + Unpin,
+) -> Result<ImportReport, ImportError> {
+    let mut reader = csv::Reader::new(input);
+    let header = reader
+        .read_record()
+        .await
+        .map_err(|e| ImportError::InvalidFile(e.to_string()))?
+        .ok_or_else(|| ImportError::InvalidFile("The file is empty.".into()))?;
+    let column = |name: &str| {
+        header
+            .iter()
+            .position(|h| h.trim().eq_ignore_ascii_case(name))
+            .ok_or_else(|| ImportError::InvalidFile(format!("The file has no `{}` column.", name)))
+    };
+    let email_column = column("email")?;
+    let name_column = column("name")?;
+
+    let mut report = ImportReport::default();
+    let mut batch = Vec::with_capacity(BATCH_SIZE);
+    loop {
+        let parsed = match reader.read_record().await {
+            Ok(Some(mut record)) => {
+                let mut field = |i: usize| record.get_mut(i).map(std::mem::take).unwrap_or_default();
+                let (email, name) = (field(email_column), field(name_column));
+                parse_new_subscriber(email, name)
+            }
+            Ok(None) => break,
+            // The reader skipped the row: it can go on with the next one.
+            Err(e @ csv::ReadError::RecordTooLong { .. }) => Err(vec![FieldError {
+                field: "row",
+                code: "too_long",
+                message: e.to_string(),
+                suggestion: None,
+            }]),
+            Err(e) => return Err(ImportError::InvalidFile(e.to_string())),
+        };
+        match parsed {
+            Ok(new_subscriber) => batch.push(new_subscriber),
+            Err(errors) => {
+                report.invalid += 1;
+                if report.errors.len() < MAX_REPORTED_ERRORS {
+                    report.errors.push(RowError { line: reader.line(), errors });
+                }
+            }
+        }
+        if batch.len() == BATCH_SIZE {
+            import_batch(db_pool, email_hasher, mode, std::mem::take(&mut batch), &mut report).await?;
+        }
+    }
+    if !batch.is_empty() {
+        import_batch(db_pool, email_hasher, mode, batch, &mut report).await?;
+    }
+    Ok(report)
+}
+
+async fn import_batch(
+    db_pool: &PgPool,
+    email_hasher: &EmailHasher,
+    mode: &ImportMode,
+    batch: Vec<NewSubscriber>,
+    report: &mut ImportReport,
+) -> Result<(), anyhow::Error> {
+    let mut transaction = db_pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let hashes: Vec<String> = batch.iter().map(|s| email_hasher.hash(s.email.as_ref())).collect();
+    let suppressed: HashSet<String> = sqlx::query_scalar!(
+        "SELECT email_hash FROM suppressions WHERE email_hash = ANY($1) AND lifted_at IS NULL",
+        &hashes,
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to look the imported subscribers up in the suppression list.")?
+    .into_iter()
+    .collect();
+    let batch_size = batch.len();
+    let batch: Vec<NewSubscriber> = batch
+        .into_iter()
+        .zip(hashes)
+        .filter_map(|(new_subscriber, hash)| (!suppressed.contains(&hash)).then_some(new_subscriber))
+        .collect();
+    report.suppressed += batch_size - batch.len();
+    let ids: Vec<Uuid> = batch.iter().map(|_| Uuid::new_v4()).collect();
+    let emails: Vec<String> = batch.iter().map(|s| s.email.as_ref().to_owned()).collect();
+    let normalized: Vec<String> = batch.iter().map(|s| s.email.normalized().to_owned()).collect();
+    let names: Vec<String> = batch.iter().map(|s| s.name.as_ref().to_owned()).collect();
+    let inserted: HashSet<Uuid> = sqlx::query_scalar!(
+        r#"
+        INSERT INTO subscriptions (id, email, email_normalized, name, subscribed_at, status, consent_source, confirmed_at)
+        SELECT id, email, email_normalized, name, now(), $4, $5, CASE WHEN $4 = 'confirmed' THEN now() END
+        FROM UNNEST($1::uuid[], $2::text[], $6::text[], $3::text[]) AS imported(id, email, email_normalized, name)
+        ON CONFLICT (email_normalized) DO NOTHING
+        RETURNING id
+        "#,
+        &ids,
+        &emails,
+        &names,
+        mode.status().as_str(),
+        mode.consent_source(),
+        &normalized,
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to insert a batch of imported subscribers.")?
+    .into_iter()
+    .collect();
+    report.imported += inserted.len();
+    report.already_subscribed += batch.len() - inserted.len();
+
+    if let ImportMode::Confirmed { .. } = mode {
+        transaction
+            .commit()
+            .await
+            .context("Failed to commit SQL transaction to import subscribers.")?;
+        return Ok(());
+    }
+
+    let subscriber_ids: Vec<Uuid> = ids.into_iter().filter(|id| inserted.contains(id)).collect();
+    let tokens: Vec<String> = subscriber_ids.iter().map(|_| generate_subscription_token()).collect();
+    sqlx::query!(
+        r#"
+        INSERT INTO subscription_tokens (subscription_token, subscriber_id)
+        SELECT * FROM UNNEST($1::text[], $2::uuid[])
+        "#,
+        &tokens,
+        &subscriber_ids,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to store the confirmation tokens of imported subscribers.")?;
+    // Sending thousands of emails would hold the request open: the worker sends them instead.
+    queue_confirmation_emails(&mut transaction, &tokens)
+        .await
+        .context("Failed to queue the confirmation emails of imported subscribers.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to import subscribers.")?;
+    report.confirmation_emails_queued += tokens.len();
+    Ok(())
+}
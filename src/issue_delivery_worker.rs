@@ -0,0 +1,276 @@
+use std::time::Duration;
+use sqlx::{PgPool, Postgres, Transaction};
+use tracing::{field::display, Span};
+use uuid::Uuid;
+use crate::configuration::Settings;
+use crate::damain::{normalize_email, SubscriberEmail};
+use crate::email_client::{EmailClient, SendEmailError};
+use crate::metrics::{EmailKind, Metrics};
+use crate::shutdown::ShutdownSignal;
+use crate::startup::get_connection_pool;
+use crate::tracking::LinkTracker;
+
+/// How many times we try to deliver an issue to a subscriber before giving up.
+const MAX_DELIVERY_ATTEMPTS: i32 = 5;
+
+pub enum ExecutionOutcome {
+    TaskCompleted,
+    EmptyQueue,
+}
+
+struct Task {
+    newsletter_issue_id: Uuid,
+    subscriber_id: Uuid,
+    subscriber_email: String,
+    attempts: i32,
+    tracking_opt_out: bool,
+}
+
+struct Issue {
+    title: String,
+    text_content: String,
+    html_content: String,
+}
+
+#[tracing::instrument(
+    skip_all,
+    fields(
+        newsletter_issue_id=tracing::field::Empty,
+        subscriber_email=tracing::field::Empty
+    ),
+    err
+)]
+pub async fn try_execute_task(
+    db_pool: &PgPool,
+    email_client: &EmailClient,
+    link_tracker: &LinkTracker,
+    metrics: &Metrics,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let Some((mut transaction, task)) = dequeue_task(db_pool).await? else {
+        return Ok(ExecutionOutcome::EmptyQueue);
+    };
+    Span::current()
+        .record("newsletter_issue_id", display(task.newsletter_issue_id))
+        .record("subscriber_email", display(&task.subscriber_email));
+
+    let email = match SubscriberEmail::parse(task.subscriber_email.clone()) {
+        Ok(email) => email,
+        // Retrying would not make the address valid: the delivery fails right away.
+        Err(e) => {
+            let e = anyhow::anyhow!(e)
+                .context("Skipping a confirmed subscriber. Their stored contact details are invalid");
+            metrics.email_failed(EmailKind::Issue);
+            tracing::error!(error.cause_chain = ?e, error.message = %e, "Failed to deliver issue to a confirmed subscriber.");
+            mark_failed(&mut transaction, &task, &format!("{:#}", e)).await?;
+            transaction.commit().await?;
+            return Ok(ExecutionOutcome::TaskCompleted);
+        }
+    };
+    let issue = get_issue(db_pool, task.newsletter_issue_id).await?;
+    let html_content = if task.tracking_opt_out {
+        issue.html_content
+    } else {
+        link_tracker.instrument_html(&issue.html_content, task.newsletter_issue_id, task.subscriber_id)
+    };
+    let result = email_client
+        .send_email(email, &issue.title, &html_content, &issue.text_content)
+        .await
+        .map_err(|e| anyhow::anyhow!(e));
+
+    match result {
+        Ok(message_id) => {
+            metrics.email_sent(EmailKind::Issue);
+            mark_sent(&mut transaction, &task, message_id).await?
+        }
+        Err(e) if matches!(e.downcast_ref(), Some(SendEmailError::Suppressed)) => {
+            mark_suppressed(&mut transaction, &task).await?
+        }
+        Err(e) => {
+            metrics.email_failed(EmailKind::Issue);
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to deliver issue to a confirmed subscriber.",
+            );
+            mark_failed_attempt(&mut transaction, &task, &format!("{:#}", e)).await?;
+        }
+    }
+    transaction.commit().await?;
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+#[tracing::instrument(skip_all)]
+async fn dequeue_task(
+    db_pool: &PgPool,
+) -> Result<Option<(Transaction<'static, Postgres>, Task)>, anyhow::Error> {
+    let mut transaction = db_pool.begin().await?;
+    let task = sqlx::query_as!(
+        Task,
+        r#"SELECT d.newsletter_issue_id, d.subscriber_id, d.subscriber_email, d.attempts, s.tracking_opt_out
+        FROM newsletter_deliveries d
+        JOIN subscriptions s ON s.id = d.subscriber_id
+        WHERE d.state = 'queued' AND d.execute_after <= now()
+        FOR UPDATE OF d
+        SKIP LOCKED
+        LIMIT 1"#,
+    )
+    .fetch_optional(&mut *transaction)
+    .await?;
+    Ok(task.map(|task| (transaction, task)))
+}
+
+#[tracing::instrument(skip_all)]
+async fn mark_sent(
+    transaction: &mut Transaction<'_, Postgres>,
+    task: &Task,
+    message_id: Option<String>,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"UPDATE newsletter_deliveries
+        SET state = 'sent', attempts = attempts + 1, message_id = $3, last_error = NULL, updated_at = now()
+        WHERE newsletter_issue_id = $1 AND subscriber_id = $2"#,
+        task.newsletter_issue_id,
+        task.subscriber_id,
+        message_id,
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
+
+/// The subscriber was suppressed after the issue was published: there is nothing to retry.
+#[tracing::instrument(skip_all)]
+async fn mark_suppressed(transaction: &mut Transaction<'_, Postgres>, task: &Task) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"UPDATE newsletter_deliveries
+        SET state = 'suppressed', last_error = NULL, updated_at = now()
+        WHERE newsletter_issue_id = $1 AND subscriber_id = $2"#,
+        task.newsletter_issue_id,
+        task.subscriber_id,
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
+
+/// Record that an email bounced, as reported by the email provider: the delivery of `message_id`
+/// if it is known, the last issue sent to `email` otherwise. Returns whether a delivery matched.
+#[tracing::instrument(name = "Mark a delivery as bounced", skip(transaction, email))]
+pub async fn mark_bounced(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &str,
+    message_id: Option<&str>,
+) -> Result<bool, sqlx::Error> {
+    let normalized = normalize_email(email).unwrap_or_else(|| email.trim().to_lowercase());
+    let result = sqlx::query!(
+        r#"UPDATE newsletter_deliveries
+        SET state = 'bounced', updated_at = now()
+        WHERE (newsletter_issue_id, subscriber_id) IN (
+            SELECT d.newsletter_issue_id, d.subscriber_id
+            FROM newsletter_deliveries d
+            JOIN subscriptions s ON s.id = d.subscriber_id
+            WHERE s.email_normalized = $1 AND d.state = 'sent' AND ($2::text IS NULL OR d.message_id = $2)
+            ORDER BY d.updated_at DESC
+            LIMIT 1
+        )"#,
+        normalized,
+        message_id,
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Record a delivery that cannot succeed, however many times it is tried.
+#[tracing::instrument(skip_all)]
+async fn mark_failed(transaction: &mut Transaction<'_, Postgres>, task: &Task, error: &str) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"UPDATE newsletter_deliveries
+        SET state = 'failed', attempts = attempts + 1, last_error = $3, updated_at = now()
+        WHERE newsletter_issue_id = $1 AND subscriber_id = $2"#,
+        task.newsletter_issue_id,
+        task.subscriber_id,
+        error,
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
+
+/// Record a failed attempt: the delivery is retried with an exponential backoff
+/// until it runs out of attempts, then it is marked as `failed`.
+#[tracing::instrument(skip_all)]
+async fn mark_failed_attempt(
+    transaction: &mut Transaction<'_, Postgres>,
+    task: &Task,
+    error: &str,
+) -> Result<(), anyhow::Error> {
+    let attempts = task.attempts + 1;
+    let state = if attempts >= MAX_DELIVERY_ATTEMPTS { "failed" } else { "queued" };
+    let backoff_seconds = 2_f64.powi(attempts) * 30.0;
+    sqlx::query!(
+        r#"UPDATE newsletter_deliveries
+        SET state = $3, attempts = $4, last_error = $5, updated_at = now(),
+            execute_after = now() + make_interval(secs => $6)
+        WHERE newsletter_issue_id = $1 AND subscriber_id = $2"#,
+        task.newsletter_issue_id,
+        task.subscriber_id,
+        state,
+        attempts,
+        error,
+        backoff_seconds,
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_issue(db_pool: &PgPool, newsletter_issue_id: Uuid) -> Result<Issue, anyhow::Error> {
+    let issue = sqlx::query_as!(
+        Issue,
+        r#"SELECT title, text_content, html_content
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1"#,
+        newsletter_issue_id
+    )
+    .fetch_one(db_pool)
+    .await?;
+    Ok(issue)
+}
+
+async fn worker_loop(
+    db_pool: PgPool,
+    email_client: EmailClient,
+    link_tracker: LinkTracker,
+    metrics: Metrics,
+    mut shutdown: ShutdownSignal,
+) -> Result<(), anyhow::Error> {
+    // The signal is only checked between tasks: an email is never abandoned half-way through.
+    while !shutdown.is_requested() {
+        let pause = match try_execute_task(&db_pool, &email_client, &link_tracker, &metrics).await {
+            Ok(ExecutionOutcome::EmptyQueue) => Duration::from_secs(10),
+            Err(_) => Duration::from_secs(1),
+            Ok(ExecutionOutcome::TaskCompleted) => continue,
+        };
+        tokio::select! {
+            _ = tokio::time::sleep(pause) => {}
+            _ = shutdown.requested() => {}
+        }
+    }
+    Ok(())
+}
+
+pub async fn run_worker_until_stopped(
+    configuration: Settings,
+    metrics: Metrics,
+    shutdown: ShutdownSignal,
+) -> Result<(), anyhow::Error> {
+    let connection_pool = get_connection_pool(&configuration.database);
+    let link_tracker = configuration.application.link_tracker();
+    let email_client = configuration
+        .email_client
+        .client()
+        .with_suppression_list(connection_pool.clone(), configuration.application.email_hasher());
+    worker_loop(connection_pool, email_client, link_tracker, metrics, shutdown).await
+}
@@ -1,7 +1,25 @@
+pub mod authentication;
+pub mod bot_protection;
 pub mod startup;
+pub mod cli;
 pub mod configuration;
+pub mod confirmation_email_worker;
 pub mod routes;
+pub mod shutdown;
 pub mod telemetry;
+pub mod tracking;
 
+pub mod csv;
 pub mod damain;
-pub mod email_client;
\ No newline at end of file
+pub mod deliverability;
+pub mod email_client;
+pub mod import;
+pub mod issue_delivery_worker;
+pub mod markdown;
+pub mod metrics;
+pub mod privacy;
+pub mod problem_details;
+pub mod rate_limit;
+pub mod request_id;
+pub mod subscribers;
+pub mod suppressions;
\ No newline at end of file
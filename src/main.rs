@@ -1,16 +1,45 @@
+use clap::Parser;
+use zero_to_production_rust_book::cli::{self, Cli, Command};
 use zero_to_production_rust_book::configuration::get_configuration;
+use zero_to_production_rust_book::{confirmation_email_worker, issue_delivery_worker};
 use zero_to_production_rust_book::startup::Application;
-use zero_to_production_rust_book::telemetry::{get_subscriber, init_subscriber};
+use zero_to_production_rust_book::telemetry::{construct_tracer_provider, get_subscriber, init_subscriber};
 
 #[tokio::main]
-async fn main() -> std::io::Result<()> {
+async fn main() -> anyhow::Result<()> {
     dotenv::dotenv().ok();
+    let cli = Cli::parse();
 
     let configuration = get_configuration().expect("Failed to get configuration");
 
-    let subscriber = get_subscriber("zero2prod".into(), "info".to_string(), std::io::stdout, &configuration.jaeger);
-    init_subscriber(subscriber);
+    let tracer_provider = construct_tracer_provider(&configuration.telemetry)?;
+    let command = cli.command.unwrap_or(Command::Serve);
+    // The logs of one-off commands go to stderr, leaving stdout to their output.
+    let service_name = configuration.telemetry.service_name.clone();
+    if let Command::Serve = command {
+        init_subscriber(get_subscriber(service_name, "info".into(), std::io::stdout, &tracer_provider));
+    } else {
+        init_subscriber(get_subscriber(service_name, "warn".into(), std::io::stderr, &tracer_provider));
+    }
 
-    Application::build(configuration).await?.run_until_stopped().await?;
-    Ok(())
-}
\ No newline at end of file
+    match command {
+        Command::Serve => {
+            let mut application = Application::build(configuration.clone())
+                .await?
+                .with_tracer_provider(tracer_provider);
+            let metrics = application.metrics();
+            application.spawn_worker("Background worker", |shutdown| {
+                issue_delivery_worker::run_worker_until_stopped(configuration.clone(), metrics.clone(), shutdown)
+            });
+            application.spawn_worker("Confirmation email worker", |shutdown| {
+                confirmation_email_worker::run_worker_until_stopped(configuration, metrics, shutdown)
+            });
+            application.run_until_stopped().await
+        }
+        command => {
+            let outcome = cli::execute(command, configuration, &mut std::io::stdout().lock()).await;
+            let _ = tracer_provider.shutdown();
+            outcome
+        }
+    }
+}
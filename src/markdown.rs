@@ -0,0 +1,146 @@
+use pulldown_cmark::{Event, HeadingLevel, Options, Parser, Tag, TagEnd};
+
+/// Styles inlined into the HTML body: most email clients ignore `<style>` blocks.
+const EMAIL_STYLESHEET: &str = r#"
+p, li { font-family: Helvetica, Arial, sans-serif; font-size: 16px; line-height: 1.5; color: #1f2328; }
+h1, h2, h3, h4, h5, h6 { font-family: Helvetica, Arial, sans-serif; color: #1f2328; margin: 24px 0 12px; }
+a { color: #0969da; text-decoration: underline; }
+blockquote { border-left: 4px solid #d0d7de; margin: 0; padding: 0 16px; color: #59636e; }
+pre { background-color: #f6f8fa; padding: 16px; overflow: auto; }
+code { font-family: Menlo, Consolas, monospace; font-size: 14px; }
+img { max-width: 100%; height: auto; }
+table { border-collapse: collapse; }
+th, td { border: 1px solid #d0d7de; padding: 6px 13px; }
+"#;
+
+/// The two bodies of an email, derived from the same Markdown source.
+pub struct RenderedContent {
+    pub html: String,
+    pub text: String,
+}
+
+fn parser(markdown: &str) -> Parser<'_> {
+    Parser::new_ext(markdown, Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH)
+}
+
+/// Render Markdown into sanitized HTML with inlined styles, and into plain text.
+pub fn render(markdown: &str) -> Result<RenderedContent, anyhow::Error> {
+    let mut html = String::with_capacity(markdown.len() * 3 / 2);
+    pulldown_cmark::html::push_html(&mut html, parser(markdown));
+    // Raw HTML is allowed in Markdown: strip anything that could run scripts in a web client.
+    let html = ammonia::clean(&html);
+    let html = css_inline::inline_fragment(&html, EMAIL_STYLESHEET)?;
+
+    Ok(RenderedContent {
+        html,
+        text: render_text(markdown),
+    })
+}
+
+fn render_text(markdown: &str) -> String {
+    let mut text = String::with_capacity(markdown.len());
+    // The next number of every ordered list we are in, `None` for bullet lists.
+    let mut lists: Vec<Option<u64>> = Vec::new();
+    let mut links: Vec<String> = Vec::new();
+    let mut link_text_start = 0;
+
+    for event in parser(markdown) {
+        match event {
+            Event::Start(Tag::Heading { level: HeadingLevel::H1, .. }) => text.push_str("# "),
+            Event::Start(Tag::Item) => {
+                text.push_str(&"  ".repeat(lists.len().saturating_sub(1)));
+                match lists.last_mut() {
+                    Some(Some(number)) => {
+                        text.push_str(&format!("{}. ", number));
+                        *number += 1;
+                    }
+                    _ => text.push_str("- "),
+                }
+            }
+            Event::Start(Tag::List(first_number)) => {
+                if !lists.is_empty() && !text.ends_with('\n') {
+                    text.push('\n');
+                }
+                lists.push(first_number);
+            }
+            Event::Start(Tag::Link { dest_url, .. }) | Event::Start(Tag::Image { dest_url, .. }) => {
+                links.push(dest_url.into_string());
+                link_text_start = text.len();
+            }
+            Event::Start(Tag::BlockQuote(_)) => text.push_str("> "),
+            Event::End(TagEnd::Link) | Event::End(TagEnd::Image) => {
+                if let Some(url) = links.pop()
+                    && text[link_text_start..] != url
+                {
+                    text.push_str(&format!(" ({})", url));
+                }
+            }
+            Event::End(TagEnd::Paragraph) | Event::End(TagEnd::Heading(_)) | Event::End(TagEnd::CodeBlock) => {
+                if lists.is_empty() {
+                    text.push_str("\n\n");
+                } else {
+                    text.push('\n');
+                }
+            }
+            Event::End(TagEnd::Item) if !text.ends_with('\n') => text.push('\n'),
+            Event::End(TagEnd::List(_)) => {
+                lists.pop();
+                if lists.is_empty() {
+                    text.push('\n');
+                }
+            }
+            Event::End(TagEnd::TableRow) | Event::End(TagEnd::TableHead) => text.push('\n'),
+            Event::End(TagEnd::TableCell) => text.push('\t'),
+            Event::Text(t) | Event::Code(t) => text.push_str(&t),
+            Event::SoftBreak => text.push(' '),
+            Event::HardBreak => text.push('\n'),
+            Event::Rule => text.push_str("----------\n\n"),
+            _ => {}
+        }
+    }
+    let trimmed_length = text.trim_end().len();
+    text.truncate(trimmed_length);
+    text.push('\n');
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn markdown_is_rendered_to_html_with_inline_styles() {
+        let rendered = render("# Hello\n\nSome *emphasis* and a [link](https://www.rust-lang.org).").unwrap();
+        assert!(rendered.html.contains("<h1 style="));
+        assert!(rendered.html.contains("<em>emphasis</em>"));
+        assert!(rendered.html.contains(r#"href="https://www.rust-lang.org""#));
+        assert!(!rendered.html.contains("<style"));
+    }
+
+    #[test]
+    fn scripts_are_stripped_from_the_html() {
+        let rendered = render("Hello <script>alert('pwned')</script>\n\n[x](javascript:alert(1))").unwrap();
+        assert!(!rendered.html.contains("<script"));
+        assert!(!rendered.html.contains("javascript:"));
+    }
+
+    #[test]
+    fn the_text_version_is_readable() {
+        let rendered = render(
+            "# Title\n\nFirst paragraph with a [link](https://www.rust-lang.org).\n\n\
+            - one\n- two\n\n1. first\n2. second\n",
+        )
+        .unwrap();
+        assert_eq!(
+            rendered.text,
+            "# Title\n\nFirst paragraph with a link (https://www.rust-lang.org).\n\n\
+            - one\n- two\n\n1. first\n2. second\n"
+        );
+    }
+
+    #[test]
+    fn bare_links_are_not_repeated_in_the_text_version() {
+        let rendered = render("Visit <https://www.rust-lang.org>").unwrap();
+        assert_eq!(rendered.text, "Visit https://www.rust-lang.org\n");
+    }
+}
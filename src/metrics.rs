@@ -0,0 +1,389 @@
+use std::fmt::{self, Display, Write};
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::StatusCode;
+use actix_web::middleware::Next;
+use actix_web::web;
+use opentelemetry::metrics::{Counter, Histogram, Meter, MeterProvider, ObservableGauge};
+use opentelemetry::{KeyValue, Value};
+use opentelemetry_sdk::error::OTelSdkResult;
+use opentelemetry_sdk::metrics::data::{self, ResourceMetrics};
+use opentelemetry_sdk::metrics::reader::MetricReader;
+use opentelemetry_sdk::metrics::{
+    InstrumentKind, ManualReader, MetricResult, Pipeline, SdkMeterProvider, Temporality,
+};
+use opentelemetry_sdk::Resource;
+use sqlx::PgPool;
+
+/// Boundaries of the request duration histogram, in seconds.
+const DURATION_BUCKETS: [f64; 12] = [
+    0.005, 0.01, 0.025, 0.05, 0.075, 0.1, 0.25, 0.5, 0.75, 1.0, 2.5, 5.0,
+];
+
+/// Why an email was sent, to tell the different flows apart in the email counters.
+#[derive(Debug, Clone, Copy)]
+pub enum EmailKind {
+    Confirmation,
+    Preview,
+    Issue,
+}
+
+impl EmailKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            EmailKind::Confirmation => "confirmation",
+            EmailKind::Preview => "preview",
+            EmailKind::Issue => "issue",
+        }
+    }
+}
+
+/// The instruments the application records its metrics with.
+///
+/// It holds on to the meter provider: metrics stop being collected once the last copy is dropped,
+/// or once it is shut down.
+#[derive(Clone)]
+pub struct Metrics {
+    provider: SdkMeterProvider,
+    request_duration: Histogram<f64>,
+    subscriptions_created: Counter<u64>,
+    subscriptions_confirmed: Counter<u64>,
+    emails_sent: Counter<u64>,
+    emails_failed: Counter<u64>,
+    _connections: Arc<[ObservableGauge<u64>]>,
+}
+
+impl Metrics {
+    pub fn new(provider: SdkMeterProvider, db_pool: &PgPool) -> Self {
+        let meter = provider.meter("zero2prod");
+        Self {
+            request_duration: meter
+                .f64_histogram("http.server.request.duration")
+                .with_description("Duration of the HTTP requests served.")
+                .with_unit("s")
+                .with_boundaries(DURATION_BUCKETS.to_vec())
+                .build(),
+            subscriptions_created: meter
+                .u64_counter("newsletter.subscriptions.created")
+                .with_description("Subscriptions awaiting confirmation.")
+                .build(),
+            subscriptions_confirmed: meter
+                .u64_counter("newsletter.subscriptions.confirmed")
+                .with_description("Subscriptions confirmed by their subscriber.")
+                .build(),
+            emails_sent: meter
+                .u64_counter("newsletter.emails.sent")
+                .with_description("Emails accepted by the email API.")
+                .build(),
+            emails_failed: meter
+                .u64_counter("newsletter.emails.failed")
+                .with_description("Emails the email API failed to accept.")
+                .build(),
+            _connections: observe_connection_pool(&meter, db_pool).into(),
+            provider,
+        }
+    }
+
+    /// Export the metrics still buffered, and stop collecting.
+    pub fn shutdown(&self) -> OTelSdkResult {
+        self.provider.shutdown()
+    }
+
+    pub fn record_request(&self, method: &str, route: &str, status: StatusCode, duration: Duration) {
+        let mut attributes = vec![
+            KeyValue::new("http.request.method", method.to_owned()),
+            KeyValue::new("http.route", route.to_owned()),
+            KeyValue::new("http.response.status_code", i64::from(status.as_u16())),
+        ];
+        if status.is_server_error() {
+            attributes.push(KeyValue::new("error.type", status.as_str().to_owned()));
+        }
+        self.request_duration.record(duration.as_secs_f64(), &attributes);
+    }
+
+    pub fn subscription_created(&self) {
+        self.subscriptions_created.add(1, &[]);
+    }
+
+    pub fn subscription_confirmed(&self) {
+        self.subscriptions_confirmed.add(1, &[]);
+    }
+
+    pub fn email_sent(&self, kind: EmailKind) {
+        self.emails_sent.add(1, &[KeyValue::new("email.kind", kind.as_str())]);
+    }
+
+    pub fn email_failed(&self, kind: EmailKind) {
+        self.emails_failed.add(1, &[KeyValue::new("email.kind", kind.as_str())]);
+    }
+}
+
+/// Connection pool utilization, following the database client semantic conventions.
+fn observe_connection_pool(meter: &Meter, db_pool: &PgPool) -> Vec<ObservableGauge<u64>> {
+    let pool = db_pool.clone();
+    let count = meter
+        .u64_observable_gauge("db.client.connection.count")
+        .with_description("Connections currently open, by state.")
+        .with_callback(move |observer| {
+            let idle = pool.num_idle() as u64;
+            let used = u64::from(pool.size()).saturating_sub(idle);
+            observer.observe(idle, &[KeyValue::new("db.client.connection.state", "idle")]);
+            observer.observe(used, &[KeyValue::new("db.client.connection.state", "used")]);
+        })
+        .build();
+    let pool = db_pool.clone();
+    let max = meter
+        .u64_observable_gauge("db.client.connection.max")
+        .with_description("The maximum number of open connections allowed.")
+        .with_callback(move |observer| {
+            observer.observe(pool.options().get_max_connections().into(), &[]);
+        })
+        .build();
+    vec![count, max]
+}
+
+/// Record the rate, errors and duration of the requests served, per route.
+pub async fn record_http_metrics(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let metrics = req.app_data::<web::Data<Metrics>>().cloned();
+    let method = req.method().to_string();
+    // Unmatched requests are grouped together, to keep the number of series bounded.
+    let route = req.match_pattern().unwrap_or_else(|| "default".into());
+    let start = Instant::now();
+
+    let outcome = next.call(req).await;
+
+    if let Some(metrics) = metrics {
+        let status = match &outcome {
+            Ok(response) => response.status(),
+            Err(e) => e.as_response_error().status_code(),
+        };
+        metrics.record_request(&method, &route, status, start.elapsed());
+    }
+    outcome
+}
+
+/// A metric reader serving the Prometheus text exposition format, for environments
+/// scraping `/metrics` rather than running an OpenTelemetry collector.
+#[derive(Debug, Clone, Default)]
+pub struct PrometheusExporter {
+    reader: Arc<ManualReader>,
+}
+
+impl PrometheusExporter {
+    pub fn render(&self) -> MetricResult<String> {
+        let mut metrics = ResourceMetrics {
+            resource: Resource::builder_empty().build(),
+            scope_metrics: Vec::new(),
+        };
+        self.reader.collect(&mut metrics)?;
+        let mut output = String::new();
+        for metric in metrics.scope_metrics.iter().flat_map(|scope| &scope.metrics) {
+            encode_metric(&mut output, metric).expect("Writing to a String cannot fail.");
+        }
+        Ok(output)
+    }
+}
+
+impl MetricReader for PrometheusExporter {
+    fn register_pipeline(&self, pipeline: Weak<Pipeline>) {
+        self.reader.register_pipeline(pipeline)
+    }
+
+    fn collect(&self, rm: &mut ResourceMetrics) -> MetricResult<()> {
+        self.reader.collect(rm)
+    }
+
+    fn force_flush(&self) -> OTelSdkResult {
+        self.reader.force_flush()
+    }
+
+    fn shutdown(&self) -> OTelSdkResult {
+        self.reader.shutdown()
+    }
+
+    fn temporality(&self, kind: InstrumentKind) -> Temporality {
+        self.reader.temporality(kind)
+    }
+}
+
+fn encode_metric(out: &mut String, metric: &data::Metric) -> fmt::Result {
+    let mut name = sanitize(&metric.name);
+    match metric.unit.as_ref() {
+        "s" => name.push_str("_seconds"),
+        "By" => name.push_str("_bytes"),
+        _ => {}
+    }
+    let data = metric.data.as_any();
+
+    if let Some(sum) = data.downcast_ref::<data::Sum<u64>>() {
+        encode_sum(out, &name, &metric.description, sum)
+    } else if let Some(sum) = data.downcast_ref::<data::Sum<i64>>() {
+        encode_sum(out, &name, &metric.description, sum)
+    } else if let Some(sum) = data.downcast_ref::<data::Sum<f64>>() {
+        encode_sum(out, &name, &metric.description, sum)
+    } else if let Some(gauge) = data.downcast_ref::<data::Gauge<u64>>() {
+        encode_gauge(out, &name, &metric.description, gauge)
+    } else if let Some(gauge) = data.downcast_ref::<data::Gauge<i64>>() {
+        encode_gauge(out, &name, &metric.description, gauge)
+    } else if let Some(gauge) = data.downcast_ref::<data::Gauge<f64>>() {
+        encode_gauge(out, &name, &metric.description, gauge)
+    } else if let Some(histogram) = data.downcast_ref::<data::Histogram<f64>>() {
+        encode_histogram(out, &name, &metric.description, histogram)
+    } else if let Some(histogram) = data.downcast_ref::<data::Histogram<u64>>() {
+        encode_histogram(out, &name, &metric.description, histogram)
+    } else {
+        tracing::warn!("Metric {} has an aggregation Prometheus cannot represent.", metric.name);
+        Ok(())
+    }
+}
+
+fn encode_sum<T: Display>(out: &mut String, name: &str, help: &str, sum: &data::Sum<T>) -> fmt::Result {
+    let (name, kind) = if sum.is_monotonic {
+        (format!("{}_total", name), "counter")
+    } else {
+        (name.to_owned(), "gauge")
+    };
+    writeln!(out, "# HELP {} {}", name, escape(help, false))?;
+    writeln!(out, "# TYPE {} {}", name, kind)?;
+    for point in &sum.data_points {
+        writeln!(out, "{}{} {}", name, labels(&point.attributes, None), point.value)?;
+    }
+    Ok(())
+}
+
+fn encode_gauge<T: Display>(out: &mut String, name: &str, help: &str, gauge: &data::Gauge<T>) -> fmt::Result {
+    writeln!(out, "# HELP {} {}", name, escape(help, false))?;
+    writeln!(out, "# TYPE {} gauge", name)?;
+    for point in &gauge.data_points {
+        writeln!(out, "{}{} {}", name, labels(&point.attributes, None), point.value)?;
+    }
+    Ok(())
+}
+
+fn encode_histogram<T: Display>(
+    out: &mut String,
+    name: &str,
+    help: &str,
+    histogram: &data::Histogram<T>,
+) -> fmt::Result {
+    writeln!(out, "# HELP {} {}", name, escape(help, false))?;
+    writeln!(out, "# TYPE {} histogram", name)?;
+    for point in &histogram.data_points {
+        // Prometheus buckets are cumulative, OpenTelemetry ones are not.
+        let mut cumulative_count = 0;
+        for (bound, count) in point.bounds.iter().zip(&point.bucket_counts) {
+            cumulative_count += count;
+            let le = bound.to_string();
+            writeln!(out, "{}_bucket{} {}", name, labels(&point.attributes, Some(&le)), cumulative_count)?;
+        }
+        writeln!(out, "{}_bucket{} {}", name, labels(&point.attributes, Some("+Inf")), point.count)?;
+        writeln!(out, "{}_sum{} {}", name, labels(&point.attributes, None), point.sum)?;
+        writeln!(out, "{}_count{} {}", name, labels(&point.attributes, None), point.count)?;
+    }
+    Ok(())
+}
+
+fn labels(attributes: &[KeyValue], le: Option<&str>) -> String {
+    let mut labels: Vec<String> = attributes
+        .iter()
+        .map(|kv| format!("{}=\"{}\"", sanitize(kv.key.as_str()), escape(&value_to_string(&kv.value), true)))
+        .collect();
+    if let Some(le) = le {
+        labels.push(format!("le=\"{}\"", le));
+    }
+    if labels.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", labels.join(","))
+    }
+}
+
+fn value_to_string(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.as_str().to_owned(),
+        other => other.to_string(),
+    }
+}
+
+/// Metric and label names may only contain `[a-zA-Z0-9_]` (and `:` for recording rules).
+fn sanitize(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '_' { c } else { '_' })
+        .collect()
+}
+
+fn escape(s: &str, escape_quotes: bool) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '"' if escape_quotes => escaped.push_str("\\\""),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn provider() -> (SdkMeterProvider, PrometheusExporter) {
+        let exporter = PrometheusExporter::default();
+        let provider = SdkMeterProvider::builder()
+            .with_reader(exporter.clone())
+            .build();
+        (provider, exporter)
+    }
+
+    #[test]
+    fn counters_are_rendered_with_a_total_suffix() {
+        let (provider, exporter) = provider();
+        let counter = provider.meter("test").u64_counter("emails.sent").build();
+        counter.add(2, &[KeyValue::new("email.kind", "issue")]);
+
+        let output = exporter.render().unwrap();
+
+        assert!(output.contains("# TYPE emails_sent_total counter\n"));
+        assert!(output.contains("emails_sent_total{email_kind=\"issue\"} 2\n"));
+    }
+
+    #[test]
+    fn histogram_buckets_are_cumulative() {
+        let (provider, exporter) = provider();
+        let histogram = provider
+            .meter("test")
+            .f64_histogram("duration")
+            .with_unit("s")
+            .with_boundaries(vec![0.1, 1.0])
+            .build();
+        histogram.record(0.05, &[]);
+        histogram.record(0.5, &[]);
+        histogram.record(2.0, &[]);
+
+        let output = exporter.render().unwrap();
+
+        assert!(output.contains("# TYPE duration_seconds histogram\n"));
+        assert!(output.contains("duration_seconds_bucket{le=\"0.1\"} 1\n"));
+        assert!(output.contains("duration_seconds_bucket{le=\"1\"} 2\n"));
+        assert!(output.contains("duration_seconds_bucket{le=\"+Inf\"} 3\n"));
+        assert!(output.contains("duration_seconds_count 3\n"));
+    }
+
+    #[test]
+    fn label_values_are_escaped() {
+        let (provider, exporter) = provider();
+        let counter = provider.meter("test").u64_counter("requests").build();
+        counter.add(1, &[KeyValue::new("path", "a\"b\\c")]);
+
+        let output = exporter.render().unwrap();
+
+        assert!(output.contains(r#"requests_total{path="a\"b\\c"} 1"#));
+    }
+}
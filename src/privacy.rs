@@ -0,0 +1,152 @@
+//! Requests from data subjects: a copy of everything stored about them, or its erasure.
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+use crate::damain::normalize_email;
+use crate::suppressions::{self, EmailHasher, SuppressionReason};
+
+/// Everything stored about an email address.
+#[derive(Debug, serde::Serialize)]
+pub struct PersonalData {
+    pub email: String,
+    /// `None` if the address is not subscribed.
+    pub subscriber: Option<SubscriberRecord>,
+    pub confirmation_tokens: Vec<ConfirmationTokenRecord>,
+    pub deliveries: Vec<DeliveryRecord>,
+    pub events: Vec<EventRecord>,
+    /// Whether the address is on the suppression list. Only a hash of it is stored there.
+    pub suppressed: bool,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct SubscriberRecord {
+    pub id: Uuid,
+    pub email: String,
+    pub name: String,
+    pub status: String,
+    pub subscribed_at: DateTime<Utc>,
+    pub confirmed_at: Option<DateTime<Utc>>,
+    pub consent_source: Option<String>,
+    pub tracking_opt_out: bool,
+}
+
+/// The token itself is left out: it is a credential, not data about the subscriber.
+#[derive(Debug, serde::Serialize)]
+pub struct ConfirmationTokenRecord {
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct DeliveryRecord {
+    pub newsletter_issue_id: Uuid,
+    pub newsletter_title: String,
+    pub subscriber_email: String,
+    pub state: String,
+    pub attempts: i32,
+    pub last_error: Option<String>,
+    pub message_id: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct EventRecord {
+    pub newsletter_issue_id: Uuid,
+    pub kind: String,
+    pub url: Option<String>,
+    pub occurred_at: DateTime<Utc>,
+}
+
+/// Requests name an address however the data subject spells it.
+fn normalized_email(email: &str) -> String {
+    normalize_email(email).unwrap_or_else(|| email.trim().to_lowercase())
+}
+
+/// Gather everything stored about `email`, to answer a data subject access request.
+#[tracing::instrument(name = "Export personal data", skip(db_pool, email_hasher, email))]
+pub async fn export_personal_data(
+    db_pool: &PgPool,
+    email_hasher: &EmailHasher,
+    email: &str,
+) -> Result<PersonalData, sqlx::Error> {
+    // A consistent snapshot: nothing can be half-deleted in between two queries.
+    let mut transaction = db_pool.begin().await?;
+    sqlx::query!("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ READ ONLY")
+        .execute(&mut *transaction)
+        .await?;
+    let subscriber = sqlx::query_as!(
+        SubscriberRecord,
+        r#"SELECT id, email, name, status, subscribed_at, confirmed_at, consent_source, tracking_opt_out
+        FROM subscriptions
+        WHERE email_normalized = $1"#,
+        normalized_email(email),
+    )
+    .fetch_optional(&mut *transaction)
+    .await?;
+    let suppressed = suppressions::is_suppressed(&mut *transaction, email_hasher, email).await?;
+    let Some(subscriber) = subscriber else {
+        return Ok(PersonalData {
+            email: email.to_owned(),
+            subscriber: None,
+            confirmation_tokens: Vec::new(),
+            deliveries: Vec::new(),
+            events: Vec::new(),
+            suppressed,
+        });
+    };
+
+    let confirmation_tokens = sqlx::query_as!(
+        ConfirmationTokenRecord,
+        "SELECT created_at FROM subscription_tokens WHERE subscriber_id = $1 ORDER BY created_at",
+        subscriber.id,
+    )
+    .fetch_all(&mut *transaction)
+    .await?;
+    let deliveries = sqlx::query_as!(
+        DeliveryRecord,
+        r#"SELECT d.newsletter_issue_id, i.title AS newsletter_title, d.subscriber_email, d.state, d.attempts,
+            d.last_error, d.message_id, d.created_at, d.updated_at
+        FROM newsletter_deliveries d
+        JOIN newsletter_issues i ON i.newsletter_issue_id = d.newsletter_issue_id
+        WHERE d.subscriber_id = $1
+        ORDER BY d.created_at"#,
+        subscriber.id,
+    )
+    .fetch_all(&mut *transaction)
+    .await?;
+    let events = sqlx::query_as!(
+        EventRecord,
+        r#"SELECT newsletter_issue_id, kind, url, occurred_at
+        FROM newsletter_events
+        WHERE subscriber_id = $1
+        ORDER BY occurred_at"#,
+        subscriber.id,
+    )
+    .fetch_all(&mut *transaction)
+    .await?;
+    transaction.commit().await?;
+
+    Ok(PersonalData {
+        email: email.to_owned(),
+        subscriber: Some(subscriber),
+        confirmation_tokens,
+        deliveries,
+        events,
+        suppressed,
+    })
+}
+
+/// Erase everything stored about `email`: the subscription, and with it their tokens, deliveries and
+/// tracking events. A keyed hash of the address is kept on the suppression list, so that it is never
+/// mailed or imported again. Returns whether there was a subscriber to erase.
+#[tracing::instrument(name = "Erase personal data", skip(db_pool, email_hasher, email))]
+pub async fn erase_personal_data(db_pool: &PgPool, email_hasher: &EmailHasher, email: &str) -> Result<bool, sqlx::Error> {
+    let mut transaction = db_pool.begin().await?;
+    let erased = sqlx::query!("DELETE FROM subscriptions WHERE email_normalized = $1", normalized_email(email))
+        .execute(&mut *transaction)
+        .await?
+        .rows_affected();
+    suppressions::suppress(&mut *transaction, email_hasher, email, SuppressionReason::Erased).await?;
+    transaction.commit().await?;
+    Ok(erased > 0)
+}
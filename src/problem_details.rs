@@ -0,0 +1,111 @@
+use actix_web::http::StatusCode;
+use actix_web::{HttpRequest, HttpResponse, ResponseError};
+use opentelemetry::trace::TraceContextExt;
+use serde_json::{Map, Value};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use crate::request_id::RequestId;
+
+pub const PROBLEM_JSON: &str = "application/problem+json";
+
+/// An error response body, as described by RFC 9457.
+///
+/// The `detail` member is only ever a human readable explanation aimed at the client:
+/// the chain of causes of an error belongs in the logs,
+/// which can be found with the `request_id` or the `trace_id`.
+#[derive(Debug, serde::Serialize)]
+pub struct ProblemDetails {
+    #[serde(rename = "type")]
+    problem_type: String,
+    title: String,
+    status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detail: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    trace_id: Option<String>,
+    #[serde(flatten)]
+    extensions: Map<String, Value>,
+}
+
+impl ProblemDetails {
+    pub fn new(status: StatusCode) -> Self {
+        Self {
+            problem_type: "about:blank".into(),
+            title: status.canonical_reason().unwrap_or("Error").into(),
+            status: status.as_u16(),
+            detail: None,
+            request_id: RequestId::current().map(|id| id.to_string()),
+            trace_id: current_trace_id(),
+            extensions: Map::new(),
+        }
+    }
+
+    /// The problem details of an error: its `Display` is shown to the client,
+    /// unless it is a server error.
+    pub fn from_error(error: &impl ResponseError) -> Self {
+        let status = error.status_code();
+        let problem = Self::new(status);
+        if status.is_server_error() {
+            problem.with_detail("An unexpected error occurred, we are looking into it.")
+        } else {
+            problem.with_detail(error.to_string())
+        }
+    }
+
+    pub fn with_type(mut self, problem_type: impl Into<String>) -> Self {
+        self.problem_type = problem_type.into();
+        self
+    }
+
+    pub fn with_title(mut self, title: impl Into<String>) -> Self {
+        self.title = title.into();
+        self
+    }
+
+    pub fn with_detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+
+    pub fn with_extension(mut self, key: &str, value: impl serde::Serialize) -> Self {
+        let value = serde_json::to_value(value).expect("Failed to serialize a problem extension.");
+        self.extensions.insert(key.into(), value);
+        self
+    }
+
+    pub fn status_code(&self) -> StatusCode {
+        StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
+    }
+
+    pub fn into_response(self) -> HttpResponse {
+        HttpResponse::build(self.status_code())
+            .content_type(PROBLEM_JSON)
+            .json(self)
+    }
+}
+
+/// Render an error as problem details, for `ResponseError::error_response` implementations.
+pub fn problem_response(error: &impl ResponseError) -> HttpResponse {
+    ProblemDetails::from_error(error).into_response()
+}
+
+/// An error handler for the `Json`, `Form`, `Query` and `Path` extractors,
+/// so that malformed requests get problem details as well.
+pub fn extractor_error_handler<E>(error: E, _request: &HttpRequest) -> actix_web::Error
+where
+    E: ResponseError + 'static,
+{
+    let response = problem_response(&error);
+    actix_web::error::InternalError::from_response(error, response).into()
+}
+
+/// The id of the trace the current request belongs to, to correlate a response with the logs.
+fn current_trace_id() -> Option<String> {
+    let context = tracing::Span::current().context();
+    let span = context.span();
+    let span_context = span.span_context();
+    span_context
+        .is_valid()
+        .then(|| span_context.trace_id().to_string())
+}
@@ -0,0 +1,403 @@
+//! Token bucket rate limits on the public subscription endpoints.
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderMap, HeaderValue, RETRY_AFTER};
+use actix_web::http::StatusCode;
+use actix_web::middleware::Next;
+use actix_web::{web, HttpResponse, ResponseError};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use ipnet::{IpNet, Ipv6Net};
+use sqlx::PgPool;
+use crate::damain::SubscriberEmail;
+use crate::problem_details::ProblemDetails;
+use crate::suppressions::EmailHasher;
+
+/// Expired buckets are deleted every this many takes.
+const PRUNE_EVERY: u64 = 1000;
+
+/// A bucket holding up to `capacity` tokens, refilled at `capacity` per `period`. Each request takes one.
+#[derive(Debug, Clone, Copy)]
+pub struct Quota {
+    capacity: u32,
+    period: Duration,
+}
+
+impl Quota {
+    pub fn new(capacity: u32, period: Duration) -> Result<Self, anyhow::Error> {
+        anyhow::ensure!(capacity > 0, "A rate limit must allow at least one request.");
+        anyhow::ensure!(!period.is_zero(), "The period of a rate limit must not be zero.");
+        Ok(Self { capacity, period })
+    }
+
+    fn tokens_per_second(&self) -> f64 {
+        self.capacity as f64 / self.period.as_secs_f64()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Decision {
+    Allowed,
+    Limited { retry_after: Duration },
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated_at: DateTime<Utc>,
+}
+
+impl Bucket {
+    fn full(quota: &Quota, now: DateTime<Utc>) -> Self {
+        Self { tokens: quota.capacity as f64, updated_at: now }
+    }
+
+    /// Refill the bucket for the time elapsed since it was last updated, then take a token if there is one.
+    fn take(&mut self, quota: &Quota, now: DateTime<Utc>) -> Decision {
+        let elapsed = (now - self.updated_at).to_std().unwrap_or_default();
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * quota.tokens_per_second()).min(quota.capacity as f64);
+        self.updated_at = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Decision::Allowed
+        } else {
+            let retry_after = (1.0 - self.tokens) / quota.tokens_per_second();
+            Decision::Limited { retry_after: Duration::from_secs_f64(retry_after) }
+        }
+    }
+
+    /// When the bucket will be full again, and can be forgotten.
+    fn expires_at(&self, quota: &Quota) -> DateTime<Utc> {
+        let missing = quota.capacity as f64 - self.tokens;
+        let refill = Duration::from_secs_f64(missing / quota.tokens_per_second());
+        self.updated_at + chrono::Duration::from_std(refill).unwrap_or(chrono::Duration::MAX)
+    }
+}
+
+/// Where the buckets are kept.
+#[async_trait]
+pub trait RateLimitStore: Send + Sync {
+    /// Take a token from the bucket of `key`, which starts full.
+    async fn take(&self, key: &str, quota: &Quota) -> Result<Decision, anyhow::Error>;
+}
+
+/// Keeps the buckets in the memory of the instance: each instance enforces the limits on its own.
+#[derive(Default)]
+pub struct InMemoryStore {
+    buckets: Mutex<HashMap<String, (Bucket, DateTime<Utc>)>>,
+    takes: AtomicU64,
+}
+
+#[async_trait]
+impl RateLimitStore for InMemoryStore {
+    async fn take(&self, key: &str, quota: &Quota) -> Result<Decision, anyhow::Error> {
+        let now = Utc::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        if self.takes.fetch_add(1, Ordering::Relaxed).is_multiple_of(PRUNE_EVERY) {
+            buckets.retain(|_, (_, expires_at)| *expires_at > now);
+        }
+        let (bucket, expires_at) = buckets
+            .entry(key.to_owned())
+            .or_insert_with(|| (Bucket::full(quota, now), now));
+        let decision = bucket.take(quota, now);
+        *expires_at = bucket.expires_at(quota);
+        Ok(decision)
+    }
+}
+
+/// Keeps the buckets in `rate_limit_buckets`, so that the instances of the application share them.
+pub struct PostgresStore {
+    db_pool: PgPool,
+    takes: AtomicU64,
+}
+
+impl PostgresStore {
+    pub fn new(db_pool: PgPool) -> Self {
+        Self { db_pool, takes: AtomicU64::new(0) }
+    }
+}
+
+#[async_trait]
+impl RateLimitStore for PostgresStore {
+    #[tracing::instrument(name = "Take a rate limit token", skip(self, quota))]
+    async fn take(&self, key: &str, quota: &Quota) -> Result<Decision, anyhow::Error> {
+        if self.takes.fetch_add(1, Ordering::Relaxed).is_multiple_of(PRUNE_EVERY) {
+            sqlx::query!("DELETE FROM rate_limit_buckets WHERE expires_at < now()")
+                .execute(&self.db_pool)
+                .await?;
+        }
+        let now = Utc::now();
+        let mut transaction = self.db_pool.begin().await?;
+        sqlx::query!(
+            r#"
+            INSERT INTO rate_limit_buckets (key, tokens, updated_at, expires_at) VALUES ($1, $2, $3, $3)
+            ON CONFLICT (key) DO NOTHING
+            "#,
+            key,
+            quota.capacity as f64,
+            now,
+        )
+        .execute(&mut *transaction)
+        .await?;
+        // The row lock makes concurrent requests for the same key take their turn.
+        let row = sqlx::query!(
+            "SELECT tokens, updated_at FROM rate_limit_buckets WHERE key = $1 FOR UPDATE",
+            key,
+        )
+        .fetch_one(&mut *transaction)
+        .await?;
+        let mut bucket = Bucket { tokens: row.tokens, updated_at: row.updated_at };
+        let decision = bucket.take(quota, now.max(bucket.updated_at));
+        sqlx::query!(
+            "UPDATE rate_limit_buckets SET tokens = $2, updated_at = $3, expires_at = $4 WHERE key = $1",
+            key,
+            bucket.tokens,
+            bucket.updated_at,
+            bucket.expires_at(quota),
+        )
+        .execute(&mut *transaction)
+        .await?;
+        transaction.commit().await?;
+        Ok(decision)
+    }
+}
+
+/// The rate limits of the application. By default, nothing is limited.
+#[derive(Clone)]
+pub struct RateLimiter {
+    store: Arc<dyn RateLimitStore>,
+    trusted_proxies: Vec<IpNet>,
+    per_ip: Option<Quota>,
+    confirmation_emails_per_address: Option<(Quota, EmailHasher)>,
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::new(Arc::new(InMemoryStore::default()))
+    }
+}
+
+impl RateLimiter {
+    pub fn new(store: Arc<dyn RateLimitStore>) -> Self {
+        Self {
+            store,
+            trusted_proxies: Vec::new(),
+            per_ip: None,
+            confirmation_emails_per_address: None,
+        }
+    }
+
+    /// Believe the `X-Forwarded-For` header of requests coming from these networks.
+    pub fn with_trusted_proxies(mut self, trusted_proxies: Vec<IpNet>) -> Self {
+        self.trusted_proxies = trusted_proxies;
+        self
+    }
+
+    /// Limit the requests of each client to the subscription endpoints.
+    pub fn with_per_ip_limit(mut self, quota: Quota) -> Self {
+        self.per_ip = Some(quota);
+        self
+    }
+
+    /// Limit the confirmation emails sent to each address, known to the store by its keyed hash.
+    pub fn with_confirmation_email_limit(mut self, quota: Quota, email_hasher: EmailHasher) -> Self {
+        self.confirmation_emails_per_address = Some((quota, email_hasher));
+        self
+    }
+
+    /// The address of the client: the peer, unless it is a trusted proxy, in which case
+    /// `X-Forwarded-For` is walked back from the closest hop to the first one we do not trust.
+    pub fn client_ip(&self, peer: Option<IpAddr>, headers: &HeaderMap) -> Option<IpAddr> {
+        let mut client = peer?.to_canonical();
+        let forwarded_for: Vec<&str> = headers
+            .get_all("X-Forwarded-For")
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .collect();
+        for hop in forwarded_for.into_iter().rev() {
+            if !self.is_trusted(client) {
+                break;
+            }
+            // Past a hop we cannot read, the rest of the header cannot be believed either.
+            let Ok(hop) = hop.trim().parse::<IpAddr>() else { break };
+            client = hop.to_canonical();
+        }
+        Some(client)
+    }
+
+    fn is_trusted(&self, ip: IpAddr) -> bool {
+        self.trusted_proxies.iter().any(|network| network.contains(&ip))
+    }
+
+    pub async fn check_ip(&self, ip: IpAddr) -> Decision {
+        let Some(quota) = &self.per_ip else { return Decision::Allowed };
+        // An IPv6 client usually has a whole /64 to pick its address from.
+        let key = match ip {
+            IpAddr::V4(ip) => format!("ip:{}", ip),
+            IpAddr::V6(ip) => format!("ip:{}", Ipv6Net::new(ip, 64).unwrap().trunc()),
+        };
+        self.take(&key, quota).await
+    }
+
+    pub async fn check_confirmation_email(&self, email: &SubscriberEmail) -> Decision {
+        let Some((quota, email_hasher)) = &self.confirmation_emails_per_address else { return Decision::Allowed };
+        let key = format!("confirmation:{}", email_hasher.hash(email.as_ref()));
+        self.take(&key, quota).await
+    }
+
+    /// A store that cannot be reached lets requests through: better than turning everybody away.
+    async fn take(&self, key: &str, quota: &Quota) -> Decision {
+        match self.store.take(key, quota).await {
+            Ok(decision) => decision,
+            Err(e) => {
+                tracing::warn!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    "Failed to check a rate limit, letting the request through."
+                );
+                Decision::Allowed
+            }
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("Too many requests: try again in {} seconds.", self.retry_after_seconds())]
+pub struct TooManyRequests {
+    pub retry_after: Duration,
+}
+
+impl TooManyRequests {
+    fn retry_after_seconds(&self) -> u64 {
+        self.retry_after.as_secs_f64().ceil().max(1.0) as u64
+    }
+}
+
+impl ResponseError for TooManyRequests {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::TOO_MANY_REQUESTS
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = ProblemDetails::from_error(self)
+            .with_type("/problems/too-many-requests")
+            .into_response();
+        response
+            .headers_mut()
+            .insert(RETRY_AFTER, HeaderValue::from(self.retry_after_seconds()));
+        response
+    }
+}
+
+/// Turn away the clients that exceed their per IP limit.
+pub async fn rate_limit_by_ip(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    if let Some(limiter) = req.app_data::<web::Data<RateLimiter>>().cloned()
+        && let Some(ip) = limiter.client_ip(req.peer_addr().map(|a| a.ip()), req.headers())
+        && let Decision::Limited { retry_after } = limiter.check_ip(ip).await
+    {
+        tracing::info!("Rate limiting {}.", ip);
+        return Err(TooManyRequests { retry_after }.into());
+    }
+    next.call(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use actix_web::http::header::{HeaderMap, HeaderName, HeaderValue};
+    use chrono::Utc;
+    use super::{Bucket, Decision, InMemoryStore, Quota, RateLimiter};
+
+    fn quota(capacity: u32, seconds: u64) -> Quota {
+        Quota::new(capacity, Duration::from_secs(seconds)).unwrap()
+    }
+
+    fn forwarded_for(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(HeaderName::from_static("x-forwarded-for"), HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    fn ip(ip: &str) -> Option<IpAddr> {
+        Some(ip.parse().unwrap())
+    }
+
+    #[test]
+    fn a_bucket_allows_a_burst_then_refills_over_the_period() {
+        let quota = quota(2, 60);
+        let start = Utc::now();
+        let mut bucket = Bucket::full(&quota, start);
+
+        assert_eq!(bucket.take(&quota, start), Decision::Allowed);
+        assert_eq!(bucket.take(&quota, start), Decision::Allowed);
+        assert_eq!(bucket.take(&quota, start), Decision::Limited { retry_after: Duration::from_secs(30) });
+        assert_eq!(bucket.take(&quota, start + chrono::Duration::seconds(30)), Decision::Allowed);
+    }
+
+    #[test]
+    fn a_bucket_never_holds_more_than_its_capacity() {
+        let quota = quota(2, 60);
+        let start = Utc::now();
+        let mut bucket = Bucket::full(&quota, start);
+
+        let later = start + chrono::Duration::hours(1);
+        for _ in 0..2 {
+            assert_eq!(bucket.take(&quota, later), Decision::Allowed);
+        }
+        assert!(matches!(bucket.take(&quota, later), Decision::Limited { .. }));
+    }
+
+    #[test]
+    fn an_empty_quota_is_rejected() {
+        assert!(Quota::new(0, Duration::from_secs(60)).is_err());
+        assert!(Quota::new(1, Duration::ZERO).is_err());
+    }
+
+    #[test]
+    fn the_forwarded_address_is_only_believed_from_a_trusted_proxy() {
+        let limiter = RateLimiter::default().with_trusted_proxies(vec!["10.0.0.0/8".parse().unwrap()]);
+        let headers = forwarded_for("203.0.113.7");
+
+        assert_eq!(limiter.client_ip(ip("10.1.2.3"), &headers), ip("203.0.113.7"));
+        assert_eq!(limiter.client_ip(ip("198.51.100.1"), &headers), ip("198.51.100.1"));
+    }
+
+    #[test]
+    fn the_client_is_the_closest_hop_that_is_not_a_trusted_proxy() {
+        let limiter = RateLimiter::default().with_trusted_proxies(vec!["10.0.0.0/8".parse().unwrap()]);
+        // The client made the first address up: only the hops our proxies added can be believed.
+        let headers = forwarded_for("192.0.2.1, 203.0.113.7, 10.0.0.2");
+
+        assert_eq!(limiter.client_ip(ip("10.0.0.1"), &headers), ip("203.0.113.7"));
+    }
+
+    #[test]
+    fn an_unreadable_hop_stops_the_walk() {
+        let limiter = RateLimiter::default().with_trusted_proxies(vec!["10.0.0.0/8".parse().unwrap()]);
+        let headers = forwarded_for("203.0.113.7, garbage");
+
+        assert_eq!(limiter.client_ip(ip("10.0.0.1"), &headers), ip("10.0.0.1"));
+    }
+
+    #[tokio::test]
+    async fn ipv6_clients_are_limited_per_64_bit_prefix() {
+        let limiter = RateLimiter::new(Arc::new(InMemoryStore::default())).with_per_ip_limit(quota(1, 60));
+
+        assert_eq!(limiter.check_ip("2001:db8::1".parse().unwrap()).await, Decision::Allowed);
+        assert!(matches!(
+            limiter.check_ip("2001:db8::2".parse().unwrap()).await,
+            Decision::Limited { .. }
+        ));
+        assert_eq!(limiter.check_ip("2001:db8:0:1::1".parse().unwrap()).await, Decision::Allowed);
+    }
+}
@@ -0,0 +1,178 @@
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::error::InternalError;
+use actix_web::http::header::{HeaderMap, HeaderName, HeaderValue};
+use actix_web::middleware::Next;
+use actix_web::HttpMessage;
+use tracing::Span;
+use tracing_actix_web::{DefaultRootSpanBuilder, RootSpanBuilder};
+
+pub const X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+/// Longer identifiers sent by clients are ignored, and a new one is generated.
+const MAX_LENGTH: usize = 128;
+
+tokio::task_local! {
+    static CURRENT_REQUEST_ID: RequestId;
+}
+
+/// Identifies a request across our logs, our traces, the responses we send and the calls we make
+/// to other services on its behalf.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestId(String);
+
+impl RequestId {
+    /// Reuse the id picked by the client (or a proxy in front of us) if there is one:
+    /// an `X-Request-Id` header first, then the trace id of a W3C `traceparent` header.
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok());
+        header("x-request-id")
+            .and_then(Self::parse)
+            .or_else(|| header("traceparent").and_then(trace_id_from_traceparent).and_then(Self::parse))
+            .unwrap_or_else(Self::generate)
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        let is_valid = !s.is_empty()
+            && s.len() <= MAX_LENGTH
+            && s.bytes().all(|b| b.is_ascii_alphanumeric() || b"-_.:".contains(&b));
+        is_valid.then(|| Self(s.to_owned()))
+    }
+
+    fn generate() -> Self {
+        Self(uuid::Uuid::new_v4().to_string())
+    }
+
+    /// The id of the request being served by the current task, if any.
+    pub fn current() -> Option<Self> {
+        CURRENT_REQUEST_ID.try_with(|id| id.clone()).ok()
+    }
+}
+
+impl AsRef<str> for RequestId {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Display for RequestId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// `traceparent` is `{version}-{trace-id}-{parent-id}-{trace-flags}`.
+fn trace_id_from_traceparent(traceparent: &str) -> Option<&str> {
+    let mut parts = traceparent.split('-');
+    let _version = parts.next()?;
+    let trace_id = parts.next()?;
+    let is_valid = trace_id.len() == 32
+        && trace_id.bytes().all(|b| b.is_ascii_hexdigit())
+        && trace_id.bytes().any(|b| b != b'0');
+    is_valid.then_some(trace_id)
+}
+
+/// Builds the root span of every request, using the `RequestId` as its `request_id` field.
+pub struct RequestIdRootSpanBuilder;
+
+impl RootSpanBuilder for RequestIdRootSpanBuilder {
+    fn on_request_start(request: &ServiceRequest) -> Span {
+        let request_id = RequestId::from_headers(request.headers());
+        let span = tracing_actix_web::root_span!(request);
+        span.record("request_id", tracing::field::display(&request_id));
+        request.extensions_mut().insert(request_id);
+        span
+    }
+
+    fn on_request_end<B: MessageBody>(
+        span: Span,
+        outcome: &Result<ServiceResponse<B>, actix_web::Error>,
+    ) {
+        DefaultRootSpanBuilder::on_request_end(span, outcome);
+    }
+}
+
+/// Make the `RequestId` available to the code serving the request, and echo it back to the client.
+///
+/// Errors are rendered here, while the id is still in scope,
+/// so that it ends up in their problem details as well.
+pub async fn propagate_request_id(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, actix_web::Error> {
+    let request_id = req
+        .extensions()
+        .get::<RequestId>()
+        .cloned()
+        .unwrap_or_else(|| RequestId::from_headers(req.headers()));
+    let outcome = CURRENT_REQUEST_ID
+        .scope(request_id.clone(), async move {
+            next.call(req).await.map_err(|e| {
+                let response = e.error_response();
+                (e, response)
+            })
+        })
+        .await;
+    let header_value =
+        HeaderValue::from_str(request_id.as_ref()).expect("Request ids are valid header values.");
+    match outcome {
+        Ok(mut response) => {
+            response.headers_mut().insert(X_REQUEST_ID, header_value);
+            Ok(response.map_into_boxed_body())
+        }
+        Err((e, mut response)) => {
+            response.headers_mut().insert(X_REQUEST_ID, header_value);
+            Err(InternalError::from_response(e, response).into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(HeaderName::from_static(name), HeaderValue::from_static(value));
+        }
+        headers
+    }
+
+    #[test]
+    fn the_request_id_sent_by_the_client_is_used() {
+        let id = RequestId::from_headers(&headers(&[("x-request-id", "support-ticket-42")]));
+        assert_eq!(id.as_ref(), "support-ticket-42");
+    }
+
+    #[test]
+    fn the_trace_id_is_used_when_there_is_no_request_id() {
+        let id = RequestId::from_headers(&headers(&[(
+            "traceparent",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+        )]));
+        assert_eq!(id.as_ref(), "4bf92f3577b34da6a3ce929d0e0e4736");
+    }
+
+    #[test]
+    fn invalid_ids_are_replaced() {
+        let invalid = [
+            ("x-request-id", "has spaces"),
+            ("x-request-id", ""),
+            ("traceparent", "00-00000000000000000000000000000000-00f067aa0ba902b7-01"),
+            ("traceparent", "garbage"),
+        ];
+        for (name, value) in invalid {
+            let id = RequestId::from_headers(&headers(&[(name, value)]));
+            assert_ne!(id.as_ref(), value);
+            assert!(uuid::Uuid::parse_str(id.as_ref()).is_ok());
+        }
+    }
+
+    #[test]
+    fn overly_long_ids_are_replaced() {
+        let mut headers = HeaderMap::new();
+        let long_id = "a".repeat(MAX_LENGTH + 1);
+        headers.insert(X_REQUEST_ID, HeaderValue::from_str(&long_id).unwrap());
+        assert_ne!(RequestId::from_headers(&headers).as_ref(), long_id);
+    }
+}
@@ -0,0 +1,11 @@
+mod newsletter_report;
+mod newsletters;
+mod privacy;
+mod subscribers;
+mod suppressions;
+
+pub use newsletter_report::*;
+pub use newsletters::*;
+pub use privacy::*;
+pub use subscribers::*;
+pub use suppressions::*;
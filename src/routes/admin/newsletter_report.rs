@@ -0,0 +1,130 @@
+use crate::routes::NewsletterError;
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 500;
+
+#[derive(serde::Deserialize, Debug)]
+pub struct ReportQuery {
+    /// Return recipients whose subscriber id comes after this one.
+    after: Option<Uuid>,
+    limit: Option<i64>,
+}
+
+#[derive(serde::Serialize, Default)]
+pub struct DeliveryCounts {
+    queued: i64,
+    sent: i64,
+    failed: i64,
+    bounced: i64,
+    suppressed: i64,
+    total: i64,
+}
+
+#[derive(serde::Serialize)]
+pub struct RecipientDelivery {
+    subscriber_id: Uuid,
+    subscriber_email: String,
+    state: String,
+    attempts: i32,
+    last_error: Option<String>,
+    message_id: Option<String>,
+    updated_at: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize)]
+pub struct DeliveryReport {
+    newsletter_issue_id: Uuid,
+    counts: DeliveryCounts,
+    recipients: Vec<RecipientDelivery>,
+    next_cursor: Option<Uuid>,
+}
+
+#[tracing::instrument(name = "Count deliveries by state", skip(db_pool))]
+async fn get_delivery_counts(
+    newsletter_issue_id: Uuid,
+    db_pool: &PgPool,
+) -> Result<DeliveryCounts, sqlx::Error> {
+    let rows = sqlx::query!(
+        r#"SELECT state, COUNT(*) AS "count!"
+        FROM newsletter_deliveries
+        WHERE newsletter_issue_id = $1
+        GROUP BY state"#,
+        newsletter_issue_id
+    )
+    .fetch_all(db_pool)
+    .await?;
+
+    let mut counts = DeliveryCounts::default();
+    for row in rows {
+        match row.state.as_str() {
+            "queued" => counts.queued = row.count,
+            "sent" => counts.sent = row.count,
+            "failed" => counts.failed = row.count,
+            "bounced" => counts.bounced = row.count,
+            "suppressed" => counts.suppressed = row.count,
+            other => tracing::warn!("Unknown delivery state {}", other),
+        }
+        counts.total += row.count;
+    }
+    Ok(counts)
+}
+
+/// Aggregate delivery counts for an issue, followed by a page of per-recipient states
+/// ordered by subscriber id. Pass `next_cursor` back as `after` to fetch the next page.
+#[tracing::instrument(name = "Build a newsletter delivery report", skip(db_pool))]
+pub async fn newsletter_report(
+    newsletter_issue_id: web::Path<Uuid>,
+    query: web::Query<ReportQuery>,
+    db_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, NewsletterError> {
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+
+    sqlx::query!(
+        "SELECT newsletter_issue_id FROM newsletter_issues WHERE newsletter_issue_id = $1",
+        newsletter_issue_id
+    )
+    .fetch_optional(db_pool.get_ref())
+    .await
+    .context("Failed to fetch the newsletter issue.")?
+    .ok_or(NewsletterError::NotFound)?;
+
+    let counts = get_delivery_counts(newsletter_issue_id, &db_pool)
+        .await
+        .context("Failed to count the newsletter deliveries.")?;
+
+    // Fetch one extra row to know whether there is a next page.
+    let mut recipients = sqlx::query_as!(
+        RecipientDelivery,
+        r#"SELECT subscriber_id, subscriber_email, state, attempts, last_error, message_id, updated_at
+        FROM newsletter_deliveries
+        WHERE newsletter_issue_id = $1 AND ($2::uuid IS NULL OR subscriber_id > $2)
+        ORDER BY subscriber_id
+        LIMIT $3"#,
+        newsletter_issue_id,
+        query.after,
+        limit + 1,
+    )
+    .fetch_all(db_pool.get_ref())
+    .await
+    .context("Failed to fetch the newsletter deliveries.")?;
+
+    let next_cursor = if recipients.len() as i64 > limit {
+        recipients.truncate(limit as usize);
+        recipients.last().map(|r| r.subscriber_id)
+    } else {
+        None
+    };
+
+    Ok(HttpResponse::Ok().json(DeliveryReport {
+        newsletter_issue_id,
+        counts,
+        recipients,
+        next_cursor,
+    }))
+}
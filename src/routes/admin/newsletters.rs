@@ -0,0 +1,569 @@
+use crate::authentication::UserId;
+use crate::damain::{IssueSlug, SubscriberEmail};
+use crate::email_client::{EmailClient, SendEmailError};
+use crate::markdown;
+use crate::metrics::{EmailKind, Metrics};
+use crate::problem_details::problem_response;
+use crate::routes::error_chain_fmt;
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse, ResponseError};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{Connection, PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+#[derive(serde::Deserialize)]
+pub struct NewsletterDraftBody {
+    title: String,
+    content: ContentBody,
+    /// Whether the issue shows up in the public web archive once published.
+    #[serde(default)]
+    public: bool,
+}
+
+/// Issues are authored either in Markdown, or as separate HTML and text bodies.
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+pub enum ContentBody {
+    Markdown { markdown: String },
+    Rendered { html: String, text: String },
+}
+
+#[derive(serde::Serialize)]
+pub struct Content {
+    html: String,
+    text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    markdown: Option<String>,
+}
+
+/// A validated draft, with both bodies rendered.
+struct NewsletterDraft {
+    title: String,
+    content: Content,
+    public: bool,
+}
+
+#[derive(serde::Deserialize)]
+pub struct VisibilityBody {
+    public: bool,
+}
+
+#[derive(serde::Deserialize)]
+pub struct PreviewBody {
+    email: String,
+}
+
+#[derive(serde::Serialize)]
+pub struct NewsletterIssue {
+    newsletter_issue_id: Uuid,
+    title: String,
+    content: Content,
+    status: String,
+    revision: i32,
+    previewed_revision: Option<i32>,
+    public: bool,
+    slug: Option<String>,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+    published_at: Option<DateTime<Utc>>,
+}
+
+#[derive(serde::Serialize)]
+pub struct NewsletterRevision {
+    revision: i32,
+    title: String,
+    content: Content,
+    created_by: Uuid,
+    created_at: DateTime<Utc>,
+}
+
+impl TryFrom<NewsletterDraftBody> for NewsletterDraft {
+    type Error = NewsletterError;
+
+    fn try_from(body: NewsletterDraftBody) -> Result<Self, Self::Error> {
+        if body.title.trim().is_empty() {
+            return Err(NewsletterError::ValidationError(
+                "The newsletter title cannot be empty.".into(),
+            ));
+        }
+        let content = match body.content {
+            ContentBody::Markdown { markdown } => {
+                if markdown.trim().is_empty() {
+                    return Err(NewsletterError::ValidationError(
+                        "The newsletter body cannot be empty.".into(),
+                    ));
+                }
+                let rendered = markdown::render(&markdown)
+                    .context("Failed to render the newsletter Markdown.")?;
+                Content {
+                    html: rendered.html,
+                    text: rendered.text,
+                    markdown: Some(markdown),
+                }
+            }
+            ContentBody::Rendered { html, text } => {
+                if html.trim().is_empty() || text.trim().is_empty() {
+                    return Err(NewsletterError::ValidationError(
+                        "The newsletter must have both an HTML and a text body.".into(),
+                    ));
+                }
+                Content {
+                    html,
+                    text,
+                    markdown: None,
+                }
+            }
+        };
+        Ok(Self {
+            title: body.title,
+            content,
+            public: body.public,
+        })
+    }
+}
+
+#[tracing::instrument(name = "Get a newsletter issue", skip(executor))]
+async fn get_newsletter_issue<'e, E>(
+    newsletter_issue_id: Uuid,
+    executor: E,
+) -> Result<Option<NewsletterIssue>, sqlx::Error>
+where
+    E: sqlx::PgExecutor<'e>,
+{
+    let issue = sqlx::query!(
+        r#"SELECT newsletter_issue_id, title, text_content, html_content, markdown_content, status, revision,
+            previewed_revision, public, slug, created_at, updated_at, published_at
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1"#,
+        newsletter_issue_id
+    )
+    .fetch_optional(executor)
+    .await?
+    .map(|r| NewsletterIssue {
+        newsletter_issue_id: r.newsletter_issue_id,
+        title: r.title,
+        content: Content {
+            html: r.html_content,
+            text: r.text_content,
+            markdown: r.markdown_content,
+        },
+        status: r.status,
+        revision: r.revision,
+        previewed_revision: r.previewed_revision,
+        public: r.public,
+        slug: r.slug,
+        created_at: r.created_at,
+        updated_at: r.updated_at,
+        published_at: r.published_at,
+    });
+    Ok(issue)
+}
+
+#[tracing::instrument(name = "Lock a newsletter issue for update", skip(transaction))]
+async fn lock_newsletter_issue(
+    newsletter_issue_id: Uuid,
+    transaction: &mut Transaction<'_, Postgres>,
+) -> Result<NewsletterIssue, NewsletterError> {
+    sqlx::query!(
+        "SELECT newsletter_issue_id FROM newsletter_issues WHERE newsletter_issue_id = $1 FOR UPDATE",
+        newsletter_issue_id
+    )
+    .fetch_optional(&mut **transaction)
+    .await
+    .context("Failed to lock the newsletter issue.")?
+    .ok_or(NewsletterError::NotFound)?;
+
+    get_newsletter_issue(newsletter_issue_id, &mut **transaction)
+        .await
+        .context("Failed to fetch the newsletter issue.")?
+        .ok_or(NewsletterError::NotFound)
+}
+
+#[tracing::instrument(name = "Store a newsletter revision", skip(draft, transaction))]
+async fn insert_revision(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+    revision: i32,
+    draft: &NewsletterDraft,
+    user_id: UserId,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"INSERT INTO newsletter_issue_revisions
+            (newsletter_issue_id, revision, title, text_content, html_content, markdown_content, created_by, created_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, now())"#,
+        newsletter_issue_id,
+        revision,
+        draft.title,
+        draft.content.text,
+        draft.content.html,
+        draft.content.markdown,
+        user_id.inner(),
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(
+    name = "Create a newsletter draft",
+    skip(body, db_pool),
+    fields(newsletter_issue_id = tracing::field::Empty)
+)]
+pub async fn create_newsletter_draft(
+    body: web::Json<NewsletterDraftBody>,
+    db_pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, NewsletterError> {
+    let draft: NewsletterDraft = body.into_inner().try_into()?;
+    let user_id = user_id.into_inner();
+    let newsletter_issue_id = Uuid::new_v4();
+    tracing::Span::current().record(
+        "newsletter_issue_id",
+        tracing::field::display(&newsletter_issue_id),
+    );
+
+    let mut transaction = db_pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    sqlx::query!(
+        r#"INSERT INTO newsletter_issues
+            (newsletter_issue_id, title, text_content, html_content, markdown_content, public, status, revision, created_at, updated_at)
+        VALUES ($1, $2, $3, $4, $5, $6, 'draft', 1, now(), now())"#,
+        newsletter_issue_id,
+        draft.title,
+        draft.content.text,
+        draft.content.html,
+        draft.content.markdown,
+        draft.public,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to insert the newsletter draft.")?;
+    insert_revision(&mut transaction, newsletter_issue_id, 1, &draft, user_id)
+        .await
+        .context("Failed to store the first revision of the newsletter draft.")?;
+    let issue = get_newsletter_issue(newsletter_issue_id, &mut *transaction)
+        .await
+        .context("Failed to fetch the newsletter draft.")?
+        .ok_or(NewsletterError::NotFound)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to store a newsletter draft.")?;
+
+    Ok(HttpResponse::Created().json(issue))
+}
+
+#[tracing::instrument(name = "Fetch a newsletter issue", skip(db_pool))]
+pub async fn get_newsletter(
+    newsletter_issue_id: web::Path<Uuid>,
+    db_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, NewsletterError> {
+    let issue = get_newsletter_issue(newsletter_issue_id.into_inner(), db_pool.get_ref())
+        .await
+        .context("Failed to fetch the newsletter issue.")?
+        .ok_or(NewsletterError::NotFound)?;
+    Ok(HttpResponse::Ok().json(issue))
+}
+
+#[tracing::instrument(name = "Update a newsletter draft", skip(body, db_pool))]
+pub async fn update_newsletter_draft(
+    newsletter_issue_id: web::Path<Uuid>,
+    body: web::Json<NewsletterDraftBody>,
+    db_pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, NewsletterError> {
+    let draft: NewsletterDraft = body.into_inner().try_into()?;
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let mut transaction = db_pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let issue = lock_newsletter_issue(newsletter_issue_id, &mut transaction).await?;
+    if issue.status != "draft" {
+        return Err(NewsletterError::Conflict(
+            "Only drafts can be edited.".into(),
+        ));
+    }
+
+    let revision = issue.revision + 1;
+    sqlx::query!(
+        r#"UPDATE newsletter_issues
+        SET title = $2, text_content = $3, html_content = $4, markdown_content = $5, public = $6,
+            revision = $7, updated_at = now()
+        WHERE newsletter_issue_id = $1"#,
+        newsletter_issue_id,
+        draft.title,
+        draft.content.text,
+        draft.content.html,
+        draft.content.markdown,
+        draft.public,
+        revision,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to update the newsletter draft.")?;
+    insert_revision(
+        &mut transaction,
+        newsletter_issue_id,
+        revision,
+        &draft,
+        user_id.into_inner(),
+    )
+    .await
+    .context("Failed to store the new revision of the newsletter draft.")?;
+    let issue = get_newsletter_issue(newsletter_issue_id, &mut *transaction)
+        .await
+        .context("Failed to fetch the newsletter draft.")?
+        .ok_or(NewsletterError::NotFound)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to update a newsletter draft.")?;
+
+    Ok(HttpResponse::Ok().json(issue))
+}
+
+#[tracing::instrument(name = "List the revisions of a newsletter issue", skip(db_pool))]
+pub async fn list_newsletter_revisions(
+    newsletter_issue_id: web::Path<Uuid>,
+    db_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, NewsletterError> {
+    let revisions: Vec<NewsletterRevision> = sqlx::query!(
+        r#"SELECT revision, title, text_content, html_content, markdown_content, created_by, created_at
+        FROM newsletter_issue_revisions
+        WHERE newsletter_issue_id = $1
+        ORDER BY revision"#,
+        newsletter_issue_id.into_inner()
+    )
+    .fetch_all(db_pool.get_ref())
+    .await
+    .context("Failed to fetch the newsletter revisions.")?
+    .into_iter()
+    .map(|r| NewsletterRevision {
+        revision: r.revision,
+        title: r.title,
+        content: Content {
+            html: r.html_content,
+            text: r.text_content,
+            markdown: r.markdown_content,
+        },
+        created_by: r.created_by,
+        created_at: r.created_at,
+    })
+    .collect();
+
+    if revisions.is_empty() {
+        return Err(NewsletterError::NotFound);
+    }
+    Ok(HttpResponse::Ok().json(revisions))
+}
+
+#[tracing::instrument(
+    name = "Send a newsletter preview",
+    skip(body, db_pool, email_client, metrics),
+    fields(preview_email = %body.email)
+)]
+pub async fn send_newsletter_preview(
+    newsletter_issue_id: web::Path<Uuid>,
+    body: web::Json<PreviewBody>,
+    db_pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    metrics: web::Data<Metrics>,
+) -> Result<HttpResponse, NewsletterError> {
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let recipient = SubscriberEmail::parse(body.into_inner().email)
+        .map_err(|e| NewsletterError::ValidationError(format!("The email {}.", e)))?;
+    let issue = get_newsletter_issue(newsletter_issue_id, db_pool.get_ref())
+        .await
+        .context("Failed to fetch the newsletter issue.")?
+        .ok_or(NewsletterError::NotFound)?;
+    if issue.status != "draft" {
+        return Err(NewsletterError::Conflict(
+            "Only drafts can be previewed.".into(),
+        ));
+    }
+
+    let outcome = email_client
+        .send_email(
+            recipient,
+            &format!("[Preview] {}", issue.title),
+            &issue.content.html,
+            &issue.content.text,
+        )
+        .await;
+    match outcome {
+        Ok(_) => metrics.email_sent(EmailKind::Preview),
+        Err(SendEmailError::Suppressed) => {
+            return Err(NewsletterError::Conflict(
+                "The preview recipient is on the suppression list.".into(),
+            ));
+        }
+        Err(_) => metrics.email_failed(EmailKind::Preview),
+    }
+    outcome.context("Failed to send the newsletter preview.")?;
+
+    // Only mark the revision we actually sent, in case the draft was edited in the meantime.
+    sqlx::query!(
+        r#"UPDATE newsletter_issues SET previewed_revision = $2
+        WHERE newsletter_issue_id = $1 AND revision = $2"#,
+        newsletter_issue_id,
+        issue.revision,
+    )
+    .execute(db_pool.get_ref())
+    .await
+    .context("Failed to record the newsletter preview.")?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[tracing::instrument(name = "Enqueue delivery tasks", skip(transaction))]
+async fn enqueue_delivery_tasks(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"INSERT INTO newsletter_deliveries (newsletter_issue_id, subscriber_id, subscriber_email, state)
+        SELECT $1, id, email, 'queued'
+        FROM subscriptions
+        WHERE status = 'confirmed'"#,
+        newsletter_issue_id,
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
+
+/// Mark the issue as published under the slug of its title, or under a suffixed slug if another
+/// issue has it. The unique constraint decides, not a prior lookup: an issue with the same title
+/// may be being published concurrently.
+#[tracing::instrument(name = "Mark a newsletter issue as published", skip(transaction))]
+async fn mark_published(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+    title: &str,
+) -> Result<(), sqlx::Error> {
+    let slug = IssueSlug::from_title(title);
+    // A savepoint: the failed attempt must not abort the whole transaction.
+    let mut attempt = Connection::begin(&mut **transaction).await?;
+    match set_published(&mut attempt, newsletter_issue_id, &slug).await {
+        Ok(()) => return attempt.commit().await,
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => attempt.rollback().await?,
+        Err(e) => return Err(e),
+    }
+    let slug = slug.with_suffix(&newsletter_issue_id.simple().to_string()[..8]);
+    set_published(transaction, newsletter_issue_id, &slug).await
+}
+
+async fn set_published(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+    slug: &IssueSlug,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"UPDATE newsletter_issues
+        SET status = 'published', slug = $2, published_at = now(), updated_at = now()
+        WHERE newsletter_issue_id = $1"#,
+        newsletter_issue_id,
+        slug.as_ref(),
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
+
+/// Publish the issue and queue one delivery per confirmed subscriber.
+/// The emails are sent by the `issue_delivery_worker`.
+#[tracing::instrument(name = "Publish a newsletter issue", skip(db_pool))]
+pub async fn publish_newsletter(
+    newsletter_issue_id: web::Path<Uuid>,
+    db_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, NewsletterError> {
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let mut transaction = db_pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let issue = lock_newsletter_issue(newsletter_issue_id, &mut transaction).await?;
+    if issue.status != "draft" {
+        return Err(NewsletterError::Conflict(
+            "The newsletter issue has already been published.".into(),
+        ));
+    }
+    if issue.previewed_revision != Some(issue.revision) {
+        return Err(NewsletterError::Conflict(
+            "The latest revision must be previewed before publishing.".into(),
+        ));
+    }
+
+    mark_published(&mut transaction, newsletter_issue_id, &issue.title)
+        .await
+        .context("Failed to mark the newsletter issue as published.")?;
+    enqueue_delivery_tasks(&mut transaction, newsletter_issue_id)
+        .await
+        .context("Failed to enqueue delivery tasks.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to publish a newsletter issue.")?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Show or hide an issue in the public web archive. Unlike the content, this can change after publishing.
+#[tracing::instrument(name = "Change the visibility of a newsletter issue", skip(body, db_pool))]
+pub async fn update_newsletter_visibility(
+    newsletter_issue_id: web::Path<Uuid>,
+    body: web::Json<VisibilityBody>,
+    db_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, NewsletterError> {
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let result = sqlx::query!(
+        r#"UPDATE newsletter_issues SET public = $2, updated_at = now()
+        WHERE newsletter_issue_id = $1"#,
+        newsletter_issue_id,
+        body.public,
+    )
+    .execute(db_pool.get_ref())
+    .await
+    .context("Failed to update the visibility of the newsletter issue.")?;
+    if result.rows_affected() == 0 {
+        return Err(NewsletterError::NotFound);
+    }
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[derive(thiserror::Error)]
+pub enum NewsletterError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("The newsletter issue does not exist.")]
+    NotFound,
+    #[error("{0}")]
+    Conflict(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for NewsletterError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for NewsletterError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            NewsletterError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            NewsletterError::NotFound => StatusCode::NOT_FOUND,
+            NewsletterError::Conflict(_) => StatusCode::CONFLICT,
+            NewsletterError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        problem_response(self)
+    }
+}
@@ -0,0 +1,83 @@
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse, ResponseError};
+use anyhow::Context;
+use sqlx::PgPool;
+use crate::privacy::{erase_personal_data, export_personal_data};
+use crate::problem_details::problem_response;
+use crate::suppressions::EmailHasher;
+use crate::routes::error_chain_fmt;
+
+/// The address is sent in the body rather than the URL, which ends up in access logs.
+#[derive(serde::Deserialize)]
+pub struct PrivacyRequestBody {
+    email: String,
+}
+
+impl PrivacyRequestBody {
+    fn email(&self) -> Result<&str, PrivacyRequestError> {
+        let email = self.email.trim();
+        if email.is_empty() {
+            return Err(PrivacyRequestError::ValidationError("The email must not be empty.".into()));
+        }
+        Ok(email)
+    }
+}
+
+#[derive(serde::Serialize)]
+struct ErasureReport {
+    subscriber_erased: bool,
+}
+
+/// Everything stored about an email address, to answer a data subject access request.
+#[tracing::instrument(name = "Answer a data subject access request", skip_all)]
+pub async fn personal_data_access(
+    body: web::Json<PrivacyRequestBody>,
+    db_pool: web::Data<PgPool>,
+    email_hasher: web::Data<EmailHasher>,
+) -> Result<HttpResponse, PrivacyRequestError> {
+    let personal_data = export_personal_data(&db_pool, &email_hasher, body.email()?)
+        .await
+        .context("Failed to export the personal data.")?;
+    Ok(HttpResponse::Ok().json(personal_data))
+}
+
+/// Erase everything stored about an email address, and make sure it is never mailed again.
+/// The address is suppressed even when it is not subscribed, so that it cannot be imported later.
+#[tracing::instrument(name = "Answer an erasure request", skip_all)]
+pub async fn personal_data_erasure(
+    body: web::Json<PrivacyRequestBody>,
+    db_pool: web::Data<PgPool>,
+    email_hasher: web::Data<EmailHasher>,
+) -> Result<HttpResponse, PrivacyRequestError> {
+    let subscriber_erased = erase_personal_data(&db_pool, &email_hasher, body.email()?)
+        .await
+        .context("Failed to erase the personal data.")?;
+    Ok(HttpResponse::Ok().json(ErasureReport { subscriber_erased }))
+}
+
+#[derive(thiserror::Error)]
+pub enum PrivacyRequestError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for PrivacyRequestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for PrivacyRequestError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            PrivacyRequestError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            PrivacyRequestError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        problem_response(self)
+    }
+}
@@ -0,0 +1,267 @@
+use actix_web::http::header::{ContentDisposition, DispositionParam, DispositionType};
+use actix_web::http::StatusCode;
+use actix_web::web::Bytes;
+use actix_web::{web, HttpResponse, ResponseError};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use futures_util::{stream, StreamExt, TryStreamExt};
+use sqlx::PgPool;
+use tokio_util::io::StreamReader;
+use crate::damain::{SubscriberEmail, SubscriberId, SubscriberName, SubscriptionStatus};
+use crate::import::{import_subscribers, ImportError, ImportMode};
+use crate::suppressions::EmailHasher;
+use crate::problem_details::{problem_response, ProblemDetails};
+use crate::routes::{error_chain_fmt, FieldError};
+use crate::subscribers::{
+    self, export_subscribers, ExportFormat, Subscriber, SubscriberCursor, SubscriberFilter, SubscriberUpdate,
+};
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 500;
+
+#[derive(serde::Deserialize, Debug)]
+pub struct SubscribersQuery {
+    status: Option<SubscriptionStatus>,
+    /// Subscribed at or after this time.
+    subscribed_from: Option<DateTime<Utc>>,
+    /// Subscribed strictly before this time.
+    subscribed_to: Option<DateTime<Utc>>,
+    /// Searched for in the email and the name, ignoring case.
+    q: Option<String>,
+    /// Return the subscribers that come after this cursor, the `next_cursor` of the previous page.
+    after: Option<SubscriberCursor>,
+    limit: Option<i64>,
+}
+
+#[derive(serde::Serialize)]
+pub struct SubscribersPage {
+    subscribers: Vec<Subscriber>,
+    next_cursor: Option<SubscriberCursor>,
+}
+
+/// A page of subscribers, most recent first. Pass `next_cursor` back as `after` to fetch the next page.
+#[tracing::instrument(name = "List subscribers", skip(db_pool))]
+pub async fn list_subscribers(
+    query: web::Query<SubscribersQuery>,
+    db_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, SubscriberError> {
+    let SubscribersQuery {
+        status,
+        subscribed_from,
+        subscribed_to,
+        q,
+        after,
+        limit,
+    } = query.into_inner();
+    let limit = limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+    let filter = SubscriberFilter {
+        status,
+        subscribed_from,
+        subscribed_to,
+        search: q.map(|q| q.trim().to_owned()).filter(|q| !q.is_empty()),
+    };
+
+    // Fetch one extra row to know whether there is a next page.
+    let mut subscribers = subscribers::search_subscribers(&db_pool, &filter, after, limit + 1)
+        .await
+        .context("Failed to fetch the subscribers.")?;
+    let next_cursor = if subscribers.len() as i64 > limit {
+        subscribers.truncate(limit as usize);
+        subscribers.last().map(SubscriberCursor::from)
+    } else {
+        None
+    };
+    Ok(HttpResponse::Ok().json(SubscribersPage { subscribers, next_cursor }))
+}
+
+#[tracing::instrument(name = "Fetch a subscriber", skip(db_pool))]
+pub async fn get_subscriber(
+    subscriber_id: web::Path<SubscriberId>,
+    db_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, SubscriberError> {
+    let subscriber = subscribers::get_subscriber(&db_pool, subscriber_id.into_inner())
+        .await
+        .context("Failed to fetch the subscriber.")?
+        .ok_or(SubscriberError::NotFound)?;
+    Ok(HttpResponse::Ok().json(subscriber))
+}
+
+/// The fields of a subscriber an admin can change. Those left out are left as they are.
+#[derive(serde::Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct SubscriberPatch {
+    email: Option<String>,
+    name: Option<String>,
+    status: Option<SubscriptionStatus>,
+}
+
+impl TryFrom<SubscriberPatch> for SubscriberUpdate {
+    type Error = Vec<FieldError>;
+
+    fn try_from(patch: SubscriberPatch) -> Result<Self, Self::Error> {
+        let email = patch.email.map(SubscriberEmail::parse).transpose();
+        let name = patch.name.map(SubscriberName::parse).transpose();
+        match (email, name) {
+            (Ok(email), Ok(name)) => Ok(SubscriberUpdate {
+                email,
+                name,
+                status: patch.status,
+            }),
+            (email, name) => Err(email
+                .err()
+                .map(|e| FieldError::new("email", e))
+                .into_iter()
+                .chain(name.err().map(|e| FieldError::new("name", e)))
+                .collect()),
+        }
+    }
+}
+
+#[tracing::instrument(name = "Update a subscriber", skip(body, db_pool))]
+pub async fn update_subscriber(
+    subscriber_id: web::Path<SubscriberId>,
+    body: web::Json<SubscriberPatch>,
+    db_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, SubscriberError> {
+    let update = body.into_inner().try_into().map_err(SubscriberError::ValidationError)?;
+    let subscriber = match subscribers::update_subscriber(&db_pool, subscriber_id.into_inner(), update).await {
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+            return Err(SubscriberError::Conflict(
+                "Another subscriber already has this email.".into(),
+            ));
+        }
+        outcome => outcome.context("Failed to update the subscriber.")?,
+    };
+    Ok(HttpResponse::Ok().json(subscriber.ok_or(SubscriberError::NotFound)?))
+}
+
+/// Delete a subscriber along with their tokens, deliveries and tracking events.
+#[tracing::instrument(name = "Delete a subscriber", skip(db_pool))]
+pub async fn delete_subscriber(
+    subscriber_id: web::Path<SubscriberId>,
+    db_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, SubscriberError> {
+    let deleted = subscribers::delete_subscriber(&db_pool, subscriber_id.into_inner())
+        .await
+        .context("Failed to delete the subscriber.")?;
+    if !deleted {
+        return Err(SubscriberError::NotFound);
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct ImportQuery {
+    status: SubscriptionStatus,
+    /// Required when importing `confirmed` subscribers, e.g. "Signed up on our previous provider".
+    consent_source: Option<String>,
+}
+
+/// Import the subscribers of a CSV file, sent as the request body.
+/// The body is streamed: files with tens of thousands of rows never have to fit in memory.
+#[tracing::instrument(
+    name = "Import subscribers from a CSV file",
+    skip(payload, db_pool, email_hasher)
+)]
+pub async fn import_subscribers_from_csv(
+    query: web::Query<ImportQuery>,
+    payload: web::Payload,
+    db_pool: web::Data<PgPool>,
+    email_hasher: web::Data<EmailHasher>,
+) -> Result<HttpResponse, ImportError> {
+    let ImportQuery { status, consent_source } = query.into_inner();
+    let mode = ImportMode::new(status, consent_source)?;
+    let input = StreamReader::new(payload.map_err(std::io::Error::other));
+    let report = import_subscribers(&db_pool, &email_hasher, &mode, input).await?;
+    Ok(HttpResponse::Ok().json(report))
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct ExportQuery {
+    format: ExportFormat,
+    status: Option<SubscriptionStatus>,
+}
+
+/// Stream the subscribers as CSV or JSON Lines, batch by batch, as they are read from the database.
+/// There is a single mailing list for now, so the rows carry no list memberships.
+#[tracing::instrument(name = "Export subscribers", skip(db_pool))]
+pub async fn export_subscribers_as_file(
+    query: web::Query<ExportQuery>,
+    db_pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let ExportQuery { format, status } = query.into_inner();
+    let mut header = Vec::new();
+    format.write_header(&mut header).expect("Writing to a Vec cannot fail.");
+    let rows = export_subscribers(db_pool.get_ref().clone(), status).map(move |batch| {
+        let batch = batch.inspect_err(|e| {
+            // The response has started by now: all that is left to do is to cut it short.
+            tracing::error!(error.cause_chain = ?e, "Failed to fetch a batch of exported subscribers.");
+        })?;
+        let mut chunk = Vec::new();
+        format.write_subscribers(&mut chunk, &batch)?;
+        Ok::<_, anyhow::Error>(Bytes::from(chunk))
+    });
+    let body = stream::once(async { Ok(Bytes::from(header)) }).chain(rows);
+    let filename = format!("subscribers.{}", format.file_extension());
+    HttpResponse::Ok()
+        .content_type(format.content_type())
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(filename)],
+        })
+        .streaming(body.map_err(|e| -> Box<dyn std::error::Error> { e.into() }))
+}
+
+impl ResponseError for ImportError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ImportError::MissingConsentSource | ImportError::InvalidFile(_) => StatusCode::BAD_REQUEST,
+            ImportError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        problem_response(self)
+    }
+}
+
+#[derive(thiserror::Error)]
+pub enum SubscriberError {
+    #[error("{}", .0.iter().map(|e| e.message.as_str()).collect::<Vec<_>>().join(" "))]
+    ValidationError(Vec<FieldError>),
+    #[error("The subscriber does not exist.")]
+    NotFound,
+    #[error("{0}")]
+    Conflict(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for SubscriberError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for SubscriberError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            SubscriberError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            SubscriberError::NotFound => StatusCode::NOT_FOUND,
+            SubscriberError::Conflict(_) => StatusCode::CONFLICT,
+            SubscriberError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let problem = ProblemDetails::from_error(self);
+        match self {
+            SubscriberError::ValidationError(errors) => problem
+                .with_type("/problems/invalid-subscriber")
+                .with_title("The subscriber is invalid.")
+                .with_extension("errors", errors),
+            _ => problem,
+        }
+        .into_response()
+    }
+}
@@ -0,0 +1,177 @@
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse, ResponseError};
+use anyhow::Context;
+use sqlx::PgPool;
+use crate::authentication::UserId;
+use crate::issue_delivery_worker::mark_bounced;
+use crate::problem_details::problem_response;
+use crate::routes::error_chain_fmt;
+use crate::suppressions::{self, EmailHasher, Suppression, SuppressionReason};
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 500;
+
+#[derive(serde::Deserialize, Debug)]
+pub struct SuppressionsQuery {
+    reason: Option<SuppressionReason>,
+    /// Lifted suppressions are left out unless asked for.
+    #[serde(default)]
+    include_lifted: bool,
+    /// Return suppressions whose email hash comes after this one.
+    after: Option<String>,
+    limit: Option<i64>,
+}
+
+#[derive(serde::Serialize)]
+pub struct SuppressionsPage {
+    suppressions: Vec<Suppression>,
+    next_cursor: Option<String>,
+}
+
+/// Addresses are sent in the body rather than the URL, which ends up in access logs.
+#[derive(serde::Deserialize)]
+pub struct SuppressBody {
+    email: String,
+    reason: SuppressionReason,
+    /// For bounces: the `MessageID` of the email that bounced, as reported by the email provider.
+    message_id: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct LiftBody {
+    email: String,
+    /// Why the address can be mailed again, e.g. "The subscriber fixed their mailbox".
+    reason: String,
+}
+
+/// A page of the suppression list, ordered by email hash.
+/// Pass `next_cursor` back as `after` to fetch the next page.
+#[tracing::instrument(name = "List suppressions", skip(db_pool))]
+pub async fn list_suppressions(
+    query: web::Query<SuppressionsQuery>,
+    db_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, SuppressionError> {
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+    // Fetch one extra row to know whether there is a next page.
+    let mut suppressions = suppressions::list_suppressions(
+        &db_pool,
+        query.reason,
+        query.include_lifted,
+        query.after.as_deref(),
+        limit + 1,
+    )
+    .await
+    .context("Failed to fetch the suppressions.")?;
+    let next_cursor = if suppressions.len() as i64 > limit {
+        suppressions.truncate(limit as usize);
+        suppressions.last().map(|s| s.email_hash.clone())
+    } else {
+        None
+    };
+    Ok(HttpResponse::Ok().json(SuppressionsPage { suppressions, next_cursor }))
+}
+
+#[tracing::instrument(name = "Fetch a suppression", skip(db_pool))]
+pub async fn get_suppression(
+    email_hash: web::Path<String>,
+    db_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, SuppressionError> {
+    let suppression = suppressions::get_suppression(&db_pool, &email_hash)
+        .await
+        .context("Failed to fetch the suppression.")?
+        .ok_or(SuppressionError::NotFound)?;
+    Ok(HttpResponse::Ok().json(suppression))
+}
+
+/// Suppress an address, e.g. one that bounced or complained according to the email provider.
+/// A bounce also marks the delivery that bounced, so that newsletter reports count it.
+#[tracing::instrument(name = "Suppress an email address", skip_all, fields(reason = ?body.reason))]
+pub async fn create_suppression(
+    body: web::Json<SuppressBody>,
+    db_pool: web::Data<PgPool>,
+    email_hasher: web::Data<EmailHasher>,
+) -> Result<HttpResponse, SuppressionError> {
+    let email = body.email.trim();
+    if email.is_empty() {
+        return Err(SuppressionError::ValidationError("The email must not be empty.".into()));
+    }
+    if body.reason == SuppressionReason::Erased {
+        return Err(SuppressionError::ValidationError(
+            "Addresses are suppressed as `erased` by erasure requests only.".into(),
+        ));
+    }
+    let mut transaction = db_pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    suppressions::suppress(&mut *transaction, &email_hasher, email, body.reason)
+        .await
+        .context("Failed to suppress the email address.")?;
+    if body.reason == SuppressionReason::Bounced
+        && !mark_bounced(&mut transaction, email, body.message_id.as_deref())
+            .await
+            .context("Failed to mark the delivery that bounced.")?
+    {
+        tracing::info!("No delivery matches the bounce.");
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to suppress the email address.")?;
+    let suppression = suppressions::get_suppression(&db_pool, &email_hasher.hash(email))
+        .await
+        .context("Failed to fetch the suppression.")?
+        .context("The suppression vanished right after it was created.")?;
+    Ok(HttpResponse::Created().json(suppression))
+}
+
+/// Allow an address to be mailed again. The suppression is kept, along with who lifted it and why.
+#[tracing::instrument(name = "Lift a suppression", skip_all)]
+pub async fn lift_suppression(
+    body: web::Json<LiftBody>,
+    db_pool: web::Data<PgPool>,
+    email_hasher: web::Data<EmailHasher>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, SuppressionError> {
+    let reason = body.reason.trim();
+    if reason.is_empty() {
+        return Err(SuppressionError::ValidationError(
+            "A reason is required to lift a suppression.".into(),
+        ));
+    }
+    let suppression = suppressions::lift_suppression(&db_pool, &email_hasher, &body.email, reason, user_id.into_inner())
+        .await
+        .context("Failed to lift the suppression.")?
+        .ok_or(SuppressionError::NotFound)?;
+    Ok(HttpResponse::Ok().json(suppression))
+}
+
+#[derive(thiserror::Error)]
+pub enum SuppressionError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("The email address is not suppressed.")]
+    NotFound,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for SuppressionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for SuppressionError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            SuppressionError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            SuppressionError::NotFound => StatusCode::NOT_FOUND,
+            SuppressionError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        problem_response(self)
+    }
+}
@@ -0,0 +1,314 @@
+use std::fmt::Write;
+use std::time::SystemTime;
+use actix_web::http::header::{
+    ContentType, ETag, EntityTag, Header, HttpDate, IfModifiedSince, IfNoneMatch, LastModified,
+};
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use crate::problem_details::problem_response;
+use crate::routes::error_chain_fmt;
+use crate::startup::ApplicationBaseUrl;
+
+const NEWSLETTER_TITLE: &str = "Our newsletter";
+/// How many issues the feeds carry.
+const FEED_SIZE: i64 = 20;
+
+struct ArchivedIssue {
+    slug: String,
+    title: String,
+    html_content: String,
+    published_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+#[tracing::instrument(name = "Get archived issues", skip(db_pool))]
+async fn get_archived_issues(db_pool: &PgPool, limit: Option<i64>) -> Result<Vec<ArchivedIssue>, sqlx::Error> {
+    sqlx::query_as!(
+        ArchivedIssue,
+        r#"SELECT slug AS "slug!", title, html_content, published_at AS "published_at!", updated_at
+        FROM newsletter_issues
+        WHERE status = 'published' AND public AND slug IS NOT NULL
+        ORDER BY published_at DESC
+        LIMIT $1"#,
+        limit,
+    )
+    .fetch_all(db_pool)
+    .await
+}
+
+/// When did the content of the archive last change.
+/// Hidden issues are taken into account, so that hiding one moves the date forward.
+#[tracing::instrument(name = "Get the archive modification date", skip(db_pool))]
+async fn get_last_modified(db_pool: &PgPool) -> Result<Option<DateTime<Utc>>, sqlx::Error> {
+    sqlx::query_scalar!(
+        "SELECT max(updated_at) FROM newsletter_issues WHERE status = 'published'"
+    )
+    .fetch_one(db_pool)
+    .await
+}
+
+fn html_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Serve `body`, or a `304 Not Modified` if the client already has it.
+/// `If-None-Match` takes precedence over `If-Modified-Since`, as mandated by RFC 9110.
+fn conditional_response(
+    request: &HttpRequest,
+    content_type: ContentType,
+    body: String,
+    last_modified: Option<DateTime<Utc>>,
+) -> HttpResponse {
+    let etag = EntityTag::new_strong(hex::encode(Sha256::digest(body.as_bytes())));
+    let last_modified = last_modified.map(|t| {
+        // HTTP dates have a one second resolution.
+        HttpDate::from(SystemTime::from(DateTime::<Utc>::from_timestamp(t.timestamp(), 0).unwrap()))
+    });
+
+    let not_modified = if request.headers().contains_key(IfNoneMatch::name()) {
+        match IfNoneMatch::parse(request) {
+            Ok(IfNoneMatch::Any) => true,
+            Ok(IfNoneMatch::Items(tags)) => tags.iter().any(|tag| tag.weak_eq(&etag)),
+            Err(_) => false,
+        }
+    } else {
+        match (IfModifiedSince::parse(request), last_modified) {
+            (Ok(IfModifiedSince(since)), Some(last_modified)) => {
+                SystemTime::from(last_modified) <= SystemTime::from(since)
+            }
+            _ => false,
+        }
+    };
+
+    let mut response = if not_modified {
+        HttpResponse::NotModified()
+    } else {
+        HttpResponse::Ok()
+    };
+    response.insert_header(ETag(etag));
+    if let Some(last_modified) = last_modified {
+        response.insert_header(LastModified(last_modified));
+    }
+    if not_modified {
+        response.finish()
+    } else {
+        response.content_type(content_type).body(body)
+    }
+}
+
+fn html_page(title: &str, body: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n\
+        <link rel=\"alternate\" type=\"application/atom+xml\" href=\"/feed.xml\">\n</head>\n\
+        <body>\n{}\n</body>\n</html>\n",
+        html_escape(title),
+        body
+    )
+}
+
+#[tracing::instrument(name = "Render the newsletter archive", skip_all)]
+pub async fn archive_index(
+    request: HttpRequest,
+    db_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ArchiveError> {
+    let issues = get_archived_issues(&db_pool, None)
+        .await
+        .context("Failed to fetch the archived issues.")?;
+    let last_modified = get_last_modified(&db_pool)
+        .await
+        .context("Failed to fetch the archive modification date.")?;
+
+    let mut body = format!("<h1>{}</h1>\n<ul>\n", html_escape(NEWSLETTER_TITLE));
+    for issue in &issues {
+        writeln!(
+            body,
+            "<li><a href=\"/archive/{}\">{}</a> <time datetime=\"{}\">{}</time></li>",
+            html_escape(&issue.slug),
+            html_escape(&issue.title),
+            issue.published_at.to_rfc3339(),
+            issue.published_at.format("%B %-d, %Y"),
+        )
+        .unwrap();
+    }
+    body.push_str("</ul>");
+
+    Ok(conditional_response(
+        &request,
+        ContentType::html(),
+        html_page(NEWSLETTER_TITLE, &body),
+        last_modified,
+    ))
+}
+
+#[tracing::instrument(name = "Render an archived issue", skip(request, db_pool))]
+pub async fn archive_issue(
+    request: HttpRequest,
+    slug: web::Path<String>,
+    db_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ArchiveError> {
+    let issue = sqlx::query_as!(
+        ArchivedIssue,
+        r#"SELECT slug AS "slug!", title, html_content, published_at AS "published_at!", updated_at
+        FROM newsletter_issues
+        WHERE status = 'published' AND public AND slug = $1"#,
+        slug.into_inner(),
+    )
+    .fetch_optional(db_pool.get_ref())
+    .await
+    .context("Failed to fetch the archived issue.")?
+    .ok_or(ArchiveError::NotFound)?;
+
+    let body = format!(
+        "<article>\n<h1>{}</h1>\n<time datetime=\"{}\">{}</time>\n{}\n</article>\n\
+        <p><a href=\"/archive\">All issues</a></p>",
+        html_escape(&issue.title),
+        issue.published_at.to_rfc3339(),
+        issue.published_at.format("%B %-d, %Y"),
+        issue.html_content,
+    );
+    Ok(conditional_response(
+        &request,
+        ContentType::html(),
+        html_page(&issue.title, &body),
+        Some(issue.updated_at),
+    ))
+}
+
+#[tracing::instrument(name = "Render the Atom feed", skip_all)]
+pub async fn atom_feed(
+    request: HttpRequest,
+    db_pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, ArchiveError> {
+    let base_url = &base_url.0;
+    let issues = get_archived_issues(&db_pool, Some(FEED_SIZE))
+        .await
+        .context("Failed to fetch the archived issues.")?;
+    let last_modified = get_last_modified(&db_pool)
+        .await
+        .context("Failed to fetch the archive modification date.")?;
+
+    let mut feed = format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+        <feed xmlns=\"http://www.w3.org/2005/Atom\">\n\
+        <title>{title}</title>\n\
+        <id>{base_url}/archive</id>\n\
+        <link rel=\"self\" href=\"{base_url}/feed.xml\"/>\n\
+        <link rel=\"alternate\" type=\"text/html\" href=\"{base_url}/archive\"/>\n\
+        <author><name>{title}</name></author>\n\
+        <updated>{updated}</updated>\n",
+        title = html_escape(NEWSLETTER_TITLE),
+        base_url = html_escape(base_url),
+        updated = last_modified.unwrap_or(DateTime::UNIX_EPOCH).to_rfc3339(),
+    );
+    for issue in &issues {
+        let link = html_escape(&format!("{}/archive/{}", base_url, issue.slug));
+        writeln!(
+            feed,
+            "<entry>\n<title>{}</title>\n<id>{link}</id>\n<link rel=\"alternate\" href=\"{link}\"/>\n\
+            <published>{}</published>\n<updated>{}</updated>\n<content type=\"html\">{}</content>\n</entry>",
+            html_escape(&issue.title),
+            issue.published_at.to_rfc3339(),
+            issue.updated_at.to_rfc3339(),
+            html_escape(&issue.html_content),
+        )
+        .unwrap();
+    }
+    feed.push_str("</feed>\n");
+
+    Ok(conditional_response(
+        &request,
+        ContentType("application/atom+xml; charset=utf-8".parse().unwrap()),
+        feed,
+        last_modified,
+    ))
+}
+
+#[tracing::instrument(name = "Render the RSS feed", skip_all)]
+pub async fn rss_feed(
+    request: HttpRequest,
+    db_pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, ArchiveError> {
+    let base_url = &base_url.0;
+    let issues = get_archived_issues(&db_pool, Some(FEED_SIZE))
+        .await
+        .context("Failed to fetch the archived issues.")?;
+    let last_modified = get_last_modified(&db_pool)
+        .await
+        .context("Failed to fetch the archive modification date.")?;
+
+    let mut feed = format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+        <rss version=\"2.0\">\n<channel>\n\
+        <title>{title}</title>\n\
+        <link>{base_url}/archive</link>\n\
+        <description>{title}</description>\n\
+        <lastBuildDate>{updated}</lastBuildDate>\n",
+        title = html_escape(NEWSLETTER_TITLE),
+        base_url = html_escape(base_url),
+        updated = last_modified.unwrap_or(DateTime::UNIX_EPOCH).to_rfc2822(),
+    );
+    for issue in &issues {
+        let link = html_escape(&format!("{}/archive/{}", base_url, issue.slug));
+        writeln!(
+            feed,
+            "<item>\n<title>{}</title>\n<link>{link}</link>\n<guid isPermaLink=\"true\">{link}</guid>\n\
+            <pubDate>{}</pubDate>\n<description>{}</description>\n</item>",
+            html_escape(&issue.title),
+            issue.published_at.to_rfc2822(),
+            html_escape(&issue.html_content),
+        )
+        .unwrap();
+    }
+    feed.push_str("</channel>\n</rss>\n");
+
+    Ok(conditional_response(
+        &request,
+        ContentType("application/rss+xml; charset=utf-8".parse().unwrap()),
+        feed,
+        last_modified,
+    ))
+}
+
+#[derive(thiserror::Error)]
+pub enum ArchiveError {
+    #[error("The newsletter issue does not exist.")]
+    NotFound,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for ArchiveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for ArchiveError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ArchiveError::NotFound => StatusCode::NOT_FOUND,
+            ArchiveError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        problem_response(self)
+    }
+}
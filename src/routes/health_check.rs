@@ -1,5 +1,164 @@
-use actix_web::{HttpResponse, Responder};
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::time::{Duration, Instant};
+use actix_web::{web, HttpResponse, Responder};
+use sqlx::PgPool;
+use crate::configuration::ReadinessSettings;
+use crate::email_client::EmailClient;
+use crate::startup::MIGRATOR;
 
+/// Liveness: the process is up and serving requests. It does not look at our dependencies,
+/// we would not want to be restarted because Postgres is down.
 pub async fn health_check() -> impl Responder {
     HttpResponse::Ok()
-}
\ No newline at end of file
+}
+
+/// Readiness: we can do our job, so we should be sent traffic.
+/// Returns a 503 when a critical component is down.
+#[tracing::instrument(name = "Check readiness", skip_all)]
+pub async fn ready(
+    db_pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    settings: web::Data<ReadinessSettings>,
+) -> HttpResponse {
+    let timeout = settings.timeout();
+    let mut components = BTreeMap::new();
+    components.insert("database", check(timeout, true, check_database(&db_pool)).await);
+    components.insert("migrations", check(timeout, true, check_migrations(&db_pool)).await);
+    if settings.probe_email_api {
+        components.insert("email_api", check(timeout, false, check_email_api(&email_client)).await);
+    }
+
+    let is_ready = components
+        .values()
+        .all(|component| !component.critical || component.status == ComponentStatus::Up);
+    let report = ReadinessReport {
+        status: if is_ready { "ready" } else { "unavailable" },
+        components,
+    };
+    if is_ready {
+        HttpResponse::Ok().json(report)
+    } else {
+        HttpResponse::ServiceUnavailable().json(report)
+    }
+}
+
+#[derive(serde::Serialize)]
+struct ReadinessReport {
+    status: &'static str,
+    components: BTreeMap<&'static str, ComponentReport>,
+}
+
+#[derive(serde::Serialize)]
+struct ComponentReport {
+    status: ComponentStatus,
+    critical: bool,
+    latency_ms: u128,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    /// The migrations embedded in the binary that have not been applied to the database.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pending: Option<Vec<i64>>,
+}
+
+#[derive(serde::Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum ComponentStatus {
+    Up,
+    Down,
+}
+
+/// The outcome of a check: `Err` holds why the component is down.
+type CheckOutcome = Result<(), CheckFailure>;
+
+/// `/ready` is not authenticated: the response only says what kind of failure it was,
+/// the error itself, which can name hosts and users, goes to the logs.
+struct CheckFailure {
+    reason: String,
+    cause: Option<anyhow::Error>,
+    pending: Option<Vec<i64>>,
+}
+
+impl CheckFailure {
+    fn unreachable(cause: impl Into<anyhow::Error>) -> Self {
+        Self { reason: "unreachable".into(), cause: Some(cause.into()), pending: None }
+    }
+}
+
+async fn check(
+    timeout: Duration,
+    critical: bool,
+    check: impl Future<Output = CheckOutcome>,
+) -> ComponentReport {
+    let start = Instant::now();
+    let outcome = match tokio::time::timeout(timeout, check).await {
+        Ok(outcome) => outcome,
+        Err(_) => Err(CheckFailure {
+            reason: "timeout".into(),
+            cause: Some(anyhow::anyhow!("No answer within {}ms.", timeout.as_millis())),
+            pending: None,
+        }),
+    };
+    let latency_ms = start.elapsed().as_millis();
+    match outcome {
+        Ok(()) => ComponentReport {
+            status: ComponentStatus::Up,
+            critical,
+            latency_ms,
+            error: None,
+            pending: None,
+        },
+        Err(CheckFailure { reason, cause, pending }) => {
+            match cause {
+                Some(cause) => tracing::warn!(
+                    error.cause_chain = ?cause,
+                    error.message = %cause,
+                    "A dependency is not ready: {}.", reason
+                ),
+                None => tracing::warn!("A dependency is not ready: {}.", reason),
+            }
+            ComponentReport {
+                status: ComponentStatus::Down,
+                critical,
+                latency_ms,
+                error: Some(reason),
+                pending,
+            }
+        }
+    }
+}
+
+async fn check_database(db_pool: &PgPool) -> CheckOutcome {
+    sqlx::query("SELECT 1")
+        .execute(db_pool)
+        .await
+        .map(|_| ())
+        .map_err(CheckFailure::unreachable)
+}
+
+/// A schema lagging behind the code would fail our queries: we are not ready until it is migrated.
+async fn check_migrations(db_pool: &PgPool) -> CheckOutcome {
+    let applied: Vec<i64> = sqlx::query_scalar("SELECT version FROM _sqlx_migrations WHERE success")
+        .fetch_all(db_pool)
+        .await
+        .map_err(CheckFailure::unreachable)?;
+    let pending: Vec<i64> = MIGRATOR
+        .iter()
+        .filter(|migration| !migration.migration_type.is_down_migration())
+        .map(|migration| migration.version)
+        .filter(|version| !applied.contains(version))
+        .collect();
+    if pending.is_empty() {
+        Ok(())
+    } else {
+        Err(CheckFailure {
+            reason: format!("{} migrations are pending", pending.len()),
+            cause: None,
+            pending: Some(pending),
+        })
+    }
+}
+
+async fn check_email_api(email_client: &EmailClient) -> CheckOutcome {
+    email_client.probe().await.map_err(CheckFailure::unreachable)
+}
@@ -0,0 +1,37 @@
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse, ResponseError};
+use anyhow::Context;
+use crate::metrics::PrometheusExporter;
+use crate::problem_details::problem_response;
+use crate::routes::error_chain_fmt;
+
+pub async fn prometheus_metrics(
+    exporter: web::Data<PrometheusExporter>,
+) -> Result<HttpResponse, MetricsError> {
+    let body = exporter.render().context("Failed to collect the metrics.")?;
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4; charset=utf-8")
+        .body(body))
+}
+
+#[derive(thiserror::Error)]
+pub enum MetricsError {
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for MetricsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for MetricsError {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::INTERNAL_SERVER_ERROR
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        problem_response(self)
+    }
+}
@@ -1,7 +1,15 @@
+mod admin;
+mod archive;
 mod health_check;
+mod metrics;
 mod subscriptions;
 mod subscriptions_confirm;
+mod tracking;
 
+pub use admin::*;
+pub use archive::*;
 pub use health_check::*;
+pub use metrics::*;
 pub use subscriptions::*;
-pub use subscriptions_confirm::*;
\ No newline at end of file
+pub use subscriptions_confirm::*;
+pub use tracking::*;
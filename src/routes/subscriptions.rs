@@ -1,9 +1,17 @@
+use crate::bot_protection::{BotCheckError, BotCheckFields, BotProtection, FormTokens, HONEYPOT_FIELD};
 use crate::damain::SubscriberName;
-use crate::damain::{NewSubscriber, SubscriberEmail};
-use crate::email_client::EmailClient;
+use crate::damain::{NewSubscriber, SubscriberEmail, ValidationError};
+use crate::deliverability::DeliverabilityChecker;
+use crate::email_client::{EmailClient, SendEmailError};
+use crate::metrics::{EmailKind, Metrics};
+use crate::problem_details::ProblemDetails;
+use crate::rate_limit::{Decision, RateLimiter, TooManyRequests};
 use crate::startup::ApplicationBaseUrl;
-use actix_web::web::Form;
-use actix_web::{HttpResponse, ResponseError, web};
+use crate::suppressions::{is_suppressed, EmailHasher};
+use actix_web::web::{Form, Json};
+use actix_web::http::StatusCode;
+use actix_web::http::header::CACHE_CONTROL;
+use actix_web::{Either, HttpResponse, ResponseError, web};
 use anyhow::Context;
 use rand::distr::Alphanumeric;
 use rand::{Rng, rng};
@@ -16,20 +24,92 @@ use uuid::Uuid;
 pub struct FormData {
     name: String,
     email: String,
+    /// The honeypot, hidden from people by the form.
+    website: Option<String>,
+    form_token: Option<String>,
+    captcha_response: Option<String>,
+}
+
+impl FormData {
+    fn bot_check_fields(&self) -> BotCheckFields<'_> {
+        BotCheckFields {
+            honeypot: self.website.as_deref(),
+            form_token: self.form_token.as_deref(),
+            captcha_response: self.captcha_response.as_deref(),
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
+struct SubscriptionForm {
+    form_token: String,
+    honeypot_field: &'static str,
+    min_fill_seconds: i64,
+    captcha_required: bool,
+}
+
+/// What a signup form needs to pass the bot checks: a fresh form token, and how to use it.
+#[tracing::instrument(name = "Issue a signup form token", skip_all)]
+pub async fn subscription_form(
+    form_tokens: web::Data<FormTokens>,
+    bot_protection: web::Data<BotProtection>,
+) -> HttpResponse {
+    HttpResponse::Ok()
+        .insert_header((CACHE_CONTROL, "no-store"))
+        .json(SubscriptionForm {
+            form_token: form_tokens.issue(Utc::now()),
+            honeypot_field: HONEYPOT_FIELD,
+            min_fill_seconds: form_tokens.min_fill_time().num_seconds(),
+            captcha_required: bot_protection.captcha_required(),
+        })
+}
+
+/// A validation failure, attributed to the field of the submitted payload that caused it.
+#[derive(Debug, serde::Serialize)]
+pub struct FieldError {
+    pub field: &'static str,
+    pub code: &'static str,
+    pub message: String,
+    /// A corrected value the user can resubmit, e.g. for a mistyped email domain.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub suggestion: Option<String>,
+}
+
+impl FieldError {
+    pub fn new(field: &'static str, error: ValidationError) -> Self {
+        Self {
+            field,
+            code: error.code(),
+            message: format!("The {} {}.", field, error),
+            suggestion: error.suggestion().map(str::to_owned),
+        }
+    }
 }
 
 impl TryFrom<FormData> for NewSubscriber {
-    type Error = String;
+    type Error = Vec<FieldError>;
 
     fn try_from(form: FormData) -> Result<Self, Self::Error> {
-        Ok(NewSubscriber {
-            email: SubscriberEmail::parse(form.email)?,
-            name: SubscriberName::parse(form.name)?,
-        })
+        parse_new_subscriber(form.email, form.name)
     }
 }
 
-fn generate_subscription_token() -> String {
+/// Every field is validated, so that all problems can be reported at once.
+pub fn parse_new_subscriber(email: String, name: String) -> Result<NewSubscriber, Vec<FieldError>> {
+    let email = SubscriberEmail::parse(email);
+    let name = SubscriberName::parse(name);
+    match (email, name) {
+        (Ok(email), Ok(name)) => Ok(NewSubscriber { email, name }),
+        (email, name) => Err(email
+            .err()
+            .map(|e| FieldError::new("email", e))
+            .into_iter()
+            .chain(name.err().map(|e| FieldError::new("name", e)))
+            .collect()),
+    }
+}
+
+pub fn generate_subscription_token() -> String {
     let mut rng = rng();
     std::iter::repeat_with(|| rng.sample(Alphanumeric))
         .map(char::from)
@@ -37,6 +117,7 @@ fn generate_subscription_token() -> String {
         .collect()
 }
 
+/// `None` when the address is already taken, in whatever case or spelling of its domain.
 #[tracing::instrument(
     name = "Saving new subscriber details in the database",
     skip(new_subscriber, transaction)
@@ -44,18 +125,20 @@ fn generate_subscription_token() -> String {
 pub async fn insert_subscriber(
     new_subscriber: &NewSubscriber,
     transaction: &mut Transaction<'_, Postgres>,
-) -> Result<Uuid, sqlx::Error> {
-    let subscriber_id = Uuid::new_v4();
-    sqlx::query!(
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let subscriber_id = sqlx::query_scalar!(
         "INSERT INTO
-            subscriptions (id, name, email, subscribed_at, status)
-        VALUES ($1, $2, $3, $4, 'pending_confirmation')",
-        subscriber_id,
+            subscriptions (id, name, email, email_normalized, subscribed_at, status)
+        VALUES ($1, $2, $3, $4, $5, 'pending_confirmation')
+        ON CONFLICT (email_normalized) DO NOTHING
+        RETURNING id",
+        Uuid::new_v4(),
         new_subscriber.name.as_ref(),
         new_subscriber.email.as_ref(),
+        new_subscriber.email.normalized(),
         Utc::now()
     )
-    .execute(&mut **transaction)
+    .fetch_optional(&mut **transaction)
     .await
     .map_err(|e| {
         tracing::error!("Failed to execute query: {:?}", e);
@@ -64,6 +147,22 @@ pub async fn insert_subscriber(
     Ok(subscriber_id)
 }
 
+/// The id of the subscriber already using the address, if they have yet to confirm it.
+#[tracing::instrument(name = "Find the pending subscriber of an address", skip(email, transaction))]
+pub async fn find_pending_subscriber(
+    email: &SubscriberEmail,
+    transaction: &mut Transaction<'_, Postgres>,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    sqlx::query_scalar!(
+        "SELECT id FROM subscriptions
+        WHERE email_normalized = $1 AND status = 'pending_confirmation'
+        FOR UPDATE",
+        email.normalized()
+    )
+    .fetch_optional(&mut **transaction)
+    .await
+}
+
 #[tracing::instrument(
     name = "Send a confirmation email to a new subscriber",
     skip(email_client, new_subscriber)
@@ -73,7 +172,7 @@ pub async fn send_confirmation_email(
     new_subscriber: NewSubscriber,
     base_url: &str,
     token: &str,
-) -> Result<(), reqwest::Error> {
+) -> Result<(), SendEmailError> {
     let confirmation_link = format!("{}/subscriptions/confirm?token={}", base_url, token);
     email_client
         .send_email(
@@ -89,7 +188,8 @@ pub async fn send_confirmation_email(
                 confirmation_link
             ),
         )
-        .await
+        .await?;
+    Ok(())
 }
 
 #[tracing::instrument(
@@ -116,33 +216,82 @@ pub async fn store_token(
     Ok(())
 }
 
+/// Accepts both `application/x-www-form-urlencoded` and `application/json` payloads.
+#[allow(clippy::too_many_arguments)]
 #[tracing::instrument(
     name = "Adding a new subscriber",
-    skip(form, db_pool, email_client, base_url),
+    skip(payload, db_pool, email_client, email_hasher, bot_protection, deliverability, rate_limiter, base_url, metrics),
     fields(
-        subscriber_email = %form.email,
-        subscriber_name= %form.name
+        subscriber_email = tracing::field::Empty,
+        subscriber_name = tracing::field::Empty
     )
 )]
-
 pub async fn subscriptions(
-    Form(form): Form<FormData>,
+    payload: Either<Json<FormData>, Form<FormData>>,
     db_pool: web::Data<PgPool>,
     email_client: web::Data<EmailClient>,
+    email_hasher: web::Data<EmailHasher>,
+    bot_protection: web::Data<BotProtection>,
+    deliverability: web::Data<DeliverabilityChecker>,
+    rate_limiter: web::Data<RateLimiter>,
     base_url: web::Data<ApplicationBaseUrl>,
+    metrics: web::Data<Metrics>,
 ) -> Result<HttpResponse, SubscribeError> {
+    let form = payload.into_inner();
+    tracing::Span::current()
+        .record("subscriber_email", tracing::field::display(&form.email))
+        .record("subscriber_name", tracing::field::display(&form.name));
+
+    let bot_check_fields = form.bot_check_fields();
+    if bot_protection.caught_in_honeypot(&bot_check_fields) {
+        tracing::info!("Ignoring a subscription request that filled the honeypot in.");
+        return Ok(HttpResponse::Ok().finish());
+    }
+    let form_token = bot_protection.check(&bot_check_fields).await?;
+
+    let new_subscriber: NewSubscriber = form
+        .try_into()
+        .map_err(SubscribeError::ValidationError)?;
+    deliverability
+        .check(&new_subscriber.email)
+        .await
+        .map_err(|e| SubscribeError::ValidationError(vec![FieldError::new("email", e)]))?;
+
+    // Answer as if the subscription went through: whether an address is suppressed is nobody's business.
+    if is_suppressed(db_pool.get_ref(), &email_hasher, new_subscriber.email.as_ref())
+        .await
+        .context("Failed to check the suppression list.")?
+    {
+        tracing::info!("Ignoring a subscription request from a suppressed address.");
+        return Ok(HttpResponse::Ok().finish());
+    }
+    // Only now: a submission rejected for its fields can be fixed and sent again with the same token.
+    bot_protection.redeem(form_token).await?;
+
     let mut transaction = db_pool
         .begin()
         .await
         .context("Failed to acquire a Postgres connection from the pool")?;
-    let new_subscriber = form
-        .try_into()
-        .map_err(|e| SubscribeError::ValidationError(e))?;
     let token = generate_subscription_token();
 
-    let subscriber_id = insert_subscriber(&new_subscriber, &mut transaction)
+    let inserted = insert_subscriber(&new_subscriber, &mut transaction)
         .await
         .context("Failed to insert new subscriber in the database.")?;
+    // Signing up again before confirming sends a fresh confirmation email. A confirmed subscriber
+    // gets the same answer, without an email: whether an address is subscribed is nobody's business.
+    let subscriber_id = match inserted {
+        Some(subscriber_id) => subscriber_id,
+        None => match find_pending_subscriber(&new_subscriber.email, &mut transaction)
+            .await
+            .context("Failed to look up the existing subscriber.")?
+        {
+            Some(subscriber_id) => subscriber_id,
+            None => {
+                tracing::info!("Ignoring a subscription request from an address that is already subscribed.");
+                return Ok(HttpResponse::Ok().finish());
+            }
+        },
+    };
     store_token(&mut transaction, subscriber_id, &token)
         .await
         .context("Failed to store the confirmation token for a new subscriber.")?;
@@ -151,10 +300,23 @@ pub async fn subscriptions(
         .commit()
         .await
         .context("Failed to commit SQL transaction to store a new subscriber.")?;
+    if inserted.is_some() {
+        metrics.subscription_created();
+    }
 
-    send_confirmation_email(&email_client, new_subscriber, &base_url.0, &token)
-        .await
-        .context("Failed to send a confirmation email.")?;
+    // Only the requests that send an email are charged, once the transaction is over: the Postgres
+    // store takes a connection of its own. A limited request leaves a token nobody received,
+    // and signing up again once the limit allows sends a fresh one.
+    if let Decision::Limited { retry_after } = rate_limiter.check_confirmation_email(&new_subscriber.email).await {
+        return Err(TooManyRequests { retry_after }.into());
+    }
+
+    let outcome = send_confirmation_email(&email_client, new_subscriber, &base_url.0, &token).await;
+    match outcome {
+        Ok(()) => metrics.email_sent(EmailKind::Confirmation),
+        Err(_) => metrics.email_failed(EmailKind::Confirmation),
+    }
+    outcome.context("Failed to send a confirmation email.")?;
 
     Ok(HttpResponse::Ok().finish())
 }
@@ -202,20 +364,53 @@ impl Debug for SubscribeError {
 }
 
 impl ResponseError for SubscribeError {
-    fn status_code(&self) -> actix_web::http::StatusCode {
+    fn status_code(&self) -> StatusCode {
         match self {
-            SubscribeError::ValidationError(_) => actix_web::http::StatusCode::BAD_REQUEST,
-            SubscribeError::UnexpectedError(_) => {
-                actix_web::http::StatusCode::INTERNAL_SERVER_ERROR
-            }
+            SubscribeError::ValidationError(_) | SubscribeError::BotCheckFailed(_) => StatusCode::BAD_REQUEST,
+            SubscribeError::TooManyRequests(e) => e.status_code(),
+            SubscribeError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        if let SubscribeError::TooManyRequests(e) = self {
+            return e.error_response();
         }
+        let problem = ProblemDetails::from_error(self);
+        match self {
+            SubscribeError::ValidationError(errors) => problem
+                .with_type("/problems/invalid-subscriber")
+                .with_title("Your subscription request is invalid.")
+                .with_extension("errors", errors),
+            SubscribeError::BotCheckFailed(_) => problem
+                .with_type("/problems/bot-check-failed")
+                .with_title("Your subscription request could not be told apart from a bot's."),
+            SubscribeError::TooManyRequests(_) | SubscribeError::UnexpectedError(_) => problem,
+        }
+        .into_response()
     }
 }
 
 #[derive(thiserror::Error)]
 pub enum SubscribeError {
-    #[error("{0}")]
-    ValidationError(String),
-    #[error("transparent")]
+    #[error("{}", .0.iter().map(|e| e.message.as_str()).collect::<Vec<_>>().join(" "))]
+    ValidationError(Vec<FieldError>),
+    #[error(transparent)]
+    BotCheckFailed(BotCheckError),
+    #[error(transparent)]
+    TooManyRequests(#[from] TooManyRequests),
+    #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
+
+impl From<BotCheckError> for SubscribeError {
+    fn from(e: BotCheckError) -> Self {
+        match e {
+            // The provider being down is our problem, not the subscriber's.
+            BotCheckError::CaptchaUnavailable(_) | BotCheckError::FormTokenUnrecorded(_) => {
+                SubscribeError::UnexpectedError(e.into())
+            }
+            e => SubscribeError::BotCheckFailed(e),
+        }
+    }
+}
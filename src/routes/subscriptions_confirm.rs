@@ -4,6 +4,9 @@ use anyhow::Context;
 use serde::Deserialize;
 use sqlx::PgPool;
 use crate::damain::SubscriberId;
+use crate::metrics::Metrics;
+use crate::problem_details::problem_response;
+use crate::startup::SubscriptionTokenTtl;
 
 
 #[derive(Deserialize, Debug)]
@@ -12,19 +15,22 @@ pub struct Token {
 }
 
 
+/// Tokens older than `ttl` are as good as unknown, whether or not they were purged yet.
 #[tracing::instrument(
     name = "Get subscriber_id from token",
     skip(db_pool),
 )]
-async fn get_subscriber_id(token: String, db_pool: &PgPool) -> Result<SubscriberId, sqlx::Error> {
+async fn get_subscriber_id(token: String, ttl: chrono::Duration, db_pool: &PgPool) -> Result<Option<SubscriberId>, sqlx::Error> {
     let subscription_token = sqlx::query!(
-        "SELECT subscriber_id FROM subscription_tokens WHERE subscription_token = $1", token
+        "SELECT subscriber_id FROM subscription_tokens WHERE subscription_token = $1 AND created_at > $2",
+        token,
+        chrono::Utc::now() - ttl,
     )
-        .fetch_one(db_pool)
+        .fetch_optional(db_pool)
         .await
         .map_err(|e| {tracing::error!("Failed to execute query: {:?}", e); e})?;
 
-    Ok(SubscriberId::new(subscription_token.subscriber_id))
+    Ok(subscription_token.map(|t| SubscriberId::new(t.subscriber_id)))
 }
 
 #[tracing::instrument(
@@ -33,7 +39,7 @@ async fn get_subscriber_id(token: String, db_pool: &PgPool) -> Result<Subscriber
 )]
 async fn update_subscriber(subscriber_id: SubscriberId, db_pool: &PgPool) -> Result<(), sqlx::Error> {
     sqlx::query!(
-        "UPDATE subscriptions SET status = 'confirmed' WHERE id = $1", subscriber_id.inner()
+        "UPDATE subscriptions SET status = 'confirmed', confirmed_at = COALESCE(confirmed_at, now()) WHERE id = $1", subscriber_id.inner()
     )
         .execute(db_pool)
         .await
@@ -43,20 +49,29 @@ async fn update_subscriber(subscriber_id: SubscriberId, db_pool: &PgPool) -> Res
 
 #[tracing::instrument(
     name = "Confirm a pending subscriber",
-    skip(db_pool),
+    skip(db_pool, ttl, metrics),
 )]
-pub async fn subscriptions_confirm(web::Query(token): web::Query<Token>, db_pool: web::Data<PgPool>) -> Result<HttpResponse, ConfirmError> {
-    let subscriber_id = get_subscriber_id(token.token, &db_pool).await.map_err(|e| ConfirmError::InvalidToken(e.to_string()))?;
+pub async fn subscriptions_confirm(
+    web::Query(token): web::Query<Token>,
+    db_pool: web::Data<PgPool>,
+    ttl: web::Data<SubscriptionTokenTtl>,
+    metrics: web::Data<Metrics>,
+) -> Result<HttpResponse, ConfirmError> {
+    let subscriber_id = get_subscriber_id(token.token, ttl.0, &db_pool)
+        .await
+        .context("Failed to retrieve the subscriber associated with the provided token.")?
+        .ok_or(ConfirmError::InvalidToken)?;
     update_subscriber(subscriber_id, &db_pool).await.context("Failed to update subscriber")?;
+    metrics.subscription_confirmed();
     Ok(HttpResponse::Ok().finish())
 }
 
 
 #[derive(thiserror::Error)]
 pub enum ConfirmError {
-    #[error("{0}")]
-    InvalidToken(String),
-    #[error("transparent")]
+    #[error("The confirmation token is invalid.")]
+    InvalidToken,
+    #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error)
 }
 
@@ -69,8 +84,12 @@ impl std::fmt::Debug for ConfirmError {
 impl ResponseError for ConfirmError {
     fn status_code(&self) -> StatusCode {
         match self {
-            ConfirmError::InvalidToken(_) => StatusCode::BAD_REQUEST,
+            ConfirmError::InvalidToken => StatusCode::BAD_REQUEST,
             _ => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
+
+    fn error_response(&self) -> HttpResponse {
+        problem_response(self)
+    }
 }
\ No newline at end of file
@@ -0,0 +1,156 @@
+use actix_web::http::header::{self, CacheControl, CacheDirective, ContentType};
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse, ResponseError};
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+use crate::problem_details::problem_response;
+use crate::routes::error_chain_fmt;
+use crate::tracking::{LinkTracker, TrackingKind, TrackingToken};
+
+/// A transparent 1x1 GIF.
+const TRACKING_PIXEL: &[u8] = &[
+    0x47, 0x49, 0x46, 0x38, 0x39, 0x61, 0x01, 0x00, 0x01, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0xff, 0xff, 0xff, 0x21, 0xf9, 0x04, 0x01, 0x00, 0x00, 0x00, 0x00, 0x2c, 0x00, 0x00, 0x00, 0x00,
+    0x01, 0x00, 0x01, 0x00, 0x00, 0x02, 0x02, 0x44, 0x01, 0x00, 0x3b,
+];
+
+fn verify_token(
+    link_tracker: &LinkTracker,
+    token: &str,
+    expected_kind: TrackingKind,
+) -> Result<TrackingToken, TrackingError> {
+    let token = link_tracker
+        .verify(token)
+        .map_err(|_| TrackingError::InvalidToken)?;
+    if token.kind != expected_kind {
+        return Err(TrackingError::InvalidToken);
+    }
+    Ok(token)
+}
+
+/// Store an engagement event, unless the subscriber opted out of tracking.
+#[tracing::instrument(name = "Record a newsletter event", skip(token, db_pool))]
+async fn record_event(token: &TrackingToken, db_pool: &PgPool) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"INSERT INTO newsletter_events (event_id, newsletter_issue_id, subscriber_id, kind, url, occurred_at)
+        SELECT $1, $2, id, $4, $5, now()
+        FROM subscriptions
+        WHERE id = $3 AND NOT tracking_opt_out"#,
+        Uuid::new_v4(),
+        token.newsletter_issue_id,
+        token.subscriber_id,
+        token.kind.as_str(),
+        token.url,
+    )
+    .execute(db_pool)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(name = "Track a newsletter click", skip(token, db_pool, link_tracker))]
+pub async fn track_click(
+    token: web::Path<String>,
+    db_pool: web::Data<PgPool>,
+    link_tracker: web::Data<LinkTracker>,
+) -> Result<HttpResponse, TrackingError> {
+    let token = verify_token(&link_tracker, &token, TrackingKind::Click)?;
+    let url = token.url.clone().ok_or(TrackingError::InvalidToken)?;
+    // Losing an event is better than losing the reader.
+    if let Err(e) = record_event(&token, &db_pool).await {
+        tracing::error!(error.cause_chain = ?e, "Failed to record a click event.");
+    }
+    Ok(HttpResponse::Found()
+        .insert_header((header::LOCATION, url))
+        .finish())
+}
+
+#[tracing::instrument(name = "Track a newsletter open", skip(token, db_pool, link_tracker))]
+pub async fn track_open(
+    token: web::Path<String>,
+    db_pool: web::Data<PgPool>,
+    link_tracker: web::Data<LinkTracker>,
+) -> HttpResponse {
+    // The pixel is always served: a broken image in the reader's inbox does not help anyone.
+    match verify_token(&link_tracker, &token, TrackingKind::Open) {
+        Ok(token) => {
+            if let Err(e) = record_event(&token, &db_pool).await {
+                tracing::error!(error.cause_chain = ?e, "Failed to record an open event.");
+            }
+        }
+        Err(e) => tracing::warn!("Ignoring an open event: {}", e),
+    }
+    HttpResponse::Ok()
+        .content_type("image/gif")
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .body(TRACKING_PIXEL)
+}
+
+/// Ask the subscriber to confirm the opt-out.
+/// Mail scanners and link previews follow every link in an email, so a GET must not change
+/// anything: the form posts back to the same URL.
+#[tracing::instrument(name = "Render the tracking opt-out page", skip(token, link_tracker))]
+pub async fn tracking_opt_out_form(
+    token: web::Path<String>,
+    link_tracker: web::Data<LinkTracker>,
+) -> Result<HttpResponse, TrackingError> {
+    verify_token(&link_tracker, &token, TrackingKind::OptOut)?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .body(
+            "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n\
+            <title>Stop tracking</title>\n</head>\n<body>\n\
+            <p>Stop tracking opens and clicks on the newsletters we send you?</p>\n\
+            <form method=\"post\"><button type=\"submit\">Stop tracking</button></form>\n\
+            </body>\n</html>\n",
+        ))
+}
+
+/// Opt the subscriber out of tracking.
+/// The body is ignored, so this also serves RFC 8058 one-click requests.
+#[tracing::instrument(name = "Opt a subscriber out of tracking", skip(token, db_pool, link_tracker))]
+pub async fn tracking_opt_out(
+    token: web::Path<String>,
+    db_pool: web::Data<PgPool>,
+    link_tracker: web::Data<LinkTracker>,
+) -> Result<HttpResponse, TrackingError> {
+    let token = verify_token(&link_tracker, &token, TrackingKind::OptOut)?;
+    sqlx::query!(
+        "UPDATE subscriptions SET tracking_opt_out = true WHERE id = $1",
+        token.subscriber_id
+    )
+    .execute(db_pool.get_ref())
+    .await
+    .context("Failed to opt the subscriber out of tracking.")?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::plaintext())
+        .body("We will no longer track opens and clicks on the newsletters we send you."))
+}
+
+#[derive(thiserror::Error)]
+pub enum TrackingError {
+    #[error("The tracking link is invalid.")]
+    InvalidToken,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for TrackingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for TrackingError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            TrackingError::InvalidToken => StatusCode::BAD_REQUEST,
+            TrackingError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        problem_response(self)
+    }
+}
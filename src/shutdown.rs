@@ -0,0 +1,123 @@
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use actix_web::body::{BodySize, BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::web::{self, Bytes};
+use tokio::sync::watch;
+
+/// Create a way to ask the application to stop, and a way for its tasks to find out.
+pub fn channel() -> (ShutdownHandle, ShutdownSignal) {
+    let (sender, receiver) = watch::channel(false);
+    (ShutdownHandle(sender), ShutdownSignal(receiver))
+}
+
+/// Asks the application to stop gracefully, as a termination signal would.
+#[derive(Clone)]
+pub struct ShutdownHandle(watch::Sender<bool>);
+
+impl ShutdownHandle {
+    pub fn trigger(&self) {
+        self.0.send_replace(true);
+    }
+}
+
+/// Lets long running tasks know when they should wind down.
+#[derive(Clone)]
+pub struct ShutdownSignal(watch::Receiver<bool>);
+
+impl ShutdownSignal {
+    pub fn is_requested(&self) -> bool {
+        *self.0.borrow()
+    }
+
+    /// Resolve once a shutdown has been requested.
+    pub async fn requested(&mut self) {
+        // The handle is gone: nobody is left to keep us running.
+        let _ = self.0.wait_for(|requested| *requested).await;
+    }
+}
+
+/// The number of requests being served, so that shutdown can wait for them to complete.
+#[derive(Clone)]
+pub struct InFlightRequests(Arc<watch::Sender<usize>>);
+
+impl Default for InFlightRequests {
+    fn default() -> Self {
+        Self(Arc::new(watch::Sender::new(0)))
+    }
+}
+
+impl InFlightRequests {
+    fn start(&self) -> InFlightRequest {
+        self.0.send_modify(|count| *count += 1);
+        InFlightRequest(self.clone())
+    }
+
+    /// Resolve once no request is being served.
+    pub async fn idle(&self) {
+        let _ = self.0.subscribe().wait_for(|count| *count == 0).await;
+    }
+}
+
+struct InFlightRequest(InFlightRequests);
+
+impl Drop for InFlightRequest {
+    fn drop(&mut self) {
+        self.0.0.send_modify(|count| *count -= 1);
+    }
+}
+
+/// A response body that keeps its request counted as in flight until it has been sent, or dropped.
+/// Streamed bodies are produced long after the handler returned.
+pub struct InFlightBody {
+    body: BoxBody,
+    _request: InFlightRequest,
+}
+
+impl MessageBody for InFlightBody {
+    type Error = Box<dyn std::error::Error>;
+
+    fn size(&self) -> BodySize {
+        self.body.size()
+    }
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Bytes, Self::Error>>> {
+        Pin::new(&mut self.body).poll_next(cx)
+    }
+}
+
+/// Count the requests being served in `InFlightRequests`, until their response body is sent.
+pub async fn track_in_flight_requests(
+    in_flight: web::Data<InFlightRequests>,
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<InFlightBody>, actix_web::Error> {
+    let request = in_flight.start();
+    let response = next.call(req).await?;
+    Ok(response.map_body(|_, body| InFlightBody { body: body.boxed(), _request: request }))
+}
+
+/// Resolve when the process is asked to terminate, with Ctrl+C or `SIGTERM`.
+pub async fn termination_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed to install the Ctrl+C handler.");
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to install the SIGTERM handler.")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
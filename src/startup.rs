@@ -1,33 +1,204 @@
+use std::future::Future;
 use std::net::TcpListener;
+use std::time::Duration;
 use actix_web::dev::Server;
+use actix_web::middleware::from_fn;
 use actix_web::{web, App, HttpServer};
-use sqlx::PgPool;
+use sqlx::{PgConnection, PgPool};
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use anyhow::Context;
+use futures_util::future::BoxFuture;
+use sqlx::error::BoxDynError;
+use sqlx::migrate::{Migrate, MigrateError, Migration, MigrationSource, Migrator};
 use sqlx::postgres::PgPoolOptions;
+use tokio::task::{JoinError, JoinSet};
 use tracing_actix_web::TracingLogger;
-use crate::configuration::{DatabaseSettings, Settings};
+use crate::authentication::reject_anonymous_users;
+use crate::bot_protection::{BotProtection, FormTokens};
+use crate::configuration::{DatabaseSettings, ReadinessSettings, Settings};
+use crate::deliverability::DeliverabilityChecker;
 use crate::email_client::EmailClient;
-use crate::routes::{health_check, subscriptions, subscriptions_confirm};
+use crate::metrics::{record_http_metrics, Metrics, PrometheusExporter};
+use crate::problem_details::extractor_error_handler;
+use crate::rate_limit::{rate_limit_by_ip, RateLimiter};
+use crate::subscribers::backfill_normalized_emails;
+use crate::suppressions::EmailHasher;
+use crate::request_id::{propagate_request_id, RequestIdRootSpanBuilder};
+use crate::telemetry::construct_meter_provider;
+use crate::routes::{
+    archive_index, archive_issue, atom_feed, create_newsletter_draft, create_suppression, delete_subscriber,
+    export_subscribers_as_file, get_newsletter, get_subscriber, get_suppression, health_check,
+    import_subscribers_from_csv, lift_suppression, list_newsletter_revisions, list_subscribers, list_suppressions,
+    newsletter_report, personal_data_access, personal_data_erasure, prometheus_metrics, publish_newsletter, ready,
+    rss_feed, send_newsletter_preview, subscription_form, subscriptions, subscriptions_confirm, track_click, track_open,
+    tracking_opt_out, tracking_opt_out_form, update_newsletter_draft, update_newsletter_visibility, update_subscriber,
+};
+use crate::shutdown::{self, track_in_flight_requests, InFlightRequests, ShutdownHandle, ShutdownSignal};
+use crate::tracking::LinkTracker;
 
+/// The migrations of `migrations/`, embedded in the binary.
+pub static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
 
 pub fn get_connection_pool(configuration: &DatabaseSettings) -> PgPool {
     PgPoolOptions::new().connect_lazy_with(configuration.with_db())
 }
 
+/// The migration adding `subscriptions.email_normalized`, which Rust fills in.
+const EMAIL_NORMALIZED_ADDED: i64 = 20250630090000;
+/// The migration making `subscriptions.email_normalized` unique, once it is filled in.
+const EMAIL_NORMALIZED_UNIQUE: i64 = 20250630100000;
 
-pub fn run(listener: TcpListener, db_pool: PgPool, email_client: EmailClient, base_url: String) -> std::io::Result<Server> {
+/// The migrations of `MIGRATOR` up to a version, included.
+#[derive(Debug)]
+struct MigrationsUpTo(i64);
+
+impl MigrationSource<'static> for MigrationsUpTo {
+    fn resolve(self) -> BoxFuture<'static, Result<Vec<Migration>, BoxDynError>> {
+        Box::pin(async move { Ok(MIGRATOR.iter().filter(|m| m.version <= self.0).cloned().collect()) })
+    }
+}
+
+/// Apply the migrations that have not been applied yet, along with the data migrations written in Rust.
+/// Replicas starting together take turns, thanks to an advisory lock held while migrating.
+#[tracing::instrument(name = "Run the database migrations", skip_all)]
+pub async fn run_migrations(db_pool: &PgPool) -> Result<(), MigrateError> {
+    let mut connection = db_pool.acquire().await?;
+    connection.lock().await?;
+    let outcome = migrate(&mut connection).await;
+    connection.unlock().await?;
+    outcome
+}
+
+async fn migrate(connection: &mut PgConnection) -> Result<(), MigrateError> {
+    connection.ensure_migrations_table().await?;
+    let applied = connection.list_applied_migrations().await?;
+    let is_applied = |version: i64| applied.iter().any(|m| m.version == version);
+    if !is_applied(EMAIL_NORMALIZED_UNIQUE) {
+        apply_migrations_up_to(connection, EMAIL_NORMALIZED_ADDED).await?;
+        let backfilled = backfill_normalized_emails(connection).await?;
+        tracing::info!("Normalized the email addresses of {} subscribers.", backfilled);
+    }
+    let mut migrator = Migrator::new(MigrationsUpTo(i64::MAX)).await?;
+    migrator.set_locking(false);
+    migrator.run(&mut *connection).await
+}
+
+/// The caller holds the lock. Later migrations may have been applied already.
+async fn apply_migrations_up_to(connection: &mut PgConnection, version: i64) -> Result<(), MigrateError> {
+    let mut migrator = Migrator::new(MigrationsUpTo(version)).await?;
+    migrator.set_ignore_missing(true);
+    migrator.set_locking(false);
+    migrator.run(&mut *connection).await
+}
+
+
+#[allow(clippy::too_many_arguments)]
+pub fn run(
+    listener: TcpListener,
+    db_pool: PgPool,
+    email_client: EmailClient,
+    email_hasher: EmailHasher,
+    form_tokens: FormTokens,
+    bot_protection: BotProtection,
+    deliverability: DeliverabilityChecker,
+    rate_limiter: RateLimiter,
+    base_url: String,
+    subscription_token_ttl: chrono::Duration,
+    link_tracker: LinkTracker,
+    metrics: Metrics,
+    prometheus_exporter: Option<PrometheusExporter>,
+    readiness: ReadinessSettings,
+    in_flight: InFlightRequests,
+    shutdown_timeout: Duration,
+) -> std::io::Result<Server> {
     let db_pool = web::Data::new(db_pool);
     let email_client = web::Data::new(email_client);
+    let email_hasher = web::Data::new(email_hasher);
+    let form_tokens = web::Data::new(form_tokens);
+    let bot_protection = web::Data::new(bot_protection);
+    let deliverability = web::Data::new(deliverability);
+    let rate_limiter = web::Data::new(rate_limiter);
     let base_url = web::Data::new(ApplicationBaseUrl(base_url));
+    let subscription_token_ttl = web::Data::new(SubscriptionTokenTtl(subscription_token_ttl));
+    let link_tracker = web::Data::new(link_tracker);
+    let metrics = web::Data::new(metrics);
+    let prometheus_exporter = prometheus_exporter.map(web::Data::new);
+    let readiness = web::Data::new(readiness);
+    let in_flight = web::Data::new(in_flight);
     let server = HttpServer::new(move || {
         App::new()
-            .wrap(TracingLogger::default())
+            .wrap(from_fn(record_http_metrics))
+            .wrap(from_fn(propagate_request_id))
+            .wrap(TracingLogger::<RequestIdRootSpanBuilder>::new())
+            .wrap(from_fn(track_in_flight_requests))
             .route("/health_check", web::get().to(health_check))
-            .route("/subscriptions", web::post().to(subscriptions))
-            .route("/subscriptions/confirm", web::get().to(subscriptions_confirm))
+            .route("/ready", web::get().to(ready))
+            .service(
+                web::scope("/subscriptions")
+                    .wrap(from_fn(rate_limit_by_ip))
+                    .route("", web::post().to(subscriptions))
+                    .route("/form", web::get().to(subscription_form))
+                    .route("/confirm", web::get().to(subscriptions_confirm)),
+            )
+            .route("/archive", web::get().to(archive_index))
+            .route("/archive/{slug}", web::get().to(archive_issue))
+            .route("/feed.xml", web::get().to(atom_feed))
+            .route("/feed.rss", web::get().to(rss_feed))
+            .route("/t/c/{token}", web::get().to(track_click))
+            .route("/t/o/{token}.gif", web::get().to(track_open))
+            .route("/t/u/{token}", web::get().to(tracking_opt_out_form))
+            .route("/t/u/{token}", web::post().to(tracking_opt_out))
+            .configure(|cfg| {
+                if let Some(exporter) = &prometheus_exporter {
+                    cfg.app_data(exporter.clone())
+                        .route("/metrics", web::get().to(prometheus_metrics));
+                }
+            })
+            .service(
+                web::scope("/admin")
+                    .wrap(from_fn(reject_anonymous_users))
+                    .route("/newsletters", web::post().to(create_newsletter_draft))
+                    .route("/newsletters/{newsletter_issue_id}", web::get().to(get_newsletter))
+                    .route("/newsletters/{newsletter_issue_id}", web::put().to(update_newsletter_draft))
+                    .route("/newsletters/{newsletter_issue_id}/visibility", web::put().to(update_newsletter_visibility))
+                    .route("/newsletters/{newsletter_issue_id}/revisions", web::get().to(list_newsletter_revisions))
+                    .route("/newsletters/{newsletter_issue_id}/preview", web::post().to(send_newsletter_preview))
+                    .route("/newsletters/{newsletter_issue_id}/publish", web::post().to(publish_newsletter))
+                    .route("/newsletters/{newsletter_issue_id}/report", web::get().to(newsletter_report))
+                    .route("/subscribers/import", web::post().to(import_subscribers_from_csv))
+                    .route("/subscribers/export", web::get().to(export_subscribers_as_file))
+                    .route("/subscribers", web::get().to(list_subscribers))
+                    .route("/subscribers/{subscriber_id}", web::get().to(get_subscriber))
+                    .route("/subscribers/{subscriber_id}", web::patch().to(update_subscriber))
+                    .route("/subscribers/{subscriber_id}", web::delete().to(delete_subscriber))
+                    .route("/privacy/access", web::post().to(personal_data_access))
+                    .route("/privacy/erasure", web::post().to(personal_data_erasure))
+                    .route("/suppressions", web::get().to(list_suppressions))
+                    .route("/suppressions", web::post().to(create_suppression))
+                    .route("/suppressions/lift", web::post().to(lift_suppression))
+                    .route("/suppressions/{email_hash}", web::get().to(get_suppression)),
+            )
+            .app_data(web::JsonConfig::default().error_handler(extractor_error_handler))
+            .app_data(web::FormConfig::default().error_handler(extractor_error_handler))
+            .app_data(web::QueryConfig::default().error_handler(extractor_error_handler))
+            .app_data(web::PathConfig::default().error_handler(extractor_error_handler))
             .app_data(db_pool.clone())
             .app_data(email_client.clone())
+            .app_data(email_hasher.clone())
+            .app_data(form_tokens.clone())
+            .app_data(bot_protection.clone())
+            .app_data(deliverability.clone())
+            .app_data(rate_limiter.clone())
             .app_data(base_url.clone())
+            .app_data(subscription_token_ttl.clone())
+            .app_data(link_tracker.clone())
+            .app_data(metrics.clone())
+            .app_data(readiness.clone())
+            .app_data(in_flight.clone())
     })
+    // Signals are handled by `Application::run_until_stopped`, which stops the workers as well.
+    .disable_signals()
+    .shutdown_timeout(shutdown_timeout.as_secs())
     .listen(listener)?
     .run();
     Ok(server)
@@ -36,38 +207,220 @@ pub fn run(listener: TcpListener, db_pool: PgPool, email_client: EmailClient, ba
 pub struct Application {
     port: u16,
     server: Server,
+    metrics: Metrics,
+    tracer_provider: Option<SdkTracerProvider>,
+    in_flight: InFlightRequests,
+    shutdown_timeout: Duration,
+    shutdown: ShutdownHandle,
+    shutdown_signal: ShutdownSignal,
+    workers: JoinSet<(&'static str, Result<(), anyhow::Error>)>,
 }
 
 pub struct ApplicationBaseUrl(pub String);
 
+/// How long a confirmation token can be used after it was issued.
+pub struct SubscriptionTokenTtl(pub chrono::Duration);
+
 
 impl Application {
-    pub async fn build(configuration: Settings) -> Result<Application, std::io::Error> {
+    pub async fn build(configuration: Settings) -> Result<Application, anyhow::Error> {
         let connection_pool = get_connection_pool(&configuration.database);
+        let email_hasher = configuration.application.email_hasher();
+        if configuration.database.run_migrations_on_startup {
+            run_migrations(&connection_pool)
+                .await
+                .context("Failed to migrate the database.")?;
+        }
+        let (meter_provider, prometheus_exporter) =
+            construct_meter_provider(&configuration.metrics, &configuration.telemetry)?;
+        let metrics = Metrics::new(meter_provider, &connection_pool);
 
-        let sender_email = configuration.email_client.sender().expect("Invalid sender email address.");
-        let timeout = configuration.email_client.timeout();
-        let email_client = EmailClient::new(
-            sender_email,
-            configuration.email_client.base_url,
-            timeout
-        );
+        let email_client = configuration
+            .email_client
+            .client()
+            .with_suppression_list(connection_pool.clone(), email_hasher.clone());
+        let hmac_secret = &configuration.application.hmac_secret;
+        let form_tokens = configuration.bot_protection.form_tokens(hmac_secret);
+        let bot_protection = configuration.bot_protection.bot_protection(hmac_secret, &connection_pool);
+        let deliverability = configuration
+            .deliverability
+            .checker()
+            .context("Failed to set the deliverability checks up.")?;
+        let rate_limiter = configuration
+            .rate_limit
+            .rate_limiter(&connection_pool, &email_hasher)
+            .context("Failed to set the rate limits up.")?;
 
         let address = format!( "{}:{}", configuration.application.address, configuration.application.port);
         let listener = TcpListener::bind(address)?;
         let port = listener.local_addr()?.port();
 
-        let server = run(listener, connection_pool, email_client, configuration.application.base_url)?;
-        Ok(Self {port, server})
+        let link_tracker = configuration.application.link_tracker();
+        let subscription_token_ttl = configuration.application.subscription_token_ttl();
+        let shutdown_timeout = configuration.application.shutdown_timeout();
+        let in_flight = InFlightRequests::default();
+        let server = run(
+            listener,
+            connection_pool,
+            email_client,
+            email_hasher,
+            form_tokens,
+            bot_protection,
+            deliverability,
+            rate_limiter,
+            configuration.application.base_url,
+            subscription_token_ttl,
+            link_tracker,
+            metrics.clone(),
+            prometheus_exporter,
+            configuration.readiness,
+            in_flight.clone(),
+            shutdown_timeout,
+        )?;
+        let (shutdown, shutdown_signal) = shutdown::channel();
+        Ok(Self {
+            port,
+            server,
+            metrics,
+            tracer_provider: None,
+            in_flight,
+            shutdown_timeout,
+            shutdown,
+            shutdown_signal,
+            workers: JoinSet::new(),
+        })
     }
 
     pub fn port(&self) -> u16 {
         self.port
     }
 
-    pub async fn run_until_stopped(self) -> Result<(), std::io::Error> {
-        self.server.await
+    /// The instruments of the application, to share with the background worker.
+    pub fn metrics(&self) -> Metrics {
+        self.metrics.clone()
     }
 
+    /// Stop the application gracefully, as if it had received `SIGTERM`.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Flush the spans still buffered by this provider when the application stops.
+    pub fn with_tracer_provider(mut self, tracer_provider: SdkTracerProvider) -> Self {
+        self.tracer_provider = Some(tracer_provider);
+        self
+    }
+
+    /// Run a background task alongside the server. It is told to stop through its `ShutdownSignal`,
+    /// and is waited for before the application exits.
+    pub fn spawn_worker<F, Fut>(&mut self, name: &'static str, worker: F)
+    where
+        F: FnOnce(ShutdownSignal) -> Fut,
+        Fut: Future<Output = Result<(), anyhow::Error>> + Send + 'static,
+    {
+        let task = worker(self.shutdown_signal.clone());
+        self.workers.spawn(async move { (name, task.await) });
+    }
+
+    /// Serve requests until a termination signal, a shutdown request, or the failure of a worker.
+    /// Then stop accepting connections, and give in-flight requests and workers
+    /// `shutdown_timeout` to finish before flushing telemetry.
+    pub async fn run_until_stopped(self) -> Result<(), anyhow::Error> {
+        let Self {
+            server,
+            metrics,
+            tracer_provider,
+            in_flight,
+            shutdown_timeout,
+            shutdown,
+            mut shutdown_signal,
+            mut workers,
+            ..
+        } = self;
+        let server_handle = server.handle();
+        let mut server_task = tokio::spawn(server);
+
+        let mut outcome = Ok(());
+        let mut server_stopped = false;
+        tokio::select! {
+            _ = shutdown::termination_signal() => {
+                tracing::info!("Received a termination signal, shutting down.");
+            }
+            _ = shutdown_signal.requested() => {
+                tracing::info!("Shutdown requested, shutting down.");
+            }
+            result = &mut server_task => {
+                server_stopped = true;
+                outcome = report_exit("API", result.map(|r| r.map_err(anyhow::Error::from)));
+            }
+            Some(result) = workers.join_next() => {
+                let name = result.as_ref().map_or("Background worker", |(name, _)| name);
+                outcome = report_exit(name, result.map(|(_, r)| r));
+            }
+        }
+
+        shutdown.trigger();
+        let drain = async {
+            if !server_stopped {
+                // A graceful stop alone is not enough: an actix worker can exit as soon as
+                // the accept thread is gone, dropping the connections it was still serving.
+                server_handle.pause().await;
+                in_flight.idle().await;
+                server_handle.stop(true).await;
+                let _ = report_exit("API", server_task.await.map(|r| r.map_err(anyhow::Error::from)));
+            }
+            while let Some(result) = workers.join_next().await {
+                let name = result.as_ref().map_or("Background worker", |(name, _)| name);
+                let _ = report_exit(name, result.map(|(_, r)| r));
+            }
+        };
+        if tokio::time::timeout(shutdown_timeout, drain).await.is_err() {
+            tracing::warn!(
+                "The application did not stop within {:?}, abandoning the remaining tasks.",
+                shutdown_timeout
+            );
+            workers.abort_all();
+        }
+
+        if let Err(e) = metrics.shutdown() {
+            tracing::warn!(error.message = %e, "Failed to flush the metrics.");
+        }
+        if let Some(tracer_provider) = tracer_provider
+            && let Err(e) = tracer_provider.shutdown()
+        {
+            // Only the OpenTelemetry layer is gone: the logs still reach the formatting layer.
+            tracing::warn!(error.message = %e, "Failed to flush the spans.");
+        }
+        outcome
+    }
 }
 
+fn report_exit(
+    task_name: &str,
+    outcome: Result<Result<(), anyhow::Error>, JoinError>,
+) -> Result<(), anyhow::Error> {
+    match outcome {
+        Ok(Ok(())) => {
+            tracing::info!("{} has exited", task_name);
+            Ok(())
+        }
+        Ok(Err(e)) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "{} failed",
+                task_name
+            );
+            Err(e)
+        }
+        Err(e) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "{} task failed to complete",
+                task_name
+            );
+            Err(e.into())
+        }
+    }
+}
@@ -0,0 +1,382 @@
+//! Queries on the subscribers, shared by the admin API and the command line.
+use std::io::Write;
+use chrono::{DateTime, Utc};
+use futures_util::Stream;
+use sqlx::{PgConnection, PgPool, Postgres, Transaction};
+use uuid::Uuid;
+use crate::csv;
+use crate::damain::{normalize_email, SubscriberEmail, SubscriberId, SubscriberName, SubscriptionStatus};
+
+/// Exported rows are fetched from the cursor this many at a time.
+const EXPORT_BATCH_SIZE: usize = 1000;
+
+/// A subscriber, as stored in `subscriptions`.
+#[derive(Debug, serde::Serialize, sqlx::FromRow)]
+pub struct Subscriber {
+    pub id: Uuid,
+    pub email: String,
+    pub name: String,
+    pub status: String,
+    pub subscribed_at: DateTime<Utc>,
+    /// `None` for pending subscribers, and for those confirmed before it was recorded.
+    pub confirmed_at: Option<DateTime<Utc>>,
+}
+
+impl Subscriber {
+    pub const CSV_HEADER: [&'static str; 6] = ["id", "email", "name", "status", "subscribed_at", "confirmed_at"];
+
+    pub fn csv_record(&self) -> [String; 6] {
+        [
+            self.id.to_string(),
+            self.email.clone(),
+            self.name.clone(),
+            self.status.clone(),
+            self.subscribed_at.to_rfc3339(),
+            self.confirmed_at.map(|t| t.to_rfc3339()).unwrap_or_default(),
+        ]
+    }
+}
+
+#[derive(Debug, Clone, Copy, serde::Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    Csv,
+    Jsonl,
+}
+
+impl ExportFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+            ExportFormat::Jsonl => "application/x-ndjson",
+        }
+    }
+
+    pub fn file_extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Jsonl => "jsonl",
+        }
+    }
+
+    /// What comes before the first subscriber: the CSV header, nothing for JSON Lines.
+    pub fn write_header(&self, out: &mut impl Write) -> std::io::Result<()> {
+        match self {
+            ExportFormat::Csv => csv::write_record(out, Subscriber::CSV_HEADER),
+            ExportFormat::Jsonl => Ok(()),
+        }
+    }
+
+    pub fn write_subscribers(&self, out: &mut impl Write, subscribers: &[Subscriber]) -> std::io::Result<()> {
+        for subscriber in subscribers {
+            match self {
+                ExportFormat::Csv => csv::write_record(out, subscriber.csv_record())?,
+                ExportFormat::Jsonl => {
+                    serde_json::to_writer(&mut *out, subscriber)?;
+                    writeln!(out)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[tracing::instrument(name = "List subscribers", skip(db_pool))]
+pub async fn list_subscribers(
+    db_pool: &PgPool,
+    status: Option<SubscriptionStatus>,
+) -> Result<Vec<Subscriber>, sqlx::Error> {
+    sqlx::query_as!(
+        Subscriber,
+        r#"
+        SELECT id, email, name, status, subscribed_at, confirmed_at
+        FROM subscriptions
+        WHERE $1::text IS NULL OR status = $1
+        ORDER BY subscribed_at, id
+        "#,
+        status.map(|s| s.as_str()),
+    )
+    .fetch_all(db_pool)
+    .await
+}
+
+enum ExportCursor {
+    NotDeclared(PgPool, Option<SubscriptionStatus>),
+    Declared(Transaction<'static, Postgres>),
+    Closed,
+}
+
+/// The subscribers with `status`, or all of them, in batches read from a server-side cursor:
+/// memory stays flat however long the list is.
+pub fn export_subscribers(
+    db_pool: PgPool,
+    status: Option<SubscriptionStatus>,
+) -> impl Stream<Item = Result<Vec<Subscriber>, sqlx::Error>> {
+    futures_util::stream::try_unfold(ExportCursor::NotDeclared(db_pool, status), |cursor| async move {
+        let mut transaction = match cursor {
+            ExportCursor::NotDeclared(db_pool, status) => {
+                // A cursor only lives as long as the transaction that declares it.
+                let mut transaction = db_pool.begin().await?;
+                sqlx::query(
+                    r#"
+                    DECLARE subscribers_export NO SCROLL CURSOR FOR
+                    SELECT id, email, name, status, subscribed_at, confirmed_at
+                    FROM subscriptions
+                    WHERE $1::text IS NULL OR status = $1
+                    ORDER BY subscribed_at, id
+                    "#,
+                )
+                .bind(status.map(|s| s.as_str()))
+                .execute(&mut *transaction)
+                .await?;
+                transaction
+            }
+            ExportCursor::Declared(transaction) => transaction,
+            ExportCursor::Closed => return Ok(None),
+        };
+        let batch: Vec<Subscriber> = sqlx::query_as(&format!("FETCH {} FROM subscribers_export", EXPORT_BATCH_SIZE))
+            .fetch_all(&mut *transaction)
+            .await?;
+        if batch.len() < EXPORT_BATCH_SIZE {
+            transaction.commit().await?;
+            if batch.is_empty() {
+                return Ok(None);
+            }
+            return Ok(Some((batch, ExportCursor::Closed)));
+        }
+        Ok(Some((batch, ExportCursor::Declared(transaction))))
+    })
+}
+
+/// Narrows down a search of the subscribers. Every criterion is optional.
+#[derive(Debug, Default)]
+pub struct SubscriberFilter {
+    pub status: Option<SubscriptionStatus>,
+    /// Subscribed at or after this time.
+    pub subscribed_from: Option<DateTime<Utc>>,
+    /// Subscribed strictly before this time.
+    pub subscribed_to: Option<DateTime<Utc>>,
+    /// Matched case-insensitively against any part of the email or the name.
+    pub search: Option<String>,
+}
+
+/// Where a page of subscribers ends: the next page starts right after it.
+///
+/// Encoded as `<subscribed_at>_<id>`. The subscriber does not have to exist anymore
+/// for the cursor to be valid, so that paging survives deletions.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SubscriberCursor {
+    pub subscribed_at: DateTime<Utc>,
+    pub id: Uuid,
+}
+
+impl std::fmt::Display for SubscriberCursor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}_{}",
+            self.subscribed_at.to_rfc3339_opts(chrono::SecondsFormat::Micros, true),
+            self.id
+        )
+    }
+}
+
+impl std::str::FromStr for SubscriberCursor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("`{}` is not a valid cursor.", s);
+        let (subscribed_at, id) = s.split_once('_').ok_or_else(invalid)?;
+        Ok(Self {
+            subscribed_at: DateTime::parse_from_rfc3339(subscribed_at)
+                .map_err(|_| invalid())?
+                .with_timezone(&Utc),
+            id: Uuid::parse_str(id).map_err(|_| invalid())?,
+        })
+    }
+}
+
+impl serde::Serialize for SubscriberCursor {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for SubscriberCursor {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl From<&Subscriber> for SubscriberCursor {
+    fn from(subscriber: &Subscriber) -> Self {
+        Self {
+            subscribed_at: subscriber.subscribed_at,
+            id: subscriber.id,
+        }
+    }
+}
+
+/// Escape the wildcards of a `LIKE` pattern, so that the search is for `text` as written.
+fn like_pattern(text: &str) -> String {
+    let escaped = text.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_");
+    format!("%{}%", escaped)
+}
+
+/// Up to `limit` subscribers matching `filter`, most recent first, after `cursor` if given.
+#[tracing::instrument(name = "Search subscribers", skip(db_pool))]
+pub async fn search_subscribers(
+    db_pool: &PgPool,
+    filter: &SubscriberFilter,
+    cursor: Option<SubscriberCursor>,
+    limit: i64,
+) -> Result<Vec<Subscriber>, sqlx::Error> {
+    sqlx::query_as!(
+        Subscriber,
+        r#"
+        SELECT id, email, name, status, subscribed_at, confirmed_at
+        FROM subscriptions
+        WHERE ($1::text IS NULL OR status = $1)
+            AND ($2::timestamptz IS NULL OR subscribed_at >= $2)
+            AND ($3::timestamptz IS NULL OR subscribed_at < $3)
+            AND ($4::text IS NULL OR email ILIKE $4 OR name ILIKE $4)
+            AND ($5::timestamptz IS NULL OR (subscribed_at, id) < ($5, $6::uuid))
+        ORDER BY subscribed_at DESC, id DESC
+        LIMIT $7
+        "#,
+        filter.status.map(|s| s.as_str()),
+        filter.subscribed_from,
+        filter.subscribed_to,
+        filter.search.as_deref().map(like_pattern),
+        cursor.map(|c| c.subscribed_at),
+        cursor.map(|c| c.id),
+        limit,
+    )
+    .fetch_all(db_pool)
+    .await
+}
+
+#[tracing::instrument(name = "Fetch a subscriber", skip(db_pool))]
+pub async fn get_subscriber(db_pool: &PgPool, subscriber_id: SubscriberId) -> Result<Option<Subscriber>, sqlx::Error> {
+    sqlx::query_as!(
+        Subscriber,
+        "SELECT id, email, name, status, subscribed_at, confirmed_at FROM subscriptions WHERE id = $1",
+        subscriber_id.inner(),
+    )
+    .fetch_optional(db_pool)
+    .await
+}
+
+/// The changes to make to a subscriber: `None` leaves a field as it is.
+#[derive(Debug, Default)]
+pub struct SubscriberUpdate {
+    pub email: Option<SubscriberEmail>,
+    pub name: Option<SubscriberName>,
+    pub status: Option<SubscriptionStatus>,
+}
+
+/// Apply `update` to a subscriber, returning it as updated, or `None` if there is no such subscriber.
+/// Changing the email to one that normalizes like another subscriber's is a unique violation.
+/// Confirming a subscriber records when, moving them back to pending forgets it.
+#[tracing::instrument(name = "Update a subscriber", skip(db_pool, update))]
+pub async fn update_subscriber(
+    db_pool: &PgPool,
+    subscriber_id: SubscriberId,
+    update: SubscriberUpdate,
+) -> Result<Option<Subscriber>, sqlx::Error> {
+    sqlx::query_as!(
+        Subscriber,
+        r#"
+        UPDATE subscriptions
+        SET email = COALESCE($2, email),
+            email_normalized = COALESCE($5, email_normalized),
+            name = COALESCE($3, name),
+            status = COALESCE($4, status),
+            confirmed_at = CASE
+                WHEN $4::text IS NULL OR $4 = status THEN confirmed_at
+                WHEN $4 = 'confirmed' THEN now()
+            END
+        WHERE id = $1
+        RETURNING id, email, name, status, subscribed_at, confirmed_at
+        "#,
+        subscriber_id.inner(),
+        update.email.as_ref().map(|e| e.as_ref()),
+        update.name.as_ref().map(|n| n.as_ref()),
+        update.status.map(|s| s.as_str()),
+        update.email.as_ref().map(|e| e.normalized()),
+    )
+    .fetch_optional(db_pool)
+    .await
+}
+
+#[tracing::instrument(name = "Find a subscriber by email", skip(db_pool, email))]
+pub async fn find_subscriber_id(db_pool: &PgPool, email: &str) -> Result<Option<SubscriberId>, sqlx::Error> {
+    let normalized = normalize_email(email).unwrap_or_else(|| email.trim().to_lowercase());
+    let row = sqlx::query!("SELECT id FROM subscriptions WHERE email_normalized = $1", normalized)
+        .fetch_optional(db_pool)
+        .await?;
+    Ok(row.map(|r| SubscriberId::new(r.id)))
+}
+
+/// Delete a subscriber: their tokens, deliveries and tracking events go with them.
+/// Returns `false` if there was no such subscriber.
+#[tracing::instrument(name = "Delete a subscriber", skip(db_pool))]
+pub async fn delete_subscriber(db_pool: &PgPool, subscriber_id: SubscriberId) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!("DELETE FROM subscriptions WHERE id = $1", subscriber_id.inner())
+        .execute(db_pool)
+        .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Fill `email_normalized` in for the subscribers who predate it, which the migration adding
+/// the column cannot do: IDNA-encoding domains takes `normalize_email`.
+#[tracing::instrument(name = "Backfill normalized subscriber emails", skip(connection))]
+pub async fn backfill_normalized_emails(connection: &mut PgConnection) -> Result<u64, sqlx::Error> {
+    let rows = sqlx::query!("SELECT id, email FROM subscriptions WHERE email_normalized IS NULL")
+        .fetch_all(&mut *connection)
+        .await?;
+    let (ids, normalized): (Vec<Uuid>, Vec<String>) = rows
+        .into_iter()
+        // Addresses that no longer pass validation still need a value: keep them apart from the rest.
+        .map(|row| (row.id, normalize_email(&row.email).unwrap_or_else(|| row.email.trim().to_lowercase())))
+        .unzip();
+    let result = sqlx::query!(
+        "UPDATE subscriptions SET email_normalized = backfill.email_normalized
+        FROM UNNEST($1::uuid[], $2::text[]) AS backfill(id, email_normalized)
+        WHERE subscriptions.id = backfill.id",
+        &ids,
+        &normalized,
+    )
+    .execute(connection)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+/// Delete the confirmation tokens issued before `cutoff`. Returns how many were deleted.
+#[tracing::instrument(name = "Purge expired subscription tokens", skip(db_pool))]
+pub async fn purge_expired_tokens(db_pool: &PgPool, cutoff: DateTime<Utc>) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!("DELETE FROM subscription_tokens WHERE created_at < $1", cutoff)
+        .execute(db_pool)
+        .await?;
+    Ok(result.rows_affected())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_cursor_survives_a_round_trip_through_its_text_form() {
+        let cursor = SubscriberCursor {
+            subscribed_at: "2025-06-27T09:30:00.123456Z".parse().unwrap(),
+            id: Uuid::new_v4(),
+        };
+        assert_eq!(cursor.to_string().parse::<SubscriberCursor>(), Ok(cursor));
+    }
+
+    #[test]
+    fn like_wildcards_are_escaped() {
+        assert_eq!(like_pattern(r"100%_\"), r"%100\%\_\\%");
+    }
+}
@@ -0,0 +1,203 @@
+//! Addresses that must never be mailed again, recorded by a hash of the address only.
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, SecretString};
+use sha2::Sha256;
+use sqlx::{Executor, PgPool, Postgres};
+use uuid::Uuid;
+use crate::authentication::UserId;
+use crate::damain::normalize_email;
+
+/// Why an address was suppressed, as stored in the `reason` column of `suppressions`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SuppressionReason {
+    /// Emails to the address bounced.
+    Bounced,
+    /// The recipient marked our emails as spam.
+    Complained,
+    /// The subscriber asked for their data to be erased.
+    Erased,
+    /// An admin suppressed the address for another reason.
+    Manual,
+}
+
+impl SuppressionReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SuppressionReason::Bounced => "bounced",
+            SuppressionReason::Complained => "complained",
+            SuppressionReason::Erased => "erased",
+            SuppressionReason::Manual => "manual",
+        }
+    }
+}
+
+/// An entry of the suppression list. It only applies while it has not been lifted.
+#[derive(Debug, serde::Serialize)]
+pub struct Suppression {
+    pub email_hash: String,
+    pub reason: String,
+    pub created_at: DateTime<Utc>,
+    pub lifted_at: Option<DateTime<Utc>>,
+    pub lift_reason: Option<String>,
+    pub lifted_by: Option<Uuid>,
+}
+
+/// Computes the key of an address in `suppressions`. The hash is keyed, with a secret of its own:
+/// without it, the entry of an erased address cannot be matched against a list of candidates.
+#[derive(Clone)]
+pub struct EmailHasher {
+    key: SecretString,
+}
+
+impl EmailHasher {
+    pub fn new(key: SecretString) -> Self {
+        Self { key }
+    }
+
+    /// The hex HMAC-SHA256 of the normalized address, or of the address trimmed and lowercased
+    /// if it cannot be normalized.
+    pub fn hash(&self, email: &str) -> String {
+        let normalized = normalize_email(email).unwrap_or_else(|| email.trim().to_lowercase());
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.key.expose_secret().as_bytes()).unwrap();
+        mac.update(normalized.as_bytes());
+        hex::encode(mac.finalize().into_bytes())
+    }
+}
+
+/// Suppress `email`. Suppressing an address twice keeps the first reason,
+/// suppressing an address whose suppression was lifted applies it again.
+#[tracing::instrument(name = "Suppress an email address", skip(executor, hasher, email))]
+pub async fn suppress<'e>(
+    executor: impl Executor<'e, Database = Postgres>,
+    hasher: &EmailHasher,
+    email: &str,
+    reason: SuppressionReason,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO suppressions (email_hash, reason) VALUES ($1, $2)
+        ON CONFLICT (email_hash) DO UPDATE
+        SET reason = EXCLUDED.reason, created_at = now(), lifted_at = NULL, lift_reason = NULL, lifted_by = NULL
+        WHERE suppressions.lifted_at IS NOT NULL
+        "#,
+        hasher.hash(email),
+        reason.as_str(),
+    )
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(name = "Check whether an email address is suppressed", skip(executor, hasher, email))]
+pub async fn is_suppressed<'e>(
+    executor: impl Executor<'e, Database = Postgres>,
+    hasher: &EmailHasher,
+    email: &str,
+) -> Result<bool, sqlx::Error> {
+    let row = sqlx::query!(
+        "SELECT reason FROM suppressions WHERE email_hash = $1 AND lifted_at IS NULL",
+        hasher.hash(email),
+    )
+    .fetch_optional(executor)
+    .await?;
+    Ok(row.is_some())
+}
+
+/// Up to `limit` suppressions ordered by hash, after `after` if given.
+#[tracing::instrument(name = "List suppressions", skip(db_pool))]
+pub async fn list_suppressions(
+    db_pool: &PgPool,
+    reason: Option<SuppressionReason>,
+    include_lifted: bool,
+    after: Option<&str>,
+    limit: i64,
+) -> Result<Vec<Suppression>, sqlx::Error> {
+    sqlx::query_as!(
+        Suppression,
+        r#"
+        SELECT email_hash, reason, created_at, lifted_at, lift_reason, lifted_by
+        FROM suppressions
+        WHERE ($1::text IS NULL OR reason = $1)
+            AND ($2 OR lifted_at IS NULL)
+            AND ($3::text IS NULL OR email_hash > $3)
+        ORDER BY email_hash
+        LIMIT $4
+        "#,
+        reason.map(|r| r.as_str()),
+        include_lifted,
+        after,
+        limit,
+    )
+    .fetch_all(db_pool)
+    .await
+}
+
+#[tracing::instrument(name = "Fetch a suppression", skip(db_pool))]
+pub async fn get_suppression(db_pool: &PgPool, email_hash: &str) -> Result<Option<Suppression>, sqlx::Error> {
+    sqlx::query_as!(
+        Suppression,
+        r#"
+        SELECT email_hash, reason, created_at, lifted_at, lift_reason, lifted_by
+        FROM suppressions
+        WHERE email_hash = $1
+        "#,
+        email_hash,
+    )
+    .fetch_optional(db_pool)
+    .await
+}
+
+/// Lift the suppression of `email`, recording who did and why.
+/// Returns `None` if the address is not suppressed.
+#[tracing::instrument(name = "Lift a suppression", skip(db_pool, hasher, email))]
+pub async fn lift_suppression(
+    db_pool: &PgPool,
+    hasher: &EmailHasher,
+    email: &str,
+    lift_reason: &str,
+    lifted_by: UserId,
+) -> Result<Option<Suppression>, sqlx::Error> {
+    sqlx::query_as!(
+        Suppression,
+        r#"
+        UPDATE suppressions
+        SET lifted_at = now(), lift_reason = $2, lifted_by = $3
+        WHERE email_hash = $1 AND lifted_at IS NULL
+        RETURNING email_hash, reason, created_at, lifted_at, lift_reason, lifted_by
+        "#,
+        hasher.hash(email),
+        lift_reason,
+        lifted_by.inner(),
+    )
+    .fetch_optional(db_pool)
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use secrecy::SecretString;
+    use super::EmailHasher;
+
+    fn hasher(key: &str) -> EmailHasher {
+        EmailHasher::new(SecretString::from(key))
+    }
+
+    #[test]
+    fn the_hash_ignores_case_and_surrounding_whitespace() {
+        let hasher = hasher("key");
+        assert_eq!(hasher.hash(" Ursula@Example.com\n"), hasher.hash("ursula@example.com"));
+    }
+
+    #[test]
+    fn the_hash_ignores_how_the_domain_is_encoded() {
+        let hasher = hasher("key");
+        assert_eq!(hasher.hash("leser@Bücher.de"), hasher.hash("leser@xn--bcher-kva.de"));
+    }
+
+    #[test]
+    fn the_hash_cannot_be_recomputed_without_the_key() {
+        assert_ne!(hasher("key").hash("ursula@example.com"), hasher("another key").hash("ursula@example.com"));
+    }
+}
@@ -1,3 +1,4 @@
+use std::io::Write;
 use std::time::Duration;
 use tracing_subscriber::{layer::SubscriberExt};
 
@@ -8,21 +9,24 @@ use tracing_bunyan_formatter::{BunyanFormattingLayer, JsonStorageLayer};
 use tracing_log::LogTracer;
 use tracing_subscriber::EnvFilter;
 use tracing_subscriber::fmt::{MakeWriter};
-use opentelemetry_sdk::trace::{RandomIdGenerator, Sampler, SdkTracerProvider};
+use opentelemetry_sdk::error::OTelSdkResult;
+use opentelemetry_sdk::metrics::{PeriodicReader, SdkMeterProvider};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{RandomIdGenerator, Sampler, SdkTracerProvider, SpanData, SpanExporter};
 use opentelemetry::{
     global,
     trace::{TracerProvider},
     KeyValue,
 };
-use opentelemetry_sdk::trace::Tracer as SdkTracer;
 use opentelemetry_otlp::{WithExportConfig};
-use crate::configuration::JaegerSettings;
+use crate::configuration::{MetricsSettings, SamplerSettings, TelemetrySettings, TraceExporterSettings};
+use crate::metrics::PrometheusExporter;
 
 pub fn get_subscriber<Sink>(
     name: String,
     env_filter: String,
     sink: Sink,
-    settings: &JaegerSettings,
+    tracer_provider: &SdkTracerProvider,
 ) -> impl Subscriber + Send + Sync
     where
         Sink: for<'a> MakeWriter<'a> + Send + Sync + 'static,
@@ -30,7 +34,7 @@ pub fn get_subscriber<Sink>(
     let env_filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(env_filter));
     let formatter_layer = BunyanFormattingLayer::new(name, sink);
 
-    let tracer = construct_open_telemetry_tracer(&settings.address, settings.port);
+    let tracer = tracer_provider.tracer(env!("CARGO_PKG_NAME"));
     let telemetry_layer = tracing_opentelemetry::layer().with_tracer(tracer);
 
     tracing_subscriber::Registry::default()
@@ -45,22 +49,114 @@ pub fn init_subscriber(subscriber: impl Subscriber + Send + Sync + 'static) {
     set_global_default(subscriber).expect("Failed to set global default subscriber");
 }
 
-pub fn construct_open_telemetry_tracer(address: &String, port: u16) -> SdkTracer {
-    let exporter = opentelemetry_otlp::SpanExporter::builder()
-        .with_tonic()
-        .with_endpoint(format!("http://{}:{}", address, port))
-        .with_timeout(Duration::from_secs(3))
-        .build().unwrap();
+/// The attributes identifying this service in traces and metrics.
+fn resource(settings: &TelemetrySettings) -> Resource {
+    Resource::builder_empty()
+        .with_attributes([
+            KeyValue::new("service.name", settings.service_name.clone()),
+            KeyValue::new("service.version", settings.service_version()),
+            KeyValue::new("deployment.environment.name", settings.environment.clone()),
+        ])
+        .build()
+}
 
-    let tracer_provider = SdkTracerProvider::builder()
-        .with_batch_exporter(exporter)
-        .with_sampler(Sampler::AlwaysOn)
+/// Spans are always recorded, so that requests get a trace id to propagate and to report in errors,
+/// even when they are not exported anywhere.
+///
+/// The provider must be shut down before exiting, to flush the spans still buffered.
+pub fn construct_tracer_provider(settings: &TelemetrySettings) -> Result<SdkTracerProvider, anyhow::Error> {
+    let sampler = match settings.sampler {
+        SamplerSettings::AlwaysOn => Sampler::AlwaysOn,
+        SamplerSettings::AlwaysOff => Sampler::AlwaysOff,
+        SamplerSettings::Ratio { ratio } => Sampler::TraceIdRatioBased(ratio),
+        SamplerSettings::ParentBased { ratio } => {
+            Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(ratio)))
+        }
+    };
+    let builder = SdkTracerProvider::builder()
+        .with_sampler(sampler)
         .with_id_generator(RandomIdGenerator::default())
         .with_max_events_per_span(64)
         .with_max_attributes_per_span(16)
-        .with_resource(Resource::builder_empty().with_attributes([KeyValue::new("service.name", "zero2prod")]).build())
-        .build();
-        
+        .with_resource(resource(settings));
+    let tracer_provider = match &settings.exporter {
+        TraceExporterSettings::None => builder.build(),
+        TraceExporterSettings::OtlpGrpc { endpoint } => {
+            let exporter = opentelemetry_otlp::SpanExporter::builder()
+                .with_tonic()
+                .with_endpoint(endpoint)
+                .with_timeout(Duration::from_secs(3))
+                .build()?;
+            builder.with_batch_exporter(exporter).build()
+        }
+        TraceExporterSettings::OtlpHttp { endpoint } => {
+            let exporter = opentelemetry_otlp::SpanExporter::builder()
+                .with_http()
+                .with_endpoint(endpoint)
+                .with_timeout(Duration::from_secs(3))
+                .build()?;
+            builder.with_batch_exporter(exporter).build()
+        }
+        TraceExporterSettings::Stdout => builder.with_simple_exporter(StdoutSpanExporter).build(),
+    };
+
     global::set_tracer_provider(tracer_provider.clone());
-    tracer_provider.tracer("tracer-name")
-}
\ No newline at end of file
+    // Read and write W3C `traceparent` headers, so that traces continue across services.
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    Ok(tracer_provider)
+}
+
+/// Print finished spans to stdout, one JSON object per line, for local debugging.
+#[derive(Debug)]
+struct StdoutSpanExporter;
+
+impl SpanExporter for StdoutSpanExporter {
+    async fn export(&self, batch: Vec<SpanData>) -> OTelSdkResult {
+        let mut stdout = std::io::stdout().lock();
+        for span in batch {
+            let attributes: serde_json::Map<String, serde_json::Value> = span
+                .attributes
+                .iter()
+                .map(|kv| (kv.key.to_string(), kv.value.to_string().into()))
+                .collect();
+            let line = serde_json::json!({
+                "name": span.name,
+                "trace_id": span.span_context.trace_id().to_string(),
+                "span_id": span.span_context.span_id().to_string(),
+                "parent_span_id": span.parent_span_id.to_string(),
+                "kind": format!("{:?}", span.span_kind),
+                "start_time": chrono::DateTime::<chrono::Utc>::from(span.start_time).to_rfc3339(),
+                "end_time": chrono::DateTime::<chrono::Utc>::from(span.end_time).to_rfc3339(),
+                "status": format!("{:?}", span.status),
+                "attributes": attributes,
+            });
+            // Tracing must never take the application down: drop the span instead.
+            let _ = writeln!(stdout, "{}", line);
+        }
+        Ok(())
+    }
+}
+
+/// Build the metrics pipeline: a Prometheus reader to scrape, an OTLP exporter to push to, or both.
+pub fn construct_meter_provider(
+    settings: &MetricsSettings,
+    telemetry: &TelemetrySettings,
+) -> Result<(SdkMeterProvider, Option<PrometheusExporter>), anyhow::Error> {
+    let mut builder = SdkMeterProvider::builder().with_resource(resource(telemetry));
+    let prometheus_exporter = settings.prometheus_enabled.then(PrometheusExporter::default);
+    if let Some(exporter) = &prometheus_exporter {
+        builder = builder.with_reader(exporter.clone());
+    }
+    if let Some(endpoint) = &settings.otlp_endpoint {
+        let exporter = opentelemetry_otlp::MetricExporter::builder()
+            .with_tonic()
+            .with_endpoint(endpoint)
+            .with_timeout(Duration::from_secs(3))
+            .build()?;
+        let reader = PeriodicReader::builder(exporter)
+            .with_interval(Duration::from_secs(settings.export_interval_seconds))
+            .build();
+        builder = builder.with_reader(reader);
+    }
+    Ok((builder.build(), prometheus_exporter))
+}
@@ -0,0 +1,354 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, SecretString};
+use sha2::Sha256;
+use uuid::Uuid;
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrackingKind {
+    #[serde(rename = "o")]
+    Open,
+    #[serde(rename = "c")]
+    Click,
+    #[serde(rename = "u")]
+    OptOut,
+}
+
+impl TrackingKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TrackingKind::Open => "open",
+            TrackingKind::Click => "click",
+            TrackingKind::OptOut => "opt_out",
+        }
+    }
+}
+
+/// The payload carried by tracking links.
+/// The destination of click links is part of the signed payload, so the redirect
+/// endpoint can only send people to URLs that were in the newsletter we sent.
+#[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq, Eq)]
+pub struct TrackingToken {
+    #[serde(rename = "k")]
+    pub kind: TrackingKind,
+    #[serde(rename = "i")]
+    pub newsletter_issue_id: Uuid,
+    #[serde(rename = "s")]
+    pub subscriber_id: Uuid,
+    #[serde(rename = "u", skip_serializing_if = "Option::is_none", default)]
+    pub url: Option<String>,
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("The tracking token is invalid.")]
+pub struct InvalidTrackingToken;
+
+/// Signs and verifies tracking tokens, and instruments outgoing newsletters with them.
+#[derive(Clone)]
+pub struct LinkTracker {
+    base_url: String,
+    hmac_secret: SecretString,
+}
+
+impl LinkTracker {
+    pub fn new(base_url: String, hmac_secret: SecretString) -> Self {
+        Self { base_url, hmac_secret }
+    }
+
+    fn mac(&self) -> Hmac<Sha256> {
+        Hmac::<Sha256>::new_from_slice(self.hmac_secret.expose_secret().as_bytes()).unwrap()
+    }
+
+    /// Encode the token as `<base64url payload>.<hex hmac>`.
+    pub fn sign(&self, token: &TrackingToken) -> String {
+        let payload = URL_SAFE_NO_PAD.encode(serde_json::to_vec(token).unwrap());
+        let mut mac = self.mac();
+        mac.update(payload.as_bytes());
+        let tag = hex::encode(mac.finalize().into_bytes());
+        format!("{}.{}", payload, tag)
+    }
+
+    pub fn verify(&self, token: &str) -> Result<TrackingToken, InvalidTrackingToken> {
+        let (payload, tag) = token.split_once('.').ok_or(InvalidTrackingToken)?;
+        let tag = hex::decode(tag).map_err(|_| InvalidTrackingToken)?;
+        let mut mac = self.mac();
+        mac.update(payload.as_bytes());
+        mac.verify_slice(&tag).map_err(|_| InvalidTrackingToken)?;
+
+        let payload = URL_SAFE_NO_PAD.decode(payload).map_err(|_| InvalidTrackingToken)?;
+        serde_json::from_slice(&payload).map_err(|_| InvalidTrackingToken)
+    }
+
+    fn tracking_url(&self, path: &str, token: &TrackingToken) -> String {
+        format!("{}/t/{}/{}", self.base_url, path, self.sign(token))
+    }
+
+    /// Route every http(s) link through the click endpoint, then append the opt-out link
+    /// and the open-tracking pixel.
+    pub fn instrument_html(&self, html: &str, newsletter_issue_id: Uuid, subscriber_id: Uuid) -> String {
+        let token = |kind, url| TrackingToken {
+            kind,
+            newsletter_issue_id,
+            subscriber_id,
+            url,
+        };
+        let mut html = rewrite_links(html, |url| {
+            self.tracking_url("c", &token(TrackingKind::Click, Some(url.to_owned())))
+        });
+
+        let footer = format!(
+            "<p><a href=\"{}\">Stop tracking opens and clicks</a></p>\
+            <img src=\"{}.gif\" width=\"1\" height=\"1\" alt=\"\" />",
+            self.tracking_url("u", &token(TrackingKind::OptOut, None)),
+            self.tracking_url("o", &token(TrackingKind::Open, None)),
+        );
+        match html.rfind("</body>") {
+            Some(position) => html.insert_str(position, &footer),
+            None => html.push_str(&footer),
+        }
+        html
+    }
+}
+
+/// Replace the target of every http(s) link (`<a href>` and `<area href>`) with the output of
+/// `rewrite`, which receives the URL with its character references decoded.
+/// Other links (anchors, `mailto:`...) and other attributes are left untouched.
+fn rewrite_links(html: &str, mut rewrite: impl FnMut(&str) -> String) -> String {
+    let bytes = html.as_bytes();
+    let mut output = String::with_capacity(html.len());
+    let mut copied = 0;
+    let mut i = 0;
+    while let Some(offset) = html[i..].find('<') {
+        let tag_start = i + offset;
+        if html[tag_start..].starts_with("<!--") {
+            i = html[tag_start..]
+                .find("-->")
+                .map_or(html.len(), |end| tag_start + end + "-->".len());
+            continue;
+        }
+        i = tag_start + 1;
+        let name = take_while(bytes, &mut i, |b| !b.is_ascii_whitespace() && b != b'>' && b != b'/');
+        let is_link = name.eq_ignore_ascii_case("a") || name.eq_ignore_ascii_case("area");
+
+        // Walk the attributes up to the end of the tag.
+        loop {
+            take_while(bytes, &mut i, |b| b.is_ascii_whitespace() || b == b'/');
+            if i >= bytes.len() {
+                break;
+            }
+            if bytes[i] == b'>' {
+                i += 1;
+                break;
+            }
+            let attribute = take_while(bytes, &mut i, |b| {
+                !b.is_ascii_whitespace() && !matches!(b, b'=' | b'>' | b'/')
+            });
+            if attribute.is_empty() {
+                i += 1;
+                continue;
+            }
+            take_while(bytes, &mut i, |b| b.is_ascii_whitespace());
+            if bytes.get(i) != Some(&b'=') {
+                continue;
+            }
+            i += 1;
+            take_while(bytes, &mut i, |b| b.is_ascii_whitespace());
+            let (value_start, value_end) = match bytes.get(i) {
+                Some(&quote) if quote == b'"' || quote == b'\'' => {
+                    i += 1;
+                    let value_start = i;
+                    take_while(bytes, &mut i, |b| b != quote);
+                    let value_end = i;
+                    i = (i + 1).min(bytes.len());
+                    (value_start, value_end)
+                }
+                _ => {
+                    let value_start = i;
+                    take_while(bytes, &mut i, |b| !b.is_ascii_whitespace() && b != b'>');
+                    (value_start, i)
+                }
+            };
+
+            if is_link && attribute.eq_ignore_ascii_case("href") {
+                let url = decode_character_references(&html[value_start..value_end]);
+                let url = url.trim();
+                if has_scheme(url, "http://") || has_scheme(url, "https://") {
+                    output.push_str(&html[copied..value_start]);
+                    output.push_str(&rewrite(url));
+                    copied = value_end;
+                }
+            }
+        }
+    }
+    output.push_str(&html[copied..]);
+    output
+}
+
+/// Advance `i` past the bytes matching `predicate` and return them.
+/// Only ever stops on ASCII bytes, so the returned slice is valid UTF-8.
+fn take_while<'a>(bytes: &'a [u8], i: &mut usize, predicate: impl Fn(u8) -> bool) -> &'a str {
+    let start = *i;
+    while *i < bytes.len() && predicate(bytes[*i]) {
+        *i += 1;
+    }
+    std::str::from_utf8(&bytes[start..*i]).unwrap()
+}
+
+fn has_scheme(url: &str, scheme: &str) -> bool {
+    url.get(..scheme.len())
+        .is_some_and(|prefix| prefix.eq_ignore_ascii_case(scheme))
+}
+
+/// Decode numeric character references (`&#38;`, `&#x26;`) and the named ones that show up
+/// in URLs. Anything else is kept as written.
+fn decode_character_references(value: &str) -> std::borrow::Cow<'_, str> {
+    if !value.contains('&') {
+        return value.into();
+    }
+    let mut decoded = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let reference = rest[1..]
+            .find(';')
+            .map(|end| &rest[1..1 + end])
+            .filter(|reference| reference.len() <= 10);
+        let character = reference.and_then(|reference| match reference {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "nbsp" => Some('\u{a0}'),
+            _ => {
+                let number = reference.strip_prefix('#')?;
+                let code = match number.strip_prefix(['x', 'X']) {
+                    Some(hex) => u32::from_str_radix(hex, 16),
+                    None => number.parse(),
+                };
+                char::from_u32(code.ok()?)
+            }
+        });
+        match (reference, character) {
+            (Some(reference), Some(character)) => {
+                decoded.push(character);
+                rest = &rest[reference.len() + 2..];
+            }
+            _ => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use claim::{assert_err, assert_ok};
+
+    fn tracker() -> LinkTracker {
+        LinkTracker::new("https://example.com".into(), SecretString::from("secret"))
+    }
+
+    fn click_token() -> TrackingToken {
+        TrackingToken {
+            kind: TrackingKind::Click,
+            newsletter_issue_id: Uuid::new_v4(),
+            subscriber_id: Uuid::new_v4(),
+            url: Some("https://www.rust-lang.org".into()),
+        }
+    }
+
+    #[test]
+    fn a_signed_token_is_verified() {
+        let token = click_token();
+        let signed = tracker().sign(&token);
+        assert_eq!(tracker().verify(&signed).unwrap(), token);
+    }
+
+    #[test]
+    fn a_token_signed_with_another_secret_is_rejected() {
+        let other = LinkTracker::new("https://example.com".into(), SecretString::from("other"));
+        let signed = other.sign(&click_token());
+        assert_err!(tracker().verify(&signed));
+    }
+
+    #[test]
+    fn a_token_with_a_tampered_url_is_rejected() {
+        let signed = tracker().sign(&click_token());
+        let (_, tag) = signed.split_once('.').unwrap();
+        let mut forged = click_token();
+        forged.url = Some("https://evil.example.com".into());
+        let forged_payload = URL_SAFE_NO_PAD.encode(serde_json::to_vec(&forged).unwrap());
+        assert_err!(tracker().verify(&format!("{}.{}", forged_payload, tag)));
+    }
+
+    #[test]
+    fn garbage_is_rejected() {
+        assert_err!(tracker().verify("not-a-token"));
+        assert_err!(tracker().verify("abc.zz"));
+    }
+
+    #[test]
+    fn only_http_links_are_rewritten() {
+        let html = r##"<a href="https://a.com/?x=1&amp;y=2">a</a> <a href="#top">b</a> <a href='mailto:x@y.com'>c</a>"##;
+        let rewritten = rewrite_links(html, |url| format!("[{}]", url));
+        assert_eq!(
+            rewritten,
+            r##"<a href="[https://a.com/?x=1&y=2]">a</a> <a href="#top">b</a> <a href='mailto:x@y.com'>c</a>"##
+        );
+    }
+
+    #[test]
+    fn links_are_found_however_the_attribute_is_written() {
+        let html = concat!(
+            r#"<A HREF="HTTPS://a.com">a</A> <a class="x" href = 'http://b.com'>b</a> "#,
+            r#"<a href=https://c.com>c</a> <area shape="rect" href="https://d.com">"#,
+        );
+        let rewritten = rewrite_links(html, |url| format!("[{}]", url));
+        assert_eq!(
+            rewritten,
+            concat!(
+                r#"<A HREF="[HTTPS://a.com]">a</A> <a class="x" href = '[http://b.com]'>b</a> "#,
+                r#"<a href=[https://c.com]>c</a> <area shape="rect" href="[https://d.com]">"#,
+            )
+        );
+    }
+
+    #[test]
+    fn character_references_in_links_are_decoded() {
+        let html = r#"<a href="https://a.com/?x=1&#38;y=&quot;2&quot;&#x26;z=&unknown;">a</a>"#;
+        let rewritten = rewrite_links(html, |url| format!("[{}]", url));
+        assert_eq!(rewritten, r#"<a href="[https://a.com/?x=1&y="2"&z=&unknown;]">a</a>"#);
+    }
+
+    #[test]
+    fn attributes_and_tags_that_are_not_links_are_left_alone() {
+        let html = concat!(
+            r#"<link href="https://a.com/style.css"> <a data-href="https://b.com" title="href=https://c.com">b</a> "#,
+            r#"<!-- <a href="https://d.com"> --> <p>href="https://e.com"</p>"#,
+        );
+        assert_eq!(rewrite_links(html, |url| format!("[{}]", url)), html);
+    }
+
+    #[test]
+    fn instrumented_html_links_carry_valid_tokens() {
+        let tracker = tracker();
+        let html = tracker.instrument_html(
+            r#"<html><body><a href="https://www.rust-lang.org">Rust</a></body></html>"#,
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+        );
+        assert!(html.ends_with("</body></html>"));
+        let click = html.split("https://example.com/t/c/").nth(1).unwrap();
+        let token = click.split('"').next().unwrap();
+        assert_ok!(tracker.verify(token));
+        assert!(html.contains("https://example.com/t/o/"));
+        assert!(html.contains("https://example.com/t/u/"));
+    }
+}
@@ -0,0 +1,231 @@
+use crate::helpers::{create_draft_with_body, preview_draft, spawn_app, TestApp};
+
+/// Publish an issue and return its slug.
+async fn publish_issue(app: &TestApp, title: &str, public: bool) -> String {
+    let body = serde_json::json!({
+        "title": title,
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        },
+        "public": public,
+    });
+    let newsletter_issue_id = create_draft_with_body(app, &body).await;
+    preview_draft(app, &newsletter_issue_id).await;
+    app.post_publish_newsletter(&newsletter_issue_id)
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let issue: serde_json::Value = app
+        .get_admin(&format!("/newsletters/{}", newsletter_issue_id))
+        .await
+        .json()
+        .await
+        .unwrap();
+    issue["slug"].as_str().unwrap().to_owned()
+}
+
+#[tokio::test]
+async fn the_archive_lists_public_published_issues_only() {
+    let app = spawn_app().await;
+    publish_issue(&app, "Public issue", true).await;
+    publish_issue(&app, "Private issue", false).await;
+    create_draft_with_body(
+        &app,
+        &serde_json::json!({
+            "title": "Draft issue",
+            "content": {"text": "Draft", "html": "<p>Draft</p>"},
+            "public": true,
+        }),
+    )
+    .await;
+
+    let response = reqwest::get(format!("{}/archive", app.address)).await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 200);
+    let html = response.text().await.unwrap();
+    assert!(html.contains(r#"<a href="/archive/public-issue">Public issue</a>"#));
+    assert!(!html.contains("Private issue"));
+    assert!(!html.contains("Draft issue"));
+}
+
+#[tokio::test]
+async fn a_public_issue_can_be_read_on_the_web() {
+    let app = spawn_app().await;
+    let slug = publish_issue(&app, "Public issue", true).await;
+
+    let response = reqwest::get(format!("{}/archive/{}", app.address, slug)).await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 200);
+    let html = response.text().await.unwrap();
+    assert!(html.contains("<p>Newsletter body as HTML</p>"));
+}
+
+#[tokio::test]
+async fn private_and_unknown_issues_are_not_found() {
+    let app = spawn_app().await;
+    let slug = publish_issue(&app, "Private issue", false).await;
+
+    for slug in [slug.as_str(), "does-not-exist"] {
+        let response = reqwest::get(format!("{}/archive/{}", app.address, slug)).await.unwrap();
+        assert_eq!(response.status().as_u16(), 404);
+    }
+}
+
+#[tokio::test]
+async fn issues_with_the_same_title_get_distinct_slugs() {
+    let app = spawn_app().await;
+    let first = publish_issue(&app, "Weekly", true).await;
+    let second = publish_issue(&app, "Weekly", true).await;
+
+    assert_eq!(first, "weekly");
+    assert_ne!(first, second);
+    assert!(second.starts_with("weekly-"));
+}
+
+#[tokio::test]
+async fn an_issue_published_while_another_takes_its_slug_gets_a_distinct_one() {
+    let app = spawn_app().await;
+    let body = serde_json::json!({
+        "title": "Weekly",
+        "content": {"text": "Newsletter body as plain text", "html": "<p>Newsletter body as HTML</p>"},
+        "public": true,
+    });
+    let other_issue_id = create_draft_with_body(&app, &body).await;
+    let newsletter_issue_id = create_draft_with_body(&app, &body).await;
+    preview_draft(&app, &newsletter_issue_id).await;
+    // Another publication holds the slug, without having committed yet.
+    let mut other = app.db_pool.begin().await.unwrap();
+    sqlx::query!(
+        "UPDATE newsletter_issues SET slug = 'weekly' WHERE newsletter_issue_id = $1",
+        other_issue_id.parse::<uuid::Uuid>().unwrap(),
+    )
+    .execute(&mut *other)
+    .await
+    .unwrap();
+
+    let db_pool = app.db_pool.clone();
+    let (response, ()) = tokio::join!(app.post_publish_newsletter(&newsletter_issue_id), async move {
+        // Commit once the publication waits on the slug of the other one.
+        loop {
+            let waiting = sqlx::query_scalar!(
+                r#"SELECT COUNT(*) AS "count!" FROM pg_stat_activity
+                WHERE datname = current_database() AND wait_event_type = 'Lock'"#
+            )
+            .fetch_one(&db_pool)
+            .await
+            .unwrap();
+            if waiting > 0 {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        other.commit().await.unwrap();
+    });
+
+    assert_eq!(response.status().as_u16(), 200);
+    let slug = sqlx::query_scalar!(
+        "SELECT slug FROM newsletter_issues WHERE newsletter_issue_id = $1",
+        newsletter_issue_id.parse::<uuid::Uuid>().unwrap(),
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert!(slug.unwrap().starts_with("weekly-"));
+}
+
+#[tokio::test]
+async fn issues_can_be_hidden_from_the_archive_after_publishing() {
+    let app = spawn_app().await;
+    publish_issue(&app, "Public issue", true).await;
+    let issue: serde_json::Value = sqlx::query_scalar!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .map(|id| serde_json::json!(id))
+        .unwrap();
+
+    let response = reqwest::Client::new()
+        .put(format!("{}/admin/newsletters/{}/visibility", app.address, issue.as_str().unwrap()))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .json(&serde_json::json!({ "public": false }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+
+    let html = reqwest::get(format!("{}/archive", app.address)).await.unwrap().text().await.unwrap();
+    assert!(!html.contains("Public issue"));
+}
+
+#[tokio::test]
+async fn the_feeds_carry_public_issues() {
+    let app = spawn_app().await;
+    publish_issue(&app, "Public issue", true).await;
+    publish_issue(&app, "Private issue", false).await;
+
+    let response = reqwest::get(format!("{}/feed.xml", app.address)).await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.headers()["Content-Type"], "application/atom+xml; charset=utf-8");
+    let atom = response.text().await.unwrap();
+    assert!(atom.contains("<title>Public issue</title>"));
+    assert!(atom.contains("<id>http://0.0.0.0/archive/public-issue</id>"));
+    assert!(atom.contains("&lt;p&gt;Newsletter body as HTML&lt;/p&gt;"));
+    assert!(!atom.contains("Private issue"));
+
+    let response = reqwest::get(format!("{}/feed.rss", app.address)).await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.headers()["Content-Type"], "application/rss+xml; charset=utf-8");
+    let rss = response.text().await.unwrap();
+    assert!(rss.contains("<link>http://0.0.0.0/archive/public-issue</link>"));
+    assert!(!rss.contains("Private issue"));
+}
+
+#[tokio::test]
+async fn unchanged_content_is_not_sent_again() {
+    let app = spawn_app().await;
+    publish_issue(&app, "Public issue", true).await;
+    let client = reqwest::Client::new();
+
+    for path in ["/archive", "/archive/public-issue", "/feed.xml", "/feed.rss"] {
+        let response = client.get(format!("{}{}", app.address, path)).send().await.unwrap();
+        let etag = response.headers()["ETag"].clone();
+        let last_modified = response.headers()["Last-Modified"].clone();
+
+        let response = client
+            .get(format!("{}{}", app.address, path))
+            .header("If-None-Match", etag)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status().as_u16(), 304, "If-None-Match on {}", path);
+
+        let response = client
+            .get(format!("{}{}", app.address, path))
+            .header("If-Modified-Since", last_modified)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status().as_u16(), 304, "If-Modified-Since on {}", path);
+    }
+}
+
+#[tokio::test]
+async fn a_new_issue_invalidates_the_cached_feed() {
+    let app = spawn_app().await;
+    publish_issue(&app, "First issue", true).await;
+    let client = reqwest::Client::new();
+    let response = client.get(format!("{}/feed.xml", app.address)).send().await.unwrap();
+    let etag = response.headers()["ETag"].clone();
+
+    publish_issue(&app, "Second issue", true).await;
+
+    let response = client
+        .get(format!("{}/feed.xml", app.address))
+        .header("If-None-Match", etag)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.text().await.unwrap().contains("Second issue"));
+}
@@ -0,0 +1,238 @@
+use secrecy::SecretString;
+use wiremock::matchers::{body_string_contains, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+use zero_to_production_rust_book::configuration::CaptchaSettings;
+use crate::helpers::{spawn_app, spawn_app_with, TestApp};
+
+async fn mock_email_api(app: &TestApp, expected_emails: u64) {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(expected_emails)
+        .mount(&app.email_server)
+        .await;
+}
+
+async fn subscribe(app: &TestApp, extra_fields: serde_json::Value) -> reqwest::Response {
+    let mut body = serde_json::json!({"name": "le guin", "email": "ursula_le_guin@gmail.com"});
+    body.as_object_mut().unwrap().extend(extra_fields.as_object().unwrap().clone());
+    app.post_subscriptions_json(&body).await
+}
+
+async fn form_token(app: &TestApp) -> String {
+    let form: serde_json::Value = app.get_subscription_form().await.json().await.unwrap();
+    form["form_token"].as_str().unwrap().to_owned()
+}
+
+async fn assert_bot_check_failed(response: reqwest::Response) {
+    assert_eq!(400, response.status().as_u16());
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["type"], "/problems/bot-check-failed");
+}
+
+#[tokio::test]
+async fn the_form_endpoint_issues_an_uncacheable_token() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app.get_subscription_form().await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(response.headers()["Cache-Control"], "no-store");
+    let form: serde_json::Value = response.json().await.unwrap();
+    assert!(form["form_token"].as_str().unwrap().contains('.'));
+    assert_eq!(form["honeypot_field"], "website");
+    assert_eq!(form["captcha_required"], false);
+}
+
+#[tokio::test]
+async fn no_bot_check_is_enabled_by_default() {
+    // Arrange
+    let app = spawn_app().await;
+    mock_email_api(&app, 1).await;
+
+    // Act
+    let response = subscribe(&app, serde_json::json!({"website": "https://spam.example.com"})).await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn a_filled_honeypot_is_silently_ignored() {
+    // Arrange
+    let app = spawn_app_with(|c| c.bot_protection.honeypot = true).await;
+    mock_email_api(&app, 0).await;
+
+    // Act
+    let response = subscribe(&app, serde_json::json!({"website": "https://spam.example.com"})).await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let saved = sqlx::query!("SELECT id FROM subscriptions").fetch_all(&app.db_pool).await.unwrap();
+    assert!(saved.is_empty());
+}
+
+#[tokio::test]
+async fn a_required_form_token_must_be_submitted() {
+    // Arrange
+    let app = spawn_app_with(|c| c.bot_protection.form_token.required = true).await;
+    mock_email_api(&app, 0).await;
+
+    // Act
+    let response = subscribe(&app, serde_json::json!({})).await;
+
+    // Assert
+    assert_bot_check_failed(response).await;
+}
+
+#[tokio::test]
+async fn a_form_submitted_faster_than_a_person_could_is_rejected() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.bot_protection.form_token.required = true;
+        c.bot_protection.form_token.min_fill_seconds = 60;
+    })
+    .await;
+    mock_email_api(&app, 0).await;
+    let token = form_token(&app).await;
+
+    // Act
+    let response = subscribe(&app, serde_json::json!({"form_token": token})).await;
+
+    // Assert
+    assert_bot_check_failed(response).await;
+}
+
+#[tokio::test]
+async fn a_valid_form_token_lets_the_subscription_through() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.bot_protection.form_token.required = true;
+        c.bot_protection.form_token.min_fill_seconds = 0;
+    })
+    .await;
+    mock_email_api(&app, 1).await;
+    let token = form_token(&app).await;
+
+    // Act
+    let response = subscribe(&app, serde_json::json!({"form_token": token})).await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn a_form_token_is_only_accepted_once() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.bot_protection.form_token.required = true;
+        c.bot_protection.form_token.min_fill_seconds = 0;
+    })
+    .await;
+    mock_email_api(&app, 1).await;
+    let token = form_token(&app).await;
+    let response = subscribe(&app, serde_json::json!({"form_token": token})).await;
+    assert_eq!(200, response.status().as_u16());
+
+    // Act
+    let replay = serde_json::json!({"form_token": token, "email": "another_address@gmail.com"});
+    let response = subscribe(&app, replay).await;
+
+    // Assert
+    assert_bot_check_failed(response).await;
+}
+
+#[tokio::test]
+async fn a_form_token_can_be_resubmitted_after_fixing_an_invalid_field() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.bot_protection.form_token.required = true;
+        c.bot_protection.form_token.min_fill_seconds = 0;
+    })
+    .await;
+    mock_email_api(&app, 1).await;
+    let token = form_token(&app).await;
+    let response = subscribe(&app, serde_json::json!({"form_token": token, "email": "not-an-email"})).await;
+    assert_eq!(400, response.status().as_u16());
+
+    // Act
+    let response = subscribe(&app, serde_json::json!({"form_token": token})).await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn a_required_captcha_must_be_solved() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.bot_protection.captcha = CaptchaSettings::Fake { accepted_response: "solved".into() };
+    })
+    .await;
+    mock_email_api(&app, 1).await;
+
+    // Act
+    let unsolved = subscribe(&app, serde_json::json!({"captcha_response": "guessed"})).await;
+    let solved = subscribe(&app, serde_json::json!({"captcha_response": "solved"})).await;
+
+    // Assert
+    assert_bot_check_failed(unsolved).await;
+    assert_eq!(200, solved.status().as_u16());
+}
+
+#[tokio::test]
+async fn captcha_responses_are_verified_with_the_provider() {
+    // Arrange
+    let captcha_provider = MockServer::start().await;
+    Mock::given(path("/siteverify"))
+        .and(method("POST"))
+        .and(body_string_contains("secret=captcha-secret"))
+        .and(body_string_contains("response=solved"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({"success": true})))
+        .expect(1)
+        .mount(&captcha_provider)
+        .await;
+    let app = spawn_app_with(|c| {
+        c.bot_protection.captcha = CaptchaSettings::SiteVerify {
+            verify_url: format!("{}/siteverify", captcha_provider.uri()),
+            secret: SecretString::from("captcha-secret"),
+            timeout_milliseconds: 1000,
+        };
+    })
+    .await;
+    mock_email_api(&app, 1).await;
+
+    // Act
+    let response = subscribe(&app, serde_json::json!({"captcha_response": "solved"})).await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn an_unreachable_captcha_provider_is_a_server_error() {
+    // Arrange
+    let captcha_provider = MockServer::start().await;
+    Mock::given(path("/siteverify"))
+        .respond_with(ResponseTemplate::new(503))
+        .mount(&captcha_provider)
+        .await;
+    let app = spawn_app_with(|c| {
+        c.bot_protection.captcha = CaptchaSettings::SiteVerify {
+            verify_url: format!("{}/siteverify", captcha_provider.uri()),
+            secret: SecretString::from("captcha-secret"),
+            timeout_milliseconds: 1000,
+        };
+    })
+    .await;
+    mock_email_api(&app, 0).await;
+
+    // Act
+    let response = subscribe(&app, serde_json::json!({"captcha_response": "solved"})).await;
+
+    // Assert
+    assert_eq!(500, response.status().as_u16());
+}
@@ -0,0 +1,200 @@
+use secrecy::SecretString;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero_to_production_rust_book::authentication::{validate_credentials, Credentials};
+use zero_to_production_rust_book::cli::{create_admin, delete_subscriber, export_subscribers, import_subscribers, send_test_email};
+use zero_to_production_rust_book::damain::SubscriptionStatus;
+use zero_to_production_rust_book::import::ImportMode;
+use zero_to_production_rust_book::subscribers::{purge_expired_tokens, ExportFormat};
+use crate::helpers::{create_confirmed_subscriber, create_unconfirmed_subscriber, spawn_app};
+
+fn confirmed() -> ImportMode {
+    ImportMode::Confirmed {
+        consent_source: "Previous provider".into(),
+    }
+}
+
+#[tokio::test]
+async fn a_created_admin_can_log_in() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let user_id = create_admin(&app.db_pool, "ops", SecretString::from("correct horse battery staple"))
+        .await
+        .unwrap();
+
+    // Assert
+    let credentials = Credentials {
+        username: "ops".into(),
+        password: SecretString::from("correct horse battery staple"),
+    };
+    assert_eq!(validate_credentials(credentials, &app.db_pool).await.unwrap(), user_id);
+}
+
+#[tokio::test]
+async fn creating_an_admin_with_a_taken_username_fails() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let outcome = create_admin(&app.db_pool, &app.test_user.username, SecretString::from("password")).await;
+
+    // Assert
+    assert!(outcome.is_err());
+}
+
+#[tokio::test]
+async fn import_reports_invalid_rows_and_skips_existing_subscribers() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let file = "Name,Email\n\
+        Ada Lovelace,ada@example.com\n\
+        Nobody,not-an-email\n\
+        \"Le Guin, Ursula\",ursula_le_guin@gmail.com\n";
+    let mut out = Vec::new();
+
+    // Act
+    let summary = import_subscribers(&app.db_pool, &app.email_hasher, &confirmed(), file.as_bytes(), &mut out)
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(summary.imported, 1);
+    assert_eq!(summary.already_subscribed, 1);
+    assert_eq!(summary.invalid, 1);
+    let report = String::from_utf8(out).unwrap();
+    assert!(report.contains("Line 3: The email"), "{}", report);
+    let saved = sqlx::query!("SELECT name, status FROM subscriptions WHERE email = 'ada@example.com'")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.name, "Ada Lovelace");
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn import_requires_an_email_column() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let input = "name\nAda\n".as_bytes();
+    let outcome = import_subscribers(&app.db_pool, &app.email_hasher, &confirmed(), input, &mut Vec::new()).await;
+
+    // Assert
+    assert!(outcome.is_err());
+}
+
+#[tokio::test]
+async fn export_writes_the_subscribers_with_the_requested_status() {
+    // Arrange
+    let app = spawn_app().await;
+    create_unconfirmed_subscriber(&app).await;
+    import_subscribers(
+        &app.db_pool,
+        &app.email_hasher,
+        &confirmed(),
+        "email,name\nada@example.com,Ada\n".as_bytes(),
+        &mut Vec::new(),
+    )
+        .await
+        .unwrap();
+
+    // Act
+    let mut csv = Vec::new();
+    export_subscribers(&app.db_pool, Some(SubscriptionStatus::Confirmed), ExportFormat::Csv, &mut csv)
+        .await
+        .unwrap();
+    let mut jsonl = Vec::new();
+    export_subscribers(&app.db_pool, None, ExportFormat::Jsonl, &mut jsonl)
+        .await
+        .unwrap();
+
+    // Assert
+    let csv = String::from_utf8(csv).unwrap();
+    let lines: Vec<_> = csv.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0], "id,email,name,status,subscribed_at,confirmed_at");
+    assert!(lines[1].contains("ada@example.com,Ada,confirmed"));
+    let jsonl = String::from_utf8(jsonl).unwrap();
+    let records: Vec<serde_json::Value> = jsonl.lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[0]["status"], "pending_confirmation");
+}
+
+#[tokio::test]
+async fn deleting_a_subscriber_by_email_removes_their_tokens() {
+    // Arrange
+    let app = spawn_app().await;
+    create_unconfirmed_subscriber(&app).await;
+
+    // Act
+    delete_subscriber(&app.db_pool, "ursula_le_guin@gmail.com").await.unwrap();
+
+    // Assert
+    let subscribers = sqlx::query!("SELECT COUNT(*) AS \"count!\" FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(subscribers.count, 0);
+    let tokens = sqlx::query!("SELECT COUNT(*) AS \"count!\" FROM subscription_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(tokens.count, 0);
+}
+
+#[tokio::test]
+async fn deleting_an_unknown_subscriber_fails() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let outcome = delete_subscriber(&app.db_pool, "nobody@example.com").await;
+
+    // Assert
+    assert!(outcome.is_err());
+}
+
+#[tokio::test]
+async fn only_expired_tokens_are_purged() {
+    // Arrange
+    let app = spawn_app().await;
+    create_unconfirmed_subscriber(&app).await;
+    sqlx::query!("UPDATE subscription_tokens SET created_at = now() - interval '8 days'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let not_yet = purge_expired_tokens(&app.db_pool, chrono::Utc::now() - chrono::Duration::days(10))
+        .await
+        .unwrap();
+    let purged = purge_expired_tokens(&app.db_pool, chrono::Utc::now() - chrono::Duration::days(7))
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(not_yet, 0);
+    assert_eq!(purged, 1);
+}
+
+#[tokio::test]
+async fn the_test_email_goes_through_the_email_api() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let outcome = send_test_email(&app.email_client, "ops@example.com".into()).await;
+
+    // Assert
+    assert!(outcome.is_ok());
+}
@@ -0,0 +1,106 @@
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero_to_production_rust_book::configuration::MailServerCheckSettings;
+use crate::helpers::{spawn_app, spawn_app_with, TestApp};
+
+async fn subscribe(app: &TestApp, email: &str) -> reqwest::Response {
+    app.post_subscriptions_json(&serde_json::json!({"name": "le guin", "email": email}))
+        .await
+}
+
+/// The single field error of a rejected subscription.
+async fn email_error(response: reqwest::Response) -> serde_json::Value {
+    assert_eq!(400, response.status().as_u16());
+    let problem: serde_json::Value = response.json().await.unwrap();
+    let errors = problem["errors"].as_array().unwrap();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0]["field"], "email");
+    errors[0].clone()
+}
+
+#[tokio::test]
+async fn no_deliverability_check_is_enabled_by_default() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = subscribe(&app, "postmaster@gmial.com").await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn a_mistyped_domain_is_rejected_with_a_suggestion() {
+    // Arrange
+    let app = spawn_app_with(|c| c.deliverability.suggest_typo_corrections = true).await;
+
+    // Act
+    let response = subscribe(&app, "ursula_le_guin@gmial.com").await;
+
+    // Assert
+    let error = email_error(response).await;
+    assert_eq!(error["code"], "possible_typo");
+    assert_eq!(error["suggestion"], "ursula_le_guin@gmail.com");
+    assert!(error["message"].as_str().unwrap().contains("ursula_le_guin@gmail.com"));
+}
+
+#[tokio::test]
+async fn addresses_on_a_listed_disposable_domain_are_rejected() {
+    // Arrange
+    let file = std::env::temp_dir().join(format!("disposable-domains-{}.txt", Uuid::new_v4()));
+    std::fs::write(&file, "# Throwaway inboxes\nmailinator.com\n").unwrap();
+    let app = spawn_app_with(|c| c.deliverability.disposable_domains_file = Some(file.clone())).await;
+
+    // Act
+    let response = subscribe(&app, "ursula_le_guin@mailinator.com").await;
+
+    // Assert
+    let error = email_error(response).await;
+    assert_eq!(error["code"], "disposable_domain");
+    assert!(error.get("suggestion").is_none());
+    std::fs::remove_file(file).unwrap();
+}
+
+#[tokio::test]
+async fn role_addresses_are_rejected() {
+    // Arrange
+    let app = spawn_app_with(|c| c.deliverability.reject_role_addresses = true).await;
+
+    // Act
+    let response = subscribe(&app, "noreply@example.com").await;
+
+    // Assert
+    assert_eq!(email_error(response).await["code"], "role_address");
+}
+
+#[tokio::test]
+async fn addresses_on_a_domain_without_a_mail_server_are_rejected() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.deliverability.mail_server_check = MailServerCheckSettings::Stub {
+            domains_without_mail_server: vec!["no-mail.example.com".into()],
+        }
+    })
+    .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let rejected = subscribe(&app, "ursula_le_guin@no-mail.example.com").await;
+    let accepted = subscribe(&app, "ursula_le_guin@example.com").await;
+
+    // Assert
+    assert_eq!(email_error(rejected).await["code"], "no_mail_server");
+    assert_eq!(200, accepted.status().as_u16());
+}
@@ -1,22 +1,35 @@
+use argon2::password_hash::SaltString;
+use argon2::{Algorithm, Argon2, Params, PasswordHasher, Version};
 use once_cell::sync::Lazy;
 use sqlx::{Connection, Executor, PgConnection, PgPool};
 use uuid::Uuid;
-use wiremock::MockServer;
-use zero_to_production_rust_book::configuration::{get_configuration, DatabaseSettings, JaegerSettings};
-use zero_to_production_rust_book::startup::{get_connection_pool, Application};
-use zero_to_production_rust_book::telemetry::{get_subscriber, init_subscriber};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+use zero_to_production_rust_book::configuration::{get_configuration, DatabaseSettings, Settings, TraceExporterSettings};
+use zero_to_production_rust_book::shutdown::ShutdownHandle;
+use zero_to_production_rust_book::confirmation_email_worker::try_send_confirmation_email;
+use zero_to_production_rust_book::email_client::EmailClient;
+use zero_to_production_rust_book::issue_delivery_worker::{try_execute_task, ExecutionOutcome};
+use zero_to_production_rust_book::metrics::Metrics;
+use zero_to_production_rust_book::suppressions::EmailHasher;
+use zero_to_production_rust_book::tracking::LinkTracker;
+use zero_to_production_rust_book::startup::{get_connection_pool, run_migrations, Application};
+use zero_to_production_rust_book::telemetry::{construct_tracer_provider, get_subscriber, init_subscriber};
 
 static TRACING: Lazy<()> = Lazy::new(|| {
     let default_filter_layer = "info".to_string();
     let subscriber_name = "test".to_string();
 
-    let jaeger_settings = JaegerSettings{address: "0.0.0.0".to_string(), port: 4317};
+    // Spans are recorded, for their trace ids, but there is no collector to send them to.
+    let mut telemetry_settings = get_configuration().expect("Failed to get configuration").telemetry;
+    telemetry_settings.exporter = TraceExporterSettings::None;
+    let tracer_provider = construct_tracer_provider(&telemetry_settings).expect("Failed to build the tracer provider");
 
     if std::env::var("TEST_LOG").is_ok_and(|x| x.to_lowercase() == "true")  {
-        let subscriber = get_subscriber(subscriber_name, default_filter_layer, std::io::stdout, &jaeger_settings);
+        let subscriber = get_subscriber(subscriber_name, default_filter_layer, std::io::stdout, &tracer_provider);
         init_subscriber(subscriber);
     } else {
-        let subscriber = get_subscriber(subscriber_name, default_filter_layer, std::io::sink, &jaeger_settings);
+        let subscriber = get_subscriber(subscriber_name, default_filter_layer, std::io::sink, &tracer_provider);
         init_subscriber(subscriber);
     }
 });
@@ -26,9 +39,87 @@ pub struct TestApp {
     pub port: u16,
     pub db_pool: PgPool,
     pub email_server: MockServer,
+    pub test_user: TestUser,
+    pub email_client: EmailClient,
+    pub email_hasher: EmailHasher,
+    /// The base of the links in the emails the application sends.
+    pub base_url: String,
+    pub link_tracker: LinkTracker,
+    pub metrics: Metrics,
+    pub shutdown: ShutdownHandle,
+    pub application_task: tokio::task::JoinHandle<Result<(), anyhow::Error>>,
+    _database: TestDatabase,
+}
+
+/// Drops the database of a test when the test is over.
+///
+/// The actix workers outlive the runtime of the test that spawned them, and so do the connections
+/// of their pool: without this, idle connections pile up until Postgres refuses new ones.
+struct TestDatabase(DatabaseSettings);
+
+impl Drop for TestDatabase {
+    fn drop(&mut self) {
+        let config = self.0.clone();
+        // The runtime of the test may be shutting down: drop the database from a runtime of our own.
+        let _ = std::thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .expect("Failed to build a runtime to drop the test database.");
+            runtime.block_on(async {
+                if let Ok(mut connection) = PgConnection::connect_with(&config.without_db()).await {
+                    let statement = format!(r#"DROP DATABASE IF EXISTS "{}" WITH (FORCE);"#, config.database_name);
+                    let _ = connection.execute(statement.as_str()).await;
+                }
+            });
+        })
+        .join();
+    }
+}
+
+pub struct TestUser {
+    pub user_id: Uuid,
+    pub username: String,
+    pub password: String,
+}
+
+impl TestUser {
+    pub fn generate() -> Self {
+        Self {
+            user_id: Uuid::new_v4(),
+            username: Uuid::new_v4().to_string(),
+            password: Uuid::new_v4().to_string(),
+        }
+    }
+
+    async fn store(&self, pool: &PgPool) {
+        let salt = SaltString::generate(&mut argon2::password_hash::rand_core::OsRng);
+        let password_hash = Argon2::new(
+            Algorithm::Argon2id,
+            Version::V0x13,
+            Params::new(15000, 2, 1, None).unwrap(),
+        )
+        .hash_password(self.password.as_bytes(), &salt)
+        .unwrap()
+        .to_string();
+        sqlx::query!(
+            "INSERT INTO users (user_id, username, password_hash) VALUES ($1, $2, $3)",
+            self.user_id,
+            self.username,
+            password_hash,
+        )
+        .execute(pool)
+        .await
+        .expect("Failed to store test user.");
+    }
 }
 
 pub async fn spawn_app() -> TestApp {
+    spawn_app_with(|_| {}).await
+}
+
+/// Spawn the application, with `configure` tweaking its settings first.
+pub async fn spawn_app_with(configure: impl FnOnce(&mut Settings)) -> TestApp {
     dotenv::dotenv().ok();
     Lazy::force(&TRACING);
 
@@ -39,50 +130,105 @@ pub async fn spawn_app() -> TestApp {
         c.database.database_name = Uuid::new_v4().to_string();
         c.application.port = 0;
         c.email_client.base_url = email_server.uri();
+        configure(&mut c);
         c
     };
-    configure_database(&configuration.database).await;
+    if configuration.database.run_migrations_on_startup {
+        create_database(&configuration.database).await;
+    } else {
+        configure_database(&configuration.database).await;
+    }
 
     let application = Application::build(configuration.clone()).await.expect("Failed to build application");
     let port = application.port();
-    tokio::spawn(application.run_until_stopped());
+    let metrics = application.metrics();
+    let shutdown = application.shutdown_handle();
+    let application_task = tokio::spawn(application.run_until_stopped());
 
-    TestApp {
+    let test_app = TestApp {
         address: format!("http://0.0.0.0:{}", port),
         port,
         db_pool: get_connection_pool(&configuration.database),
         email_server,
-    }
+        test_user: TestUser::generate(),
+        email_client: configuration
+            .email_client
+            .client()
+            .with_suppression_list(get_connection_pool(&configuration.database), configuration.application.email_hasher()),
+        email_hasher: configuration.application.email_hasher(),
+        base_url: configuration.application.base_url.clone(),
+        link_tracker: configuration.application.link_tracker(),
+        metrics,
+        shutdown,
+        application_task,
+        _database: TestDatabase(configuration.database.clone()),
+    };
+    test_app.test_user.store(&test_app.db_pool).await;
+    test_app
 }
 
 
 pub async fn configure_database(config: &DatabaseSettings) -> PgPool {
-    // Create a database
-    let mut connection = PgConnection::connect_with(&config.without_db())
-        .await
-        .expect("Failed to connect to Postgres");
-
-    connection.execute(format!(r#"CREATE DATABASE "{}";"#, config.database_name).as_str())
-        .await
-        .expect("Failed to create database.");
+    create_database(config).await;
 
     // Migrate database
     let connection_pool = PgPool::connect_with(config.with_db())
         .await
         .expect("Failed to connect to Postgres.");
 
-    sqlx::migrate!("./migrations")
-        .run(&connection_pool)
+    run_migrations(&connection_pool)
         .await
         .expect("Failed to migrate the database");
 
     connection_pool
 }
 
+/// Create an empty database, leaving the migrations to the test.
+pub async fn create_database(config: &DatabaseSettings) {
+    let mut connection = PgConnection::connect_with(&config.without_db())
+        .await
+        .expect("Failed to connect to Postgres");
+
+    connection.execute(format!(r#"CREATE DATABASE "{}";"#, config.database_name).as_str())
+        .await
+        .expect("Failed to create database.");
+}
+
 impl TestApp {
+    pub async fn dispatch_all_pending_emails(&self) {
+        loop {
+            if let ExecutionOutcome::EmptyQueue = try_execute_task(&self.db_pool, &self.email_client, &self.link_tracker, &self.metrics)
+                .await
+                .unwrap()
+            {
+                break;
+            }
+        }
+    }
+
+    pub async fn dispatch_all_confirmation_emails(&self) {
+        loop {
+            if let ExecutionOutcome::EmptyQueue =
+                try_send_confirmation_email(&self.db_pool, &self.email_client, &self.base_url, &self.metrics)
+                    .await
+                    .unwrap()
+            {
+                break;
+            }
+        }
+    }
+
+    pub async fn get_ready(&self) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!("{}/ready", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
     pub async fn post_subscriptions(&self, body: String) -> reqwest::Response {
         reqwest::Client::new()
-            .post(&format!("{}/subscriptions", &self.address))
+            .post(format!("{}/subscriptions", &self.address))
             .header("Content-Type", "application/x-www-form-urlencoded")
             .body(body)
             .send()
@@ -90,6 +236,130 @@ impl TestApp {
             .expect("Failed to execute request.")
     }
 
+    pub async fn get_subscription_form(&self) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!("{}/subscriptions/form", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_subscriptions_json(&self, body: &serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/subscriptions", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_newsletter(&self, body: &serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/admin/newsletters", &self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn put_newsletter(&self, newsletter_issue_id: &str, body: &serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .put(format!("{}/admin/newsletters/{}", &self.address, newsletter_issue_id))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_admin(&self, path: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!("{}/admin{}", &self.address, path))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_privacy_request(&self, kind: &str, email: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/admin/privacy/{}", &self.address, kind))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(&serde_json::json!({"email": email}))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_admin(&self, path: &str, body: &serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/admin{}", &self.address, path))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn patch_subscriber(&self, subscriber_id: &str, body: &serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .patch(format!("{}/admin/subscribers/{}", &self.address, subscriber_id))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn delete_subscriber(&self, subscriber_id: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .delete(format!("{}/admin/subscribers/{}", &self.address, subscriber_id))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_subscribers_export(&self, query: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!("{}/admin/subscribers/export?{}", &self.address, query))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_subscribers_import(&self, query: &str, csv: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/admin/subscribers/import?{}", &self.address, query))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .header("Content-Type", "text/csv")
+            .body(csv.to_owned())
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_newsletter_preview(&self, newsletter_issue_id: &str, email: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/admin/newsletters/{}/preview", &self.address, newsletter_issue_id))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(&serde_json::json!({ "email": email }))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_publish_newsletter(&self, newsletter_issue_id: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/admin/newsletters/{}/publish", &self.address, newsletter_issue_id))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
     pub fn get_confirmation_links(&self, email_request: &wiremock::Request) -> ConfirmationLinks {
         let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
 
@@ -106,8 +376,8 @@ impl TestApp {
             confirmation_link.set_port(Some(self.port)).unwrap(); confirmation_link
 
         };
-        let html = get_link(&body["HtmlBody"].as_str().unwrap());
-        let plain_text = get_link(&body["TextBody"].as_str().unwrap());
+        let html = get_link(body["HtmlBody"].as_str().unwrap());
+        let plain_text = get_link(body["TextBody"].as_str().unwrap());
 
         ConfirmationLinks {
             html,
@@ -119,4 +389,75 @@ impl TestApp {
 pub struct ConfirmationLinks {
     pub html: reqwest::Url,
     pub plain_text: reqwest::Url,
-}
\ No newline at end of file
+}
+
+pub async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .named("Create unconfirmed subscriber")
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_subscriptions(body.into())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let email_request = &app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    app.get_confirmation_links(email_request)
+}
+
+pub async fn create_confirmed_subscriber(app: &TestApp) {
+    let confirmation_link = create_unconfirmed_subscriber(app).await;
+    reqwest::get(confirmation_link.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
+
+pub fn newsletter_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    })
+}
+
+/// Create a draft and return its id.
+pub async fn create_draft(app: &TestApp) -> String {
+    create_draft_with_body(app, &newsletter_body()).await
+}
+
+/// Create a draft with the given body and return its id.
+pub async fn create_draft_with_body(app: &TestApp, body: &serde_json::Value) -> String {
+    let response = app.post_newsletter(body).await;
+    assert_eq!(response.status().as_u16(), 201);
+    let body: serde_json::Value = response.json().await.unwrap();
+    body["newsletter_issue_id"].as_str().unwrap().to_owned()
+}
+
+/// Send a preview of the latest revision, so that the draft can be published.
+pub async fn preview_draft(app: &TestApp, newsletter_issue_id: &str) {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    let response = app
+        .post_newsletter_preview(newsletter_issue_id, "admin@example.com")
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+}
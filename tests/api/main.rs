@@ -1,4 +1,22 @@
 mod helpers;
+mod archive;
+mod bot_protection;
+mod cli;
+mod deliverability;
 mod health_check;
+mod metrics;
+mod migrations;
+mod newsletters;
+mod privacy;
+mod rate_limit;
+mod ready;
+mod request_id;
+mod shutdown;
+mod subscribers;
+mod subscribers_export;
+mod subscribers_import;
 mod subscriptions;
-mod subscriptions_confirm;
\ No newline at end of file
+mod subscriptions_confirm;
+mod suppressions;
+mod trace_context;
+mod tracking;
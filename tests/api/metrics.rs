@@ -0,0 +1,79 @@
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use crate::helpers::spawn_app;
+
+async fn get_metrics(address: &str) -> String {
+    let response = reqwest::get(format!("{}/metrics", address)).await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    response.text().await.unwrap()
+}
+
+#[tokio::test]
+async fn subscriptions_and_confirmation_emails_are_counted() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    reqwest::get(confirmation_links.html).await.unwrap();
+
+    let metrics = get_metrics(&app.address).await;
+    assert!(metrics.contains("newsletter_subscriptions_created_total 1\n"));
+    assert!(metrics.contains("newsletter_subscriptions_confirmed_total 1\n"));
+    assert!(metrics.contains("newsletter_emails_sent_total{email_kind=\"confirmation\"} 1\n"));
+}
+
+#[tokio::test]
+async fn failed_emails_are_counted() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    let metrics = get_metrics(&app.address).await;
+    assert!(metrics.contains("newsletter_emails_failed_total{email_kind=\"confirmation\"} 1\n"));
+}
+
+#[tokio::test]
+async fn requests_are_measured_per_route() {
+    let app = spawn_app().await;
+
+    reqwest::get(format!("{}/health_check", app.address)).await.unwrap();
+    reqwest::get(format!("{}/archive/{}", app.address, uuid::Uuid::new_v4()))
+        .await
+        .unwrap();
+
+    let metrics = get_metrics(&app.address).await;
+    let series: Vec<&str> = metrics
+        .lines()
+        .filter(|l| l.starts_with("http_server_request_duration_seconds_count"))
+        .collect();
+    assert!(series.iter().any(|l| l.contains("http_route=\"/health_check\"")
+        && l.contains("http_response_status_code=\"200\"")
+        && l.ends_with(" 1")));
+    // Series are labelled with the route pattern, not the path.
+    assert!(series.iter().any(|l| l.contains("http_route=\"/archive/{slug}\"")
+        && l.contains("http_response_status_code=\"404\"")));
+}
+
+#[tokio::test]
+async fn connection_pool_utilization_is_reported() {
+    let app = spawn_app().await;
+
+    let metrics = get_metrics(&app.address).await;
+
+    assert!(metrics.contains("# TYPE db_client_connection_count gauge\n"));
+    assert!(metrics.contains("db_client_connection_count{db_client_connection_state=\"idle\"}"));
+    assert!(metrics.contains("db_client_connection_max 10\n"));
+}
@@ -0,0 +1,81 @@
+use sqlx::migrate::Migrate;
+use sqlx::{Connection, PgConnection, PgPool};
+use uuid::Uuid;
+use zero_to_production_rust_book::configuration::get_configuration;
+use zero_to_production_rust_book::startup::{run_migrations, MIGRATOR};
+use crate::helpers::{create_database, spawn_app_with};
+
+#[tokio::test]
+async fn migrations_are_applied_on_startup_when_enabled() {
+    // Arrange
+    let app = spawn_app_with(|c| c.database.run_migrations_on_startup = true).await;
+
+    // Act
+    let response = app.get_ready().await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["components"]["migrations"]["status"], "up");
+}
+
+#[tokio::test]
+async fn concurrent_migrations_do_not_race() {
+    // Arrange
+    let mut configuration = get_configuration().unwrap();
+    configuration.database.database_name = Uuid::new_v4().to_string();
+    create_database(&configuration.database).await;
+    let db_pool = PgPool::connect_with(configuration.database.with_db()).await.unwrap();
+
+    // Act
+    let (first, second) = tokio::join!(run_migrations(&db_pool), run_migrations(&db_pool));
+
+    // Assert
+    first.expect("The first replica failed to migrate the database.");
+    second.expect("The second replica failed to migrate the database.");
+    let pending: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM _sqlx_migrations WHERE NOT success")
+        .fetch_one(&db_pool)
+        .await
+        .unwrap();
+    assert_eq!(pending, 0);
+}
+
+#[tokio::test]
+async fn existing_subscribers_are_normalized_like_new_ones() {
+    // Arrange: a database migrated up to the addition of `email_normalized`, with an IDN subscriber.
+    let mut configuration = get_configuration().unwrap();
+    configuration.database.database_name = Uuid::new_v4().to_string();
+    create_database(&configuration.database).await;
+    let mut connection = PgConnection::connect_with(&configuration.database.with_db()).await.unwrap();
+    connection.ensure_migrations_table().await.unwrap();
+    for migration in MIGRATOR.iter().filter(|m| m.version <= 20250630090000) {
+        connection.apply(migration).await.unwrap();
+    }
+    sqlx::query(
+        "INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+        VALUES ($1, 'Ursula@Bücher.de', 'le guin', now(), 'confirmed')",
+    )
+    .bind(Uuid::new_v4())
+    .execute(&mut connection)
+    .await
+    .unwrap();
+    let db_pool = PgPool::connect_with(configuration.database.with_db()).await.unwrap();
+
+    // Act
+    run_migrations(&db_pool).await.expect("Failed to migrate the database.");
+
+    // Assert
+    let normalized: String = sqlx::query_scalar("SELECT email_normalized FROM subscriptions")
+        .fetch_one(&db_pool)
+        .await
+        .unwrap();
+    assert_eq!(normalized, "ursula@xn--bcher-kva.de");
+    let conflict = sqlx::query(
+        "INSERT INTO subscriptions (id, email, email_normalized, name, subscribed_at, status)
+        VALUES ($1, 'ursula@xn--bcher-kva.de', 'ursula@xn--bcher-kva.de', 'le guin', now(), 'pending_confirmation')",
+    )
+    .bind(Uuid::new_v4())
+    .execute(&db_pool)
+    .await;
+    assert!(conflict.is_err());
+}
@@ -0,0 +1,429 @@
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+use crate::helpers::{
+    create_confirmed_subscriber, create_draft, create_unconfirmed_subscriber, newsletter_body,
+    preview_draft, spawn_app,
+};
+
+#[tokio::test]
+async fn requests_missing_authorization_are_rejected() {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/admin/newsletters", &app.address))
+        .json(&newsletter_body())
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(401, response.status().as_u16());
+    assert_eq!(r#"Basic realm="admin""#, response.headers()["WWW-Authenticate"]);
+    assert_eq!("application/problem+json", response.headers()["Content-Type"]);
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["status"], 401);
+    assert_eq!(problem["detail"], "Invalid credentials.");
+}
+
+#[tokio::test]
+async fn invalid_password_is_rejected() {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/admin/newsletters", &app.address))
+        .basic_auth(&app.test_user.username, Some(uuid::Uuid::new_v4().to_string()))
+        .json(&newsletter_body())
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(401, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn creating_a_draft_stores_the_first_revision() {
+    let app = spawn_app().await;
+
+    let newsletter_issue_id = create_draft(&app).await;
+
+    let response = app.get_admin(&format!("/newsletters/{}", newsletter_issue_id)).await;
+    let issue: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(issue["status"], "draft");
+    assert_eq!(issue["revision"], 1);
+    assert_eq!(issue["content"]["html"], "<p>Newsletter body as HTML</p>");
+    assert_eq!(issue["content"]["text"], "Newsletter body as plain text");
+}
+
+#[tokio::test]
+async fn editing_a_draft_records_a_new_revision() {
+    let app = spawn_app().await;
+    let newsletter_issue_id = create_draft(&app).await;
+
+    let mut body = newsletter_body();
+    body["title"] = "Updated title".into();
+    let response = app.put_newsletter(&newsletter_issue_id, &body).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response = app
+        .get_admin(&format!("/newsletters/{}/revisions", newsletter_issue_id))
+        .await;
+    let revisions: Vec<serde_json::Value> = response.json().await.unwrap();
+    assert_eq!(revisions.len(), 2);
+    assert_eq!(revisions[0]["title"], "Newsletter title");
+    assert_eq!(revisions[1]["title"], "Updated title");
+    assert_eq!(revisions[1]["revision"], 2);
+    assert_eq!(revisions[1]["created_by"], app.test_user.user_id.to_string());
+}
+
+#[tokio::test]
+async fn drafts_return_400_for_invalid_data() {
+    let app = spawn_app().await;
+    let test_cases = vec![
+        (
+            serde_json::json!({"content": {"text": "Newsletter body", "html": "<p>Newsletter body</p>"}}),
+            "missing title",
+        ),
+        (serde_json::json!({"title": "Newsletter!"}), "missing content"),
+        (
+            serde_json::json!({"title": " ", "content": {"text": "Newsletter body", "html": "<p>Newsletter body</p>"}}),
+            "empty title",
+        ),
+        (
+            serde_json::json!({"title": "Newsletter!", "content": {"text": "Newsletter body", "html": ""}}),
+            "empty html body",
+        ),
+    ];
+
+    for (invalid_body, error_message) in test_cases {
+        let response = app.post_newsletter(&invalid_body).await;
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not fail with 400 Bad Request when the payload was {}.",
+            error_message
+        );
+    }
+}
+
+#[tokio::test]
+async fn preview_is_sent_to_the_requested_address_only() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let newsletter_issue_id = create_draft(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_newsletter_preview(&newsletter_issue_id, "admin@example.com")
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let email_request = app.email_server.received_requests().await.unwrap().pop().unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["To"], "admin@example.com");
+    assert_eq!(body["Subject"], "[Preview] Newsletter title");
+}
+
+#[tokio::test]
+async fn publishing_without_a_preview_is_rejected() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let newsletter_issue_id = create_draft(&app).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_publish_newsletter(&newsletter_issue_id).await;
+    assert_eq!(response.status().as_u16(), 409);
+}
+
+#[tokio::test]
+async fn editing_a_draft_after_the_preview_requires_a_new_preview() {
+    let app = spawn_app().await;
+    let newsletter_issue_id = create_draft(&app).await;
+    preview_draft(&app, &newsletter_issue_id).await;
+
+    app.put_newsletter(&newsletter_issue_id, &newsletter_body())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let response = app.post_publish_newsletter(&newsletter_issue_id).await;
+    assert_eq!(response.status().as_u16(), 409);
+}
+
+#[tokio::test]
+async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
+    let app = spawn_app().await;
+    create_unconfirmed_subscriber(&app).await;
+    let newsletter_issue_id = create_draft(&app).await;
+    preview_draft(&app, &newsletter_issue_id).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_publish_newsletter(&newsletter_issue_id).await;
+    assert_eq!(response.status().as_u16(), 200);
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn newsletters_are_delivered_to_confirmed_subscribers() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let newsletter_issue_id = create_draft(&app).await;
+    preview_draft(&app, &newsletter_issue_id).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_publish_newsletter(&newsletter_issue_id).await;
+    assert_eq!(response.status().as_u16(), 200);
+    app.dispatch_all_pending_emails().await;
+
+    let response = app.get_admin(&format!("/newsletters/{}", newsletter_issue_id)).await;
+    let issue: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(issue["status"], "published");
+}
+
+#[tokio::test]
+async fn published_issues_cannot_be_edited() {
+    let app = spawn_app().await;
+    let newsletter_issue_id = create_draft(&app).await;
+    preview_draft(&app, &newsletter_issue_id).await;
+    app.post_publish_newsletter(&newsletter_issue_id)
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let response = app.put_newsletter(&newsletter_issue_id, &newsletter_body()).await;
+    assert_eq!(response.status().as_u16(), 409);
+}
+
+#[tokio::test]
+async fn the_report_records_the_message_id_of_delivered_issues() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let newsletter_issue_id = create_draft(&app).await;
+    preview_draft(&app, &newsletter_issue_id).await;
+    app.post_publish_newsletter(&newsletter_issue_id)
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let response = app.get_admin(&format!("/newsletters/{}/report", newsletter_issue_id)).await;
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["counts"]["queued"], 1);
+    assert_eq!(report["recipients"][0]["state"], "queued");
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "MessageID": "b7bc2f4a-e38e-4336-af7d-e6c392c2f817",
+        })))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    let response = app.get_admin(&format!("/newsletters/{}/report", newsletter_issue_id)).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["counts"]["sent"], 1);
+    assert_eq!(report["counts"]["total"], 1);
+    let recipient = &report["recipients"][0];
+    assert_eq!(recipient["subscriber_email"], "ursula_le_guin@gmail.com");
+    assert_eq!(recipient["state"], "sent");
+    assert_eq!(recipient["attempts"], 1);
+    assert_eq!(recipient["message_id"], "b7bc2f4a-e38e-4336-af7d-e6c392c2f817");
+}
+
+#[tokio::test]
+async fn a_reported_bounce_marks_the_delivery_as_bounced() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let newsletter_issue_id = create_draft(&app).await;
+    preview_draft(&app, &newsletter_issue_id).await;
+    app.post_publish_newsletter(&newsletter_issue_id)
+        .await
+        .error_for_status()
+        .unwrap();
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "MessageID": "b7bc2f4a-e38e-4336-af7d-e6c392c2f817",
+        })))
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    // Act
+    let response = app
+        .post_admin(
+            "/suppressions",
+            &serde_json::json!({
+                "email": "Ursula_Le_Guin@gmail.com",
+                "reason": "bounced",
+                "message_id": "b7bc2f4a-e38e-4336-af7d-e6c392c2f817",
+            }),
+        )
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 201);
+    let response = app.get_admin(&format!("/newsletters/{}/report", newsletter_issue_id)).await;
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["counts"]["sent"], 0);
+    assert_eq!(report["counts"]["bounced"], 1);
+    assert_eq!(report["recipients"][0]["state"], "bounced");
+}
+
+#[tokio::test]
+async fn the_report_records_failed_delivery_attempts() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let newsletter_issue_id = create_draft(&app).await;
+    preview_draft(&app, &newsletter_issue_id).await;
+    app.post_publish_newsletter(&newsletter_issue_id)
+        .await
+        .error_for_status()
+        .unwrap();
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    let response = app.get_admin(&format!("/newsletters/{}/report", newsletter_issue_id)).await;
+    let report: serde_json::Value = response.json().await.unwrap();
+    let recipient = &report["recipients"][0];
+    // The delivery is retried later on.
+    assert_eq!(recipient["state"], "queued");
+    assert_eq!(recipient["attempts"], 1);
+    assert!(recipient["last_error"].as_str().unwrap().contains("500"));
+}
+
+#[tokio::test]
+async fn a_delivery_to_an_invalid_stored_address_fails_without_retries() {
+    let app = spawn_app().await;
+    sqlx::query!(
+        "INSERT INTO subscriptions (id, email, email_normalized, name, subscribed_at, status)
+        VALUES ($1, 'not-an-email', 'not-an-email', 'le guin', now(), 'confirmed')",
+        uuid::Uuid::new_v4(),
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    let newsletter_issue_id = create_draft(&app).await;
+    preview_draft(&app, &newsletter_issue_id).await;
+    app.post_publish_newsletter(&newsletter_issue_id)
+        .await
+        .error_for_status()
+        .unwrap();
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    let response = app.get_admin(&format!("/newsletters/{}/report", newsletter_issue_id)).await;
+    let report: serde_json::Value = response.json().await.unwrap();
+    let recipient = &report["recipients"][0];
+    assert_eq!(recipient["state"], "failed");
+    assert_eq!(recipient["attempts"], 1);
+}
+
+#[tokio::test]
+async fn the_report_is_paginated() {
+    let app = spawn_app().await;
+    for i in 0..3 {
+        sqlx::query!(
+            "INSERT INTO subscriptions (id, email, email_normalized, name, subscribed_at, status)
+            VALUES ($1, $2, $2, 'le guin', now(), 'confirmed')",
+            uuid::Uuid::new_v4(),
+            format!("subscriber-{}@example.com", i),
+        )
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    }
+    let newsletter_issue_id = create_draft(&app).await;
+    preview_draft(&app, &newsletter_issue_id).await;
+    app.post_publish_newsletter(&newsletter_issue_id)
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let response = app
+        .get_admin(&format!("/newsletters/{}/report?limit=2", newsletter_issue_id))
+        .await;
+    let first_page: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(first_page["counts"]["total"], 3);
+    assert_eq!(first_page["recipients"].as_array().unwrap().len(), 2);
+    let cursor = first_page["next_cursor"].as_str().unwrap();
+
+    let response = app
+        .get_admin(&format!("/newsletters/{}/report?limit=2&after={}", newsletter_issue_id, cursor))
+        .await;
+    let second_page: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(second_page["recipients"].as_array().unwrap().len(), 1);
+    assert!(second_page["next_cursor"].is_null());
+}
+
+#[tokio::test]
+async fn markdown_drafts_are_rendered_to_html_and_text() {
+    let app = spawn_app().await;
+    let body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "markdown": "# Hello\n\nRead the [book](https://doc.rust-lang.org/book/).\n\n<script>alert(1)</script>",
+        }
+    });
+    let response = app.post_newsletter(&body).await;
+    assert_eq!(response.status().as_u16(), 201);
+
+    let issue: serde_json::Value = response.json().await.unwrap();
+    let html = issue["content"]["html"].as_str().unwrap();
+    assert!(html.contains("<h1 style="));
+    assert!(html.contains(r#"href="https://doc.rust-lang.org/book/""#));
+    assert!(!html.contains("<script"));
+    assert_eq!(
+        issue["content"]["text"],
+        "# Hello\n\nRead the book (https://doc.rust-lang.org/book/).\n"
+    );
+    assert_eq!(issue["content"]["markdown"], body["content"]["markdown"]);
+}
+
+#[tokio::test]
+async fn empty_markdown_drafts_are_rejected() {
+    let app = spawn_app().await;
+    let body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": { "markdown": "  " }
+    });
+
+    let response = app.post_newsletter(&body).await;
+
+    assert_eq!(response.status().as_u16(), 400);
+}
@@ -0,0 +1,179 @@
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use crate::helpers::{create_confirmed_subscriber, create_draft, preview_draft, spawn_app, TestApp};
+
+const EMAIL: &str = "ursula_le_guin@gmail.com";
+
+/// Deliver an issue to the subscribers and record that it was opened, returning the issue id.
+async fn deliver_an_opened_issue(app: &TestApp) -> Uuid {
+    let newsletter_issue_id = create_draft(app).await;
+    preview_draft(app, &newsletter_issue_id).await;
+    app.post_publish_newsletter(&newsletter_issue_id)
+        .await
+        .error_for_status()
+        .unwrap();
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    let newsletter_issue_id = Uuid::parse_str(&newsletter_issue_id).unwrap();
+    sqlx::query!(
+        r#"INSERT INTO newsletter_events (event_id, newsletter_issue_id, subscriber_id, kind, occurred_at)
+        SELECT $1, $2, id, 'open', now() FROM subscriptions"#,
+        Uuid::new_v4(),
+        newsletter_issue_id,
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    newsletter_issue_id
+}
+
+async fn count(app: &TestApp, table: &str) -> i64 {
+    sqlx::query_scalar(&format!("SELECT COUNT(*) FROM {}", table))
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn privacy_requests_require_authentication() {
+    // Arrange
+    let app = spawn_app().await;
+
+    for kind in ["access", "erasure"] {
+        // Act
+        let response = reqwest::Client::new()
+            .post(format!("{}/admin/privacy/{}", &app.address, kind))
+            .json(&serde_json::json!({"email": EMAIL}))
+            .send()
+            .await
+            .expect("Failed to execute request.");
+
+        // Assert
+        assert_eq!(401, response.status().as_u16());
+    }
+}
+
+#[tokio::test]
+async fn an_access_request_returns_everything_stored_about_the_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let newsletter_issue_id = deliver_an_opened_issue(&app).await;
+
+    // Act
+    let response = app.post_privacy_request("access", EMAIL).await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let data: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(data["email"], EMAIL);
+    assert_eq!(data["subscriber"]["name"], "le guin");
+    assert_eq!(data["subscriber"]["status"], "confirmed");
+    assert_eq!(data["confirmation_tokens"].as_array().unwrap().len(), 1);
+    assert!(data["confirmation_tokens"][0].get("subscription_token").is_none());
+    assert_eq!(data["deliveries"][0]["newsletter_issue_id"], newsletter_issue_id.to_string());
+    assert_eq!(data["deliveries"][0]["state"], "sent");
+    assert_eq!(data["events"][0]["kind"], "open");
+    assert_eq!(data["suppressed"], false);
+}
+
+#[tokio::test]
+async fn an_access_request_for_an_unknown_email_finds_nothing() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app.post_privacy_request("access", "nobody@example.com").await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let data: serde_json::Value = response.json().await.unwrap();
+    assert!(data["subscriber"].is_null());
+    assert_eq!(data["deliveries"], serde_json::json!([]));
+    assert_eq!(data["events"], serde_json::json!([]));
+}
+
+#[tokio::test]
+async fn an_erasure_request_removes_the_subscriber_and_everything_linked_to_them() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    deliver_an_opened_issue(&app).await;
+
+    // Act
+    let response = app.post_privacy_request("erasure", EMAIL).await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["subscriber_erased"], true);
+    for table in ["subscriptions", "subscription_tokens", "newsletter_deliveries", "newsletter_events"] {
+        assert_eq!(count(&app, table).await, 0, "{} still holds rows", table);
+    }
+    let suppression = sqlx::query!("SELECT email_hash, reason FROM suppressions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(suppression.reason, "erased");
+    assert!(!suppression.email_hash.contains("ursula"));
+    let data: serde_json::Value = app.post_privacy_request("access", EMAIL).await.json().await.unwrap();
+    assert!(data["subscriber"].is_null());
+    assert_eq!(data["suppressed"], true);
+}
+
+#[tokio::test]
+async fn an_erasure_request_matches_the_address_in_any_case() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    // Act
+    let response = app.post_privacy_request("erasure", " Ursula_Le_Guin@GMAIL.com").await;
+
+    // Assert
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["subscriber_erased"], true);
+    assert_eq!(count(&app, "subscriptions").await, 0);
+}
+
+#[tokio::test]
+async fn an_erased_email_is_never_imported_again() {
+    // Arrange
+    let app = spawn_app().await;
+    app.post_privacy_request("erasure", EMAIL).await;
+    let csv = "email,name\nURSULA_LE_GUIN@gmail.com,Ursula\nada@example.com,Ada\n";
+
+    // Act
+    let response = app
+        .post_subscribers_import("status=confirmed&consent_source=Previous%20provider", csv)
+        .await;
+
+    // Assert
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["imported"], 1);
+    assert_eq!(report["suppressed"], 1);
+    let emails: Vec<String> = sqlx::query_scalar!("SELECT email FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(emails, ["ada@example.com"]);
+}
+
+#[tokio::test]
+async fn an_erasure_request_without_an_email_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app.post_privacy_request("erasure", "  ").await;
+
+    // Assert
+    assert_eq!(400, response.status().as_u16());
+    assert_eq!(count(&app, "suppressions").await, 0);
+}
@@ -0,0 +1,167 @@
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero_to_production_rust_book::configuration::{QuotaSettings, RateLimitStoreSettings};
+use crate::helpers::{spawn_app_with, TestApp};
+
+fn quota(capacity: u32) -> Option<QuotaSettings> {
+    Some(QuotaSettings { capacity, period_seconds: 3600 })
+}
+
+async fn get_form(app: &TestApp, forwarded_for: Option<&str>) -> reqwest::Response {
+    let mut request = reqwest::Client::new().get(format!("{}/subscriptions/form", &app.address));
+    if let Some(forwarded_for) = forwarded_for {
+        request = request.header("X-Forwarded-For", forwarded_for);
+    }
+    request.send().await.expect("Failed to execute request.")
+}
+
+async fn assert_too_many_requests(response: reqwest::Response) {
+    assert_eq!(429, response.status().as_u16());
+    let retry_after: u64 = response.headers()["Retry-After"].to_str().unwrap().parse().unwrap();
+    assert!(retry_after > 0);
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["type"], "/problems/too-many-requests");
+}
+
+#[tokio::test]
+async fn clients_over_their_limit_are_told_when_to_retry() {
+    // Arrange
+    let app = spawn_app_with(|c| c.rate_limit.per_ip = quota(2)).await;
+
+    // Act
+    let responses = [get_form(&app, None).await, get_form(&app, None).await];
+    let limited = get_form(&app, None).await;
+
+    // Assert
+    assert!(responses.iter().all(|r| r.status().is_success()));
+    assert_too_many_requests(limited).await;
+}
+
+#[tokio::test]
+async fn the_limit_only_applies_to_the_subscription_endpoints() {
+    // Arrange
+    let app = spawn_app_with(|c| c.rate_limit.per_ip = quota(1)).await;
+    get_form(&app, None).await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .get(format!("{}/health_check", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert!(response.status().is_success());
+}
+
+#[tokio::test]
+async fn the_forwarded_address_of_an_untrusted_peer_is_ignored() {
+    // Arrange
+    let app = spawn_app_with(|c| c.rate_limit.per_ip = quota(1)).await;
+    get_form(&app, Some("203.0.113.1")).await;
+
+    // Act
+    let response = get_form(&app, Some("203.0.113.2")).await;
+
+    // Assert
+    assert_too_many_requests(response).await;
+}
+
+#[tokio::test]
+async fn clients_behind_a_trusted_proxy_have_their_own_limit() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.rate_limit.per_ip = quota(1);
+        c.rate_limit.trusted_proxies = vec!["127.0.0.1".into()];
+    })
+    .await;
+    get_form(&app, Some("203.0.113.1")).await;
+
+    // Act
+    let other_client = get_form(&app, Some("203.0.113.2")).await;
+    let same_client = get_form(&app, Some("203.0.113.1")).await;
+
+    // Assert
+    assert!(other_client.status().is_success());
+    assert_too_many_requests(same_client).await;
+}
+
+#[tokio::test]
+async fn the_limit_can_be_shared_through_the_database() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.rate_limit.per_ip = quota(1);
+        c.rate_limit.store = RateLimitStoreSettings::Postgres;
+    })
+    .await;
+    get_form(&app, None).await;
+
+    // Act
+    let response = get_form(&app, None).await;
+
+    // Assert
+    assert_too_many_requests(response).await;
+    let keys = sqlx::query_scalar!("SELECT key FROM rate_limit_buckets")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(keys, ["ip:127.0.0.1"]);
+}
+
+#[tokio::test]
+async fn confirmation_emails_to_an_address_are_capped() {
+    // Arrange
+    let app = spawn_app_with(|c| c.rate_limit.confirmation_emails_per_address = quota(1)).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    // Act
+    let same_address = app
+        .post_subscriptions("name=le%20guin&email=Ursula_Le_Guin%40gmail.com".into())
+        .await;
+    let other_address = app
+        .post_subscriptions("name=le%20guin&email=le_guin%40gmail.com".into())
+        .await;
+
+    // Assert
+    assert_too_many_requests(same_address).await;
+    assert_eq!(200, other_address.status().as_u16());
+}
+
+#[tokio::test]
+async fn a_signup_that_sent_no_email_does_not_use_up_the_limit() {
+    // Arrange
+    let app = spawn_app_with(|c| c.rate_limit.confirmation_emails_per_address = quota(1)).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    sqlx::query!("ALTER TABLE subscription_tokens RENAME TO broken_subscription_tokens")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let failed = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    assert_eq!(500, failed.status().as_u16());
+    sqlx::query!("ALTER TABLE broken_subscription_tokens RENAME TO subscription_tokens")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let retry = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(200, retry.status().as_u16());
+}
@@ -0,0 +1,106 @@
+use sqlx::{Connection, Executor, PgConnection};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero_to_production_rust_book::configuration::get_configuration;
+use crate::helpers::{spawn_app, spawn_app_with};
+
+#[tokio::test]
+async fn ready_returns_200_when_the_dependencies_are_up() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app.get_ready().await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "ready");
+    assert_eq!(body["components"]["database"]["status"], "up");
+    assert!(body["components"]["database"]["latency_ms"].is_u64());
+    assert_eq!(body["components"]["migrations"]["status"], "up");
+    assert!(body["components"].get("email_api").is_none());
+}
+
+#[tokio::test]
+async fn ready_returns_503_when_the_database_is_unreachable() {
+    // Arrange
+    let app = spawn_app().await;
+    let configuration = get_configuration().unwrap();
+    let mut connection = PgConnection::connect_with(&configuration.database.without_db())
+        .await
+        .unwrap();
+    app.db_pool.close().await;
+    let database_name = app.db_pool.connect_options().get_database().unwrap().to_owned();
+    connection
+        .execute(format!(r#"DROP DATABASE "{}" WITH (FORCE);"#, database_name).as_str())
+        .await
+        .unwrap();
+
+    // Act
+    let response = app.get_ready().await;
+
+    // Assert
+    assert_eq!(503, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "unavailable");
+    assert_eq!(body["components"]["database"]["status"], "down");
+    assert_eq!(body["components"]["database"]["critical"], true);
+    assert_eq!(body["components"]["database"]["error"], "unreachable");
+    assert!(!body.to_string().contains(&database_name));
+}
+
+#[tokio::test]
+async fn ready_reports_pending_migrations() {
+    // Arrange
+    let app = spawn_app().await;
+    let version: i64 = sqlx::query_scalar(
+        "DELETE FROM _sqlx_migrations WHERE version = (SELECT MAX(version) FROM _sqlx_migrations) RETURNING version",
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    let response = app.get_ready().await;
+
+    // Assert
+    assert_eq!(503, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["components"]["migrations"]["status"], "down");
+    assert_eq!(body["components"]["migrations"]["pending"], serde_json::json!([version]));
+}
+
+#[tokio::test]
+async fn an_unreachable_email_api_does_not_make_the_application_unavailable() {
+    // Arrange
+    let app = spawn_app_with(|c| c.readiness.probe_email_api = true).await;
+    Mock::given(path("/"))
+        .and(method("GET"))
+        .respond_with(ResponseTemplate::new(503))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.get_ready().await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["components"]["email_api"]["status"], "down");
+    assert_eq!(body["components"]["email_api"]["critical"], false);
+}
+
+#[tokio::test]
+async fn a_reachable_email_api_is_reported_as_up() {
+    // Arrange
+    let app = spawn_app_with(|c| c.readiness.probe_email_api = true).await;
+
+    // Act
+    let response = app.get_ready().await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["components"]["email_api"]["status"], "up");
+}
@@ -0,0 +1,84 @@
+use wiremock::matchers::{header, method, path};
+use wiremock::{Mock, ResponseTemplate};
+use crate::helpers::spawn_app;
+
+#[tokio::test]
+async fn every_response_carries_a_request_id() {
+    let app = spawn_app().await;
+
+    let response = reqwest::get(format!("{}/health_check", app.address))
+        .await
+        .unwrap();
+
+    let request_id = response.headers()["X-Request-Id"].to_str().unwrap();
+    assert!(uuid::Uuid::parse_str(request_id).is_ok());
+}
+
+#[tokio::test]
+async fn the_request_id_of_the_client_is_echoed_back() {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/subscriptions/confirm?token=unknown", app.address))
+        .header("X-Request-Id", "support-ticket-42")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.headers()["X-Request-Id"], "support-ticket-42");
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["request_id"], "support-ticket-42");
+}
+
+#[tokio::test]
+async fn the_trace_id_is_used_as_request_id_when_there_is_none() {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/health_check", app.address))
+        .header("traceparent", "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.headers()["X-Request-Id"], "4bf92f3577b34da6a3ce929d0e0e4736");
+}
+
+#[tokio::test]
+async fn rejected_credentials_carry_the_request_id() {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/admin/newsletters/{}", app.address, uuid::Uuid::new_v4()))
+        .header("X-Request-Id", "support-ticket-42")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 401);
+    assert_eq!(response.headers()["X-Request-Id"], "support-ticket-42");
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["request_id"], "support-ticket-42");
+}
+
+#[tokio::test]
+async fn the_request_id_is_forwarded_to_the_email_api() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .and(header("X-Request-Id", "support-ticket-42"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/subscriptions", app.address))
+        .header("X-Request-Id", "support-ticket-42")
+        .form(&[("name", "le guin"), ("email", "ursula_le_guin@gmail.com")])
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 200);
+}
@@ -0,0 +1,95 @@
+use std::time::Duration;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use crate::helpers::spawn_app;
+
+#[tokio::test]
+async fn in_flight_requests_complete_when_the_application_shuts_down() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_millis(500)))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com".to_string();
+    let request = tokio::spawn({
+        let address = app.address.clone();
+        async move {
+            reqwest::Client::new()
+                .post(format!("{}/subscriptions", address))
+                .header("Content-Type", "application/x-www-form-urlencoded")
+                .body(body)
+                .send()
+                .await
+        }
+    });
+    // Wait for the request to reach the email API before asking to stop.
+    while app.email_server.received_requests().await.unwrap().is_empty() {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    // Act
+    app.shutdown.trigger();
+
+    // Assert
+    let response = request.await.unwrap().expect("The in-flight request was dropped.");
+    assert_eq!(200, response.status().as_u16());
+    let outcome = tokio::time::timeout(Duration::from_secs(5), app.application_task)
+        .await
+        .expect("The application did not stop in time.")
+        .unwrap();
+    assert!(outcome.is_ok());
+}
+
+#[tokio::test]
+async fn streamed_responses_are_sent_in_full_when_the_application_shuts_down() {
+    // Arrange
+    let app = spawn_app().await;
+    // Enough rows for the export to outgrow the socket buffers, so that it is still being sent below.
+    sqlx::query!(
+        "INSERT INTO subscriptions (id, email, email_normalized, name, subscribed_at, status)
+        SELECT gen_random_uuid(), 'reader' || n || '@example.com', 'reader' || n || '@example.com', 'reader', now(), 'confirmed'
+        FROM generate_series(1, 100000) AS n"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    let response = app.get_admin("/subscribers/export?format=csv").await;
+    assert_eq!(200, response.status().as_u16());
+
+    // Act
+    app.shutdown.trigger();
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    // Assert
+    let export = response.text().await.expect("The export was cut short.");
+    assert_eq!(export.lines().count(), 100_001);
+    let outcome = tokio::time::timeout(Duration::from_secs(5), app.application_task)
+        .await
+        .expect("The application did not stop in time.")
+        .unwrap();
+    assert!(outcome.is_ok());
+}
+
+#[tokio::test]
+async fn new_connections_are_refused_after_shutdown() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    app.shutdown.trigger();
+    tokio::time::timeout(Duration::from_secs(5), app.application_task)
+        .await
+        .expect("The application did not stop in time.")
+        .unwrap()
+        .unwrap();
+
+    // Assert
+    let outcome = reqwest::Client::new()
+        .get(format!("{}/health_check", app.address))
+        .send()
+        .await;
+    assert!(outcome.is_err());
+}
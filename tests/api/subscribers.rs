@@ -0,0 +1,294 @@
+use chrono::{DateTime, Duration, Utc};
+use uuid::Uuid;
+use crate::helpers::{create_confirmed_subscriber, spawn_app, TestApp};
+
+async fn insert_subscriber(app: &TestApp, email: &str, name: &str, status: &str, subscribed_at: DateTime<Utc>) -> Uuid {
+    let id = Uuid::new_v4();
+    sqlx::query!(
+        "INSERT INTO subscriptions (id, email, email_normalized, name, status, subscribed_at)
+        VALUES ($1, $2, lower($2), $3, $4, $5)",
+        id,
+        email,
+        name,
+        status,
+        subscribed_at,
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    id
+}
+
+fn emails(page: &serde_json::Value) -> Vec<&str> {
+    page["subscribers"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|s| s["email"].as_str().unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn the_subscriber_api_requires_authentication() {
+    // Arrange
+    let app = spawn_app().await;
+    let id = insert_subscriber(&app, "ada@example.com", "Ada", "confirmed", Utc::now()).await;
+    let client = reqwest::Client::new();
+
+    for request in [
+        client.get(format!("{}/admin/subscribers", &app.address)),
+        client.get(format!("{}/admin/subscribers/{}", &app.address, id)),
+        client
+            .patch(format!("{}/admin/subscribers/{}", &app.address, id))
+            .json(&serde_json::json!({"name": "Anonymous"})),
+        client.delete(format!("{}/admin/subscribers/{}", &app.address, id)),
+    ] {
+        // Act
+        let response = request.send().await.expect("Failed to execute request.");
+
+        // Assert
+        assert_eq!(401, response.status().as_u16());
+    }
+}
+
+#[tokio::test]
+async fn subscribers_are_listed_most_recent_first_one_page_at_a_time() {
+    // Arrange
+    let app = spawn_app().await;
+    let now = Utc::now();
+    for i in 0..5 {
+        let email = format!("subscriber{}@example.com", i);
+        insert_subscriber(&app, &email, "Subscriber", "confirmed", now - Duration::days(i)).await;
+    }
+
+    // Act
+    let first_page: serde_json::Value = app.get_admin("/subscribers?limit=2").await.json().await.unwrap();
+    let cursor = first_page["next_cursor"].as_str().unwrap();
+    let second_page: serde_json::Value = app
+        .get_admin(&format!("/subscribers?limit=2&after={}", cursor))
+        .await
+        .json()
+        .await
+        .unwrap();
+    let cursor = second_page["next_cursor"].as_str().unwrap();
+    let last_page: serde_json::Value = app
+        .get_admin(&format!("/subscribers?limit=2&after={}", cursor))
+        .await
+        .json()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(emails(&first_page), ["subscriber0@example.com", "subscriber1@example.com"]);
+    assert_eq!(emails(&second_page), ["subscriber2@example.com", "subscriber3@example.com"]);
+    assert_eq!(emails(&last_page), ["subscriber4@example.com"]);
+    assert!(last_page["next_cursor"].is_null());
+}
+
+#[tokio::test]
+async fn paging_goes_on_when_the_last_subscriber_of_a_page_is_deleted() {
+    // Arrange
+    let app = spawn_app().await;
+    let now = Utc::now();
+    let mut ids = Vec::new();
+    for i in 0..3 {
+        let email = format!("subscriber{}@example.com", i);
+        ids.push(insert_subscriber(&app, &email, "Subscriber", "confirmed", now - Duration::days(i)).await);
+    }
+    let first_page: serde_json::Value = app.get_admin("/subscribers?limit=2").await.json().await.unwrap();
+    app.delete_subscriber(&ids[1].to_string()).await;
+
+    // Act
+    let cursor = first_page["next_cursor"].as_str().unwrap();
+    let second_page: serde_json::Value = app
+        .get_admin(&format!("/subscribers?limit=2&after={}", cursor))
+        .await
+        .json()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(emails(&second_page), ["subscriber2@example.com"]);
+}
+
+#[tokio::test]
+async fn subscribers_can_be_filtered_by_status_signup_date_and_search() {
+    // Arrange
+    let app = spawn_app().await;
+    let now = Utc::now();
+    insert_subscriber(&app, "ada@example.com", "Ada Lovelace", "confirmed", now - Duration::days(10)).await;
+    insert_subscriber(&app, "grace@example.com", "Grace Hopper", "confirmed", now - Duration::days(3)).await;
+    insert_subscriber(&app, "ursula@example.com", "Ursula Le Guin", "pending_confirmation", now - Duration::days(2))
+        .await;
+    insert_subscriber(&app, "100%_sure@example.com", "Percent", "confirmed", now - Duration::days(1)).await;
+    let since = (now - Duration::days(5)).to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
+
+    let test_cases = [
+        ("status=pending_confirmation".to_string(), vec!["ursula@example.com"]),
+        (
+            format!("subscribed_from={}", since),
+            vec!["100%_sure@example.com", "ursula@example.com", "grace@example.com"],
+        ),
+        (format!("subscribed_to={}", since), vec!["ada@example.com"]),
+        ("q=HOPPER".to_string(), vec!["grace@example.com"]),
+        ("q=EXAMPLE.COM&status=confirmed&limit=2".to_string(), vec!["100%_sure@example.com", "grace@example.com"]),
+        // Wildcards are searched for as written.
+        ("q=%25_".to_string(), vec!["100%_sure@example.com"]),
+    ];
+    for (query, expected) in test_cases {
+        // Act
+        let response = app.get_admin(&format!("/subscribers?{}", query)).await;
+
+        // Assert
+        assert_eq!(200, response.status().as_u16(), "Query: {}", query);
+        let page: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(emails(&page), expected, "Query: {}", query);
+    }
+}
+
+#[tokio::test]
+async fn an_invalid_cursor_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app.get_admin("/subscribers?after=not-a-cursor").await;
+
+    // Assert
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn a_subscriber_can_be_fetched_by_id() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let id = sqlx::query!("SELECT id FROM subscriptions").fetch_one(&app.db_pool).await.unwrap().id;
+
+    // Act
+    let response = app.get_admin(&format!("/subscribers/{}", id)).await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let subscriber: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(subscriber["id"], id.to_string());
+    assert_eq!(subscriber["email"], "ursula_le_guin@gmail.com");
+    assert_eq!(subscriber["status"], "confirmed");
+    assert!(subscriber["confirmed_at"].is_string());
+}
+
+#[tokio::test]
+async fn an_unknown_subscriber_is_not_found() {
+    // Arrange
+    let app = spawn_app().await;
+    let id = Uuid::new_v4();
+
+    // Act
+    let get = app.get_admin(&format!("/subscribers/{}", id)).await;
+    let patch = app
+        .patch_subscriber(&id.to_string(), &serde_json::json!({"name": "Nobody"}))
+        .await;
+    let delete = app.delete_subscriber(&id.to_string()).await;
+
+    // Assert
+    assert_eq!(404, get.status().as_u16());
+    assert_eq!(404, patch.status().as_u16());
+    assert_eq!(404, delete.status().as_u16());
+}
+
+#[tokio::test]
+async fn patching_a_subscriber_only_changes_the_given_fields() {
+    // Arrange
+    let app = spawn_app().await;
+    let id = insert_subscriber(&app, "ada@example.com", "Ada", "pending_confirmation", Utc::now()).await;
+
+    // Act
+    let response = app
+        .patch_subscriber(&id.to_string(), &serde_json::json!({"name": "Ada Lovelace", "status": "confirmed"}))
+        .await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let subscriber: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(subscriber["email"], "ada@example.com");
+    assert_eq!(subscriber["name"], "Ada Lovelace");
+    assert_eq!(subscriber["status"], "confirmed");
+    assert!(subscriber["confirmed_at"].is_string());
+}
+
+#[tokio::test]
+async fn patching_a_subscriber_with_invalid_fields_reports_every_field() {
+    // Arrange
+    let app = spawn_app().await;
+    let id = insert_subscriber(&app, "ada@example.com", "Ada", "confirmed", Utc::now()).await;
+
+    // Act
+    let response = app
+        .patch_subscriber(&id.to_string(), &serde_json::json!({"email": "not-an-email", "name": " "}))
+        .await;
+
+    // Assert
+    assert_eq!(400, response.status().as_u16());
+    let problem: serde_json::Value = response.json().await.unwrap();
+    let fields: Vec<_> = problem["errors"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|e| e["field"].as_str().unwrap())
+        .collect();
+    assert_eq!(fields, ["email", "name"]);
+    let saved = sqlx::query!("SELECT email, name FROM subscriptions").fetch_one(&app.db_pool).await.unwrap();
+    assert_eq!((saved.email.as_str(), saved.name.as_str()), ("ada@example.com", "Ada"));
+}
+
+#[tokio::test]
+async fn taking_the_email_of_another_subscriber_is_a_conflict() {
+    // Arrange
+    let app = spawn_app().await;
+    insert_subscriber(&app, "ada@example.com", "Ada", "confirmed", Utc::now()).await;
+    let id = insert_subscriber(&app, "grace@example.com", "Grace", "confirmed", Utc::now()).await;
+
+    // Act
+    let response = app
+        .patch_subscriber(&id.to_string(), &serde_json::json!({"email": "ada@example.com"}))
+        .await;
+
+    // Assert
+    assert_eq!(409, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn taking_the_email_of_another_subscriber_in_another_case_is_a_conflict() {
+    // Arrange
+    let app = spawn_app().await;
+    insert_subscriber(&app, "ada@example.com", "Ada", "confirmed", Utc::now()).await;
+    let id = insert_subscriber(&app, "grace@example.com", "Grace", "confirmed", Utc::now()).await;
+
+    // Act
+    let response = app
+        .patch_subscriber(&id.to_string(), &serde_json::json!({"email": "Ada@Example.com"}))
+        .await;
+
+    // Assert
+    assert_eq!(409, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn a_deleted_subscriber_is_gone_along_with_their_tokens() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let id = sqlx::query!("SELECT id FROM subscriptions").fetch_one(&app.db_pool).await.unwrap().id;
+
+    // Act
+    let response = app.delete_subscriber(&id.to_string()).await;
+
+    // Assert
+    assert_eq!(204, response.status().as_u16());
+    assert_eq!(404, app.get_admin(&format!("/subscribers/{}", id)).await.status().as_u16());
+    let tokens = sqlx::query!("SELECT COUNT(*) AS \"count!\" FROM subscription_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(tokens.count, 0);
+}
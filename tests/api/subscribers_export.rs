@@ -0,0 +1,106 @@
+use wiremock::matchers::path;
+use wiremock::{Mock, ResponseTemplate};
+use crate::helpers::{create_confirmed_subscriber, create_unconfirmed_subscriber, spawn_app};
+
+#[tokio::test]
+async fn exporting_requires_authentication() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::get(format!("{}/admin/subscribers/export?format=csv", &app.address))
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(401, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn an_unknown_format_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app.get_subscribers_export("format=xlsx").await;
+
+    // Assert
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn the_csv_export_holds_the_subscribers_with_the_requested_status() {
+    // Arrange
+    let app = spawn_app().await;
+    create_unconfirmed_subscriber(&app).await;
+    let csv = "email,name\nada@example.com,\"Lovelace, Ada\"\n";
+    app.post_subscribers_import("status=confirmed&consent_source=Conference", csv).await;
+
+    // Act
+    let response = app.get_subscribers_export("format=csv&status=confirmed").await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(response.headers()["Content-Type"], "text/csv; charset=utf-8");
+    assert_eq!(
+        response.headers()["Content-Disposition"],
+        "attachment; filename=\"subscribers.csv\""
+    );
+    let body = response.text().await.unwrap();
+    let lines: Vec<_> = body.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0], "id,email,name,status,subscribed_at,confirmed_at");
+    assert!(lines[1].contains(",ada@example.com,\"Lovelace, Ada\",confirmed,"));
+    assert!(!lines[1].ends_with(','), "The confirmation time is missing: {}", lines[1]);
+}
+
+#[tokio::test]
+async fn the_json_lines_export_records_when_subscribers_confirmed() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_subscribers_import("status=pending_confirmation", "email,name\nada@example.com,Ada\n")
+        .await;
+
+    // Act
+    let response = app.get_subscribers_export("format=jsonl").await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(response.headers()["Content-Type"], "application/x-ndjson");
+    let body = response.text().await.unwrap();
+    let records: Vec<serde_json::Value> = body.lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+    assert_eq!(records.len(), 2);
+    let confirmed = records.iter().find(|r| r["status"] == "confirmed").unwrap();
+    assert!(confirmed["confirmed_at"].is_string());
+    let pending = records.iter().find(|r| r["status"] == "pending_confirmation").unwrap();
+    assert!(pending["confirmed_at"].is_null());
+}
+
+#[tokio::test]
+async fn large_lists_are_exported_across_several_batches() {
+    // Arrange
+    let app = spawn_app().await;
+    let mut csv = String::from("email,name\n");
+    for i in 0..2345 {
+        csv.push_str(&format!("subscriber{}@example.com,Subscriber {}\n", i, i));
+    }
+    app.post_subscribers_import("status=confirmed&consent_source=Previous%20provider", &csv)
+        .await;
+
+    // Act
+    let response = app.get_subscribers_export("format=jsonl").await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let body = response.text().await.unwrap();
+    let emails: std::collections::HashSet<String> = body
+        .lines()
+        .map(|l| serde_json::from_str::<serde_json::Value>(l).unwrap()["email"].as_str().unwrap().to_owned())
+        .collect();
+    assert_eq!(emails.len(), 2345);
+}
@@ -0,0 +1,244 @@
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use crate::helpers::{create_confirmed_subscriber, spawn_app};
+
+#[tokio::test]
+async fn importing_requires_authentication() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!("{}/admin/subscribers/import?status=pending_confirmation", &app.address))
+        .body("email,name\nada@example.com,Ada\n")
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(401, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn confirmed_subscribers_are_imported_with_their_consent_source() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    let csv = "email,name\nada@example.com,Ada Lovelace\ngrace@example.com,Grace Hopper\n";
+
+    // Act
+    let response = app
+        .post_subscribers_import("status=confirmed&consent_source=Previous%20provider", csv)
+        .await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["imported"], 2);
+    let saved = sqlx::query!("SELECT email, status, consent_source FROM subscriptions ORDER BY email")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.len(), 2);
+    assert!(saved.iter().all(|s| s.status == "confirmed"));
+    assert!(saved.iter().all(|s| s.consent_source.as_deref() == Some("Previous provider")));
+}
+
+#[tokio::test]
+async fn addresses_that_differ_only_in_case_are_imported_once() {
+    // Arrange
+    let app = spawn_app().await;
+    let csv = "email,name\nada@example.com,Ada\nAda@EXAMPLE.com,Ada Lovelace\n";
+
+    // Act
+    let response = app
+        .post_subscribers_import("status=confirmed&consent_source=Previous%20provider", csv)
+        .await;
+
+    // Assert
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["imported"], 1);
+    assert_eq!(report["already_subscribed"], 1);
+    let saved = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.len(), 1);
+    assert_eq!(saved[0].email, "ada@example.com");
+}
+
+#[tokio::test]
+async fn importing_confirmed_subscribers_requires_a_consent_source() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_subscribers_import("status=confirmed", "email,name\nada@example.com,Ada\n")
+        .await;
+
+    // Assert
+    assert_eq!(400, response.status().as_u16());
+    let subscribers = sqlx::query!("SELECT COUNT(*) AS \"count!\" FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(subscribers.count, 0);
+}
+
+#[tokio::test]
+async fn pending_subscribers_are_sent_a_confirmation_email() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    let csv = "email,name\nada@example.com,Ada Lovelace\ngrace@example.com,Grace Hopper\n";
+
+    // Act
+    let response = app.post_subscribers_import("status=pending_confirmation", csv).await;
+    app.dispatch_all_confirmation_emails().await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["confirmation_emails_queued"], 2);
+    let tokens = sqlx::query!("SELECT COUNT(*) AS \"count!\" FROM subscription_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(tokens.count, 2);
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn a_confirmation_email_that_fails_is_left_queued_for_a_retry() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let csv = "email,name\nada@example.com,Ada Lovelace\n";
+    let response = app.post_subscribers_import("status=pending_confirmation", csv).await;
+    assert_eq!(200, response.status().as_u16());
+
+    // Act
+    app.dispatch_all_confirmation_emails().await;
+
+    // Assert
+    let queued = sqlx::query!("SELECT attempts, execute_after > now() AS \"later!\" FROM confirmation_email_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(queued.attempts, 1);
+    assert!(queued.later);
+}
+
+#[tokio::test]
+async fn invalid_rows_are_reported_and_the_others_imported() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let csv = "name,email\n\
+        Ada Lovelace,ada@example.com\n\
+        ,not-an-email\n\
+        Ursula,ursula_le_guin@gmail.com\n\
+        \"Grace Hopper\",grace@example.com\n";
+
+    // Act
+    let response = app
+        .post_subscribers_import("status=confirmed&consent_source=Conference", csv)
+        .await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["imported"], 2);
+    assert_eq!(report["already_subscribed"], 1);
+    assert_eq!(report["invalid"], 1);
+    assert_eq!(report["errors"][0]["line"], 3);
+    let codes: Vec<_> = report["errors"][0]["errors"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|e| e["code"].as_str().unwrap())
+        .collect();
+    assert_eq!(codes, ["invalid_email", "empty_name"]);
+}
+
+#[tokio::test]
+async fn an_oversized_row_is_reported_without_being_read_into_memory() {
+    // Arrange
+    let app = spawn_app().await;
+    let csv = format!(
+        "name,email\n\"{}\",huge@example.com\nAda Lovelace,ada@example.com\n",
+        "a".repeat(1024 * 1024)
+    );
+
+    // Act
+    let response = app
+        .post_subscribers_import("status=confirmed&consent_source=Conference", &csv)
+        .await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["imported"], 1);
+    assert_eq!(report["invalid"], 1);
+    assert_eq!(report["errors"][0]["line"], 2);
+    assert_eq!(report["errors"][0]["errors"][0]["code"], "too_long");
+}
+
+#[tokio::test]
+async fn large_files_are_imported_in_batches() {
+    // Arrange
+    let app = spawn_app().await;
+    let mut csv = String::from("email,name\n");
+    for i in 0..1234 {
+        csv.push_str(&format!("subscriber{}@example.com,Subscriber {}\n", i, i));
+    }
+
+    // Act
+    let response = app
+        .post_subscribers_import("status=confirmed&consent_source=Previous%20provider", &csv)
+        .await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["imported"], 1234);
+    let subscribers = sqlx::query!("SELECT COUNT(*) AS \"count!\" FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(subscribers.count, 1234);
+}
+
+#[tokio::test]
+async fn a_file_without_an_email_column_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_subscribers_import("status=pending_confirmation", "name\nAda\n")
+        .await;
+
+    // Assert
+    assert_eq!(400, response.status().as_u16());
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["detail"], "The file has no `email` column.");
+}
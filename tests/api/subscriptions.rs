@@ -1,7 +1,8 @@
 use wiremock::matchers::{method, path};
 use wiremock::{Mock, ResponseTemplate};
-use crate::helpers::spawn_app;
+use crate::helpers::{create_confirmed_subscriber, create_unconfirmed_subscriber, spawn_app};
 
+#[tokio::test]
 async fn subscribe_returns_a_200_for_valid_form_data() {
     // Arrange
     let app = spawn_app().await;
@@ -40,6 +41,99 @@ async fn subscribe_persists_the_new_subscriber() {
     assert_eq!(saved.status, "pending_confirmation");
 }
 
+#[tokio::test]
+async fn subscribe_keeps_the_address_as_typed_and_stores_it_normalized() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_subscriptions("name=le%20guin&email=%20Ursula_Le_Guin%40GMail.com%20".into())
+        .await;
+
+    // Assert
+    let saved = sqlx::query!("SELECT email, email_normalized FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!(saved.email, "Ursula_Le_Guin@GMail.com");
+    assert_eq!(saved.email_normalized, "ursula_le_guin@gmail.com");
+}
+
+#[tokio::test]
+async fn subscribing_again_in_another_case_does_not_create_a_second_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let first = app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    // Act
+    let second = app.post_subscriptions("name=le%20guin&email=Ursula_Le_Guin%40GMail.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(first.status().as_u16(), 200);
+    assert_eq!(second.status().as_u16(), 200);
+    let count = sqlx::query_scalar!(r#"SELECT count(*) AS "count!" FROM subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(count, 1);
+}
+
+#[tokio::test]
+async fn subscribing_again_before_confirming_sends_a_new_confirmation_email() {
+    // Arrange
+    let app = spawn_app().await;
+    create_unconfirmed_subscriber(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_subscriptions("name=le%20guin&email=Ursula_Le_Guin%40GMail.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let email_request = app.email_server.received_requests().await.unwrap().pop().unwrap();
+    let confirmation_links = app.get_confirmation_links(&email_request);
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn subscribing_again_once_confirmed_sends_no_email() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+}
+
 #[tokio::test]
 async fn subscribe_returns_a_400_when_data_is_missing() {
     let app = spawn_app().await;
@@ -103,4 +197,132 @@ async fn subscribe_sends_a_confirmation_email_with_a_link() {
     let confirmation_links =  app.get_confirmation_links(email_request);
     assert_eq!(confirmation_links.html, confirmation_links.plain_text);
 
-}
\ No newline at end of file
+}
+#[tokio::test]
+async fn subscribe_accepts_json_payloads() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_subscriptions_json(&serde_json::json!({
+            "name": "le guin",
+            "email": "ursula_le_guin@gmail.com",
+        }))
+        .await;
+
+    assert_eq!(200, response.status().as_u16());
+    let saved = sqlx::query!("SELECT email, name, status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!(saved.email, "ursula_le_guin@gmail.com");
+    assert_eq!(saved.name, "le guin");
+}
+
+#[tokio::test]
+async fn subscribe_returns_a_400_when_json_data_is_missing() {
+    let app = spawn_app().await;
+    let test_cases = vec![
+        (serde_json::json!({"name": "le guin"}), "missing the email"),
+        (serde_json::json!({"email": "ursula_le_guin@gmail.com"}), "missing the name"),
+        (serde_json::json!({}), "missing both name and email"),
+    ];
+
+    for (body, message) in test_cases {
+        let response = app.post_subscriptions_json(&body).await;
+        assert_eq!(400, response.status().as_u16(), "{}", message);
+    }
+}
+
+#[tokio::test]
+async fn subscribe_reports_the_invalid_field() {
+    let app = spawn_app().await;
+    let test_cases = vec![
+        ("name=&email=ursula_le_guin%40gmail.com", "name"),
+        ("name=Ursula&email=definitely-not-an-email", "email"),
+    ];
+
+    for (body, field) in test_cases {
+        let response = app.post_subscriptions(body.into()).await;
+        assert_eq!(400, response.status().as_u16());
+        let error: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(error["errors"][0]["field"], field);
+        assert!(error["errors"][0]["message"].is_string());
+    }
+
+    let response = app
+        .post_subscriptions_json(&serde_json::json!({"name": "Ursula", "email": "not-an-email"}))
+        .await;
+    assert_eq!(400, response.status().as_u16());
+    let error: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(error["errors"][0]["field"], "email");
+}
+
+#[tokio::test]
+async fn subscribe_reports_every_invalid_field_as_problem_details() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_subscriptions_json(&serde_json::json!({"name": "   ", "email": "not-an-email"}))
+        .await;
+
+    assert_eq!(400, response.status().as_u16());
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "application/problem+json"
+    );
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["status"], 400);
+    assert!(problem["title"].is_string());
+    assert!(problem["detail"].is_string());
+    let errors = problem["errors"].as_array().unwrap();
+    assert_eq!(errors.len(), 2);
+    assert_eq!(errors[0]["field"], "email");
+    assert_eq!(errors[0]["code"], "invalid_email");
+    assert_eq!(errors[1]["field"], "name");
+    assert_eq!(errors[1]["code"], "empty_name");
+}
+
+#[tokio::test]
+async fn subscribe_explains_why_a_name_is_invalid() {
+    let app = spawn_app().await;
+    let test_cases = vec![
+        ("Ursula <script>".to_string(), "forbidden_character"),
+        ("a".repeat(257), "too_long"),
+    ];
+
+    for (name, code) in test_cases {
+        let response = app
+            .post_subscriptions_json(&serde_json::json!({"name": name, "email": "ursula_le_guin@gmail.com"}))
+            .await;
+        assert_eq!(400, response.status().as_u16());
+        let problem: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(problem["errors"][0]["field"], "name");
+        assert_eq!(problem["errors"][0]["code"], code);
+    }
+}
+
+#[tokio::test]
+async fn subscribe_does_not_leak_the_cause_of_unexpected_errors() {
+    let app = spawn_app().await;
+    sqlx::query("ALTER TABLE subscription_tokens DROP COLUMN subscription_token CASCADE;")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    assert_eq!(500, response.status().as_u16());
+    assert_eq!(response.headers()["Content-Type"], "application/problem+json");
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["status"], 500);
+    assert!(problem["trace_id"].is_string());
+    assert!(!problem.to_string().contains("subscription_token"));
+}
@@ -0,0 +1,225 @@
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use crate::helpers::{create_confirmed_subscriber, create_draft, preview_draft, spawn_app, TestApp};
+
+const EMAIL: &str = "ursula_le_guin@gmail.com";
+
+async fn suppress(app: &TestApp, email: &str, reason: &str) -> reqwest::Response {
+    app.post_admin("/suppressions", &serde_json::json!({"email": email, "reason": reason}))
+        .await
+}
+
+async fn lift(app: &TestApp, email: &str, reason: &str) -> reqwest::Response {
+    app.post_admin("/suppressions/lift", &serde_json::json!({"email": email, "reason": reason}))
+        .await
+}
+
+#[tokio::test]
+async fn the_suppression_api_requires_authentication() {
+    // Arrange
+    let app = spawn_app().await;
+    let client = reqwest::Client::new();
+
+    for request in [
+        client.get(format!("{}/admin/suppressions", &app.address)),
+        client
+            .post(format!("{}/admin/suppressions", &app.address))
+            .json(&serde_json::json!({"email": EMAIL, "reason": "bounced"})),
+        client
+            .post(format!("{}/admin/suppressions/lift", &app.address))
+            .json(&serde_json::json!({"email": EMAIL, "reason": "Fixed"})),
+    ] {
+        // Act
+        let response = request.send().await.expect("Failed to execute request.");
+
+        // Assert
+        assert_eq!(401, response.status().as_u16());
+    }
+}
+
+#[tokio::test]
+async fn a_suppressed_address_cannot_subscribe_again() {
+    // Arrange
+    let app = spawn_app().await;
+    assert_eq!(201, suppress(&app, "Ursula_Le_Guin@gmail.com", "bounced").await.status().as_u16());
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into()).await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let subscribers: i64 = sqlx::query_scalar!("SELECT COUNT(*) AS \"count!\" FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(subscribers, 0);
+}
+
+#[tokio::test]
+async fn issues_are_not_delivered_to_subscribers_suppressed_after_publication() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let newsletter_issue_id = create_draft(&app).await;
+    preview_draft(&app, &newsletter_issue_id).await;
+    app.post_publish_newsletter(&newsletter_issue_id)
+        .await
+        .error_for_status()
+        .unwrap();
+    suppress(&app, EMAIL, "complained").await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let report: serde_json::Value = app
+        .get_admin(&format!("/newsletters/{}/report", newsletter_issue_id))
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(report["counts"]["suppressed"], 1);
+    assert_eq!(report["counts"]["queued"], 0);
+    assert_eq!(report["recipients"][0]["state"], "suppressed");
+}
+
+#[tokio::test]
+async fn previews_are_not_sent_to_suppressed_addresses() {
+    // Arrange
+    let app = spawn_app().await;
+    let newsletter_issue_id = create_draft(&app).await;
+    suppress(&app, "admin@example.com", "manual").await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_newsletter_preview(&newsletter_issue_id, "admin@example.com").await;
+
+    // Assert
+    assert_eq!(409, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn a_lifted_suppression_records_who_lifted_it_and_why() {
+    // Arrange
+    let app = spawn_app().await;
+    suppress(&app, EMAIL, "bounced").await;
+
+    // Act
+    let response = lift(&app, EMAIL, "The subscriber fixed their mailbox").await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let suppression: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(suppression["reason"], "bounced");
+    assert_eq!(suppression["lift_reason"], "The subscriber fixed their mailbox");
+    assert_eq!(suppression["lifted_by"], app.test_user.user_id.to_string());
+    assert!(suppression["lifted_at"].is_string());
+    let email_hash = suppression["email_hash"].as_str().unwrap();
+    let fetched: serde_json::Value = app
+        .get_admin(&format!("/suppressions/{}", email_hash))
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(fetched, suppression);
+}
+
+#[tokio::test]
+async fn a_lifted_address_can_subscribe_again() {
+    // Arrange
+    let app = spawn_app().await;
+    suppress(&app, EMAIL, "bounced").await;
+    lift(&app, EMAIL, "The subscriber fixed their mailbox").await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into()).await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn lifting_requires_a_reason_and_a_suppressed_address() {
+    // Arrange
+    let app = spawn_app().await;
+    suppress(&app, EMAIL, "bounced").await;
+
+    // Act
+    let without_reason = lift(&app, EMAIL, " ").await;
+    let not_suppressed = lift(&app, "ada@example.com", "Why not").await;
+
+    // Assert
+    assert_eq!(400, without_reason.status().as_u16());
+    assert_eq!(404, not_suppressed.status().as_u16());
+}
+
+#[tokio::test]
+async fn addresses_cannot_be_suppressed_as_erased_by_hand() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = suppress(&app, EMAIL, "erased").await;
+
+    // Assert
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn suppressions_are_listed_by_reason_one_page_at_a_time() {
+    // Arrange
+    let app = spawn_app().await;
+    for i in 0..3 {
+        suppress(&app, &format!("bounced{}@example.com", i), "bounced").await;
+    }
+    suppress(&app, "complained@example.com", "complained").await;
+    suppress(&app, "lifted@example.com", "bounced").await;
+    lift(&app, "lifted@example.com", "Fixed").await;
+
+    // Act
+    let first_page: serde_json::Value = app
+        .get_admin("/suppressions?reason=bounced&limit=2")
+        .await
+        .json()
+        .await
+        .unwrap();
+    let cursor = first_page["next_cursor"].as_str().unwrap();
+    let second_page: serde_json::Value = app
+        .get_admin(&format!("/suppressions?reason=bounced&limit=2&after={}", cursor))
+        .await
+        .json()
+        .await
+        .unwrap();
+    let with_lifted: serde_json::Value = app
+        .get_admin("/suppressions?reason=bounced&include_lifted=true")
+        .await
+        .json()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(first_page["suppressions"].as_array().unwrap().len(), 2);
+    assert_eq!(second_page["suppressions"].as_array().unwrap().len(), 1);
+    assert!(second_page["next_cursor"].is_null());
+    assert_eq!(with_lifted["suppressions"].as_array().unwrap().len(), 4);
+}
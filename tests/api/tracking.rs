@@ -0,0 +1,166 @@
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use crate::helpers::{create_confirmed_subscriber, create_draft_with_body, preview_draft, spawn_app, TestApp};
+
+/// Publish an issue linking to rust-lang.org and return the HTML body received by the subscriber.
+async fn deliver_issue_with_a_link(app: &TestApp) -> String {
+    let body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Read https://www.rust-lang.org",
+            "html": r#"<p>Read <a href="https://www.rust-lang.org/learn?a=1&amp;b=2">this</a></p>"#,
+        }
+    });
+    let newsletter_issue_id = create_draft_with_body(app, &body).await;
+    preview_draft(app, &newsletter_issue_id).await;
+    app.post_publish_newsletter(&newsletter_issue_id)
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = app.email_server.received_requests().await.unwrap().pop().unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    body["HtmlBody"].as_str().unwrap().to_owned()
+}
+
+/// Extract the first link pointing to the given tracking endpoint, targeting the test server.
+fn tracking_link(app: &TestApp, html: &str, prefix: &str) -> reqwest::Url {
+    let start = html.find(prefix).expect("No tracking link found.");
+    let end = html[start..].find('"').unwrap();
+    let mut link = reqwest::Url::parse(&html[start..start + end]).unwrap();
+    link.set_port(Some(app.port)).unwrap();
+    link
+}
+
+fn no_redirect_client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn clicks_are_recorded_and_redirected_to_the_original_link() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let html = deliver_issue_with_a_link(&app).await;
+    assert!(!html.contains(r#"href="https://www.rust-lang.org"#));
+
+    let link = tracking_link(&app, &html, "http://0.0.0.0/t/c/");
+    let response = no_redirect_client().get(link).send().await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 302);
+    assert_eq!(response.headers()["Location"], "https://www.rust-lang.org/learn?a=1&b=2");
+    let event = sqlx::query!("SELECT kind, url FROM newsletter_events")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(event.kind, "click");
+    assert_eq!(event.url.as_deref(), Some("https://www.rust-lang.org/learn?a=1&b=2"));
+}
+
+#[tokio::test]
+async fn opens_are_recorded_through_the_tracking_pixel() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let html = deliver_issue_with_a_link(&app).await;
+
+    let link = tracking_link(&app, &html, "http://0.0.0.0/t/o/");
+    let response = reqwest::get(link).await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.headers()["Content-Type"], "image/gif");
+    let event = sqlx::query!("SELECT kind FROM newsletter_events")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(event.kind, "open");
+}
+
+#[tokio::test]
+async fn forged_click_links_are_rejected() {
+    let app = spawn_app().await;
+
+    let response = no_redirect_client()
+        .get(format!("{}/t/c/eyJrIjoiYyJ9.deadbeef", app.address))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn open_tokens_cannot_be_used_as_click_links() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let html = deliver_issue_with_a_link(&app).await;
+    let open_link = tracking_link(&app, &html, "http://0.0.0.0/t/o/");
+    let token = open_link.path().trim_start_matches("/t/o/").trim_end_matches(".gif");
+
+    let response = no_redirect_client()
+        .get(format!("{}/t/c/{}", app.address, token))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn subscribers_who_opted_out_are_not_tracked() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let html = deliver_issue_with_a_link(&app).await;
+
+    let opt_out_link = tracking_link(&app, &html, "http://0.0.0.0/t/u/");
+    reqwest::Client::new()
+        .post(opt_out_link)
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Links in emails that were already delivered keep working, but nothing is recorded.
+    let link = tracking_link(&app, &html, "http://0.0.0.0/t/c/");
+    let response = no_redirect_client().get(link).send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 302);
+    let events = sqlx::query!("SELECT kind FROM newsletter_events")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(events.is_empty());
+
+    // New issues are sent without tracking.
+    let html = deliver_issue_with_a_link(&app).await;
+    assert!(html.contains(r#"href="https://www.rust-lang.org/learn?a=1&amp;b=2""#));
+    assert!(!html.contains("/t/o/"));
+}
+
+#[tokio::test]
+async fn following_the_opt_out_link_only_asks_for_confirmation() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let html = deliver_issue_with_a_link(&app).await;
+
+    let opt_out_link = tracking_link(&app, &html, "http://0.0.0.0/t/u/");
+    let response = reqwest::get(opt_out_link).await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 200);
+    let page = response.text().await.unwrap();
+    assert!(page.contains(r#"<form method="post">"#));
+    let subscriber = sqlx::query!("SELECT tracking_opt_out FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(!subscriber.tracking_opt_out);
+}